@@ -31,7 +31,9 @@ use mimalloc::MiMalloc;
 use tracing::info;
 
 use bichon::modules::{
-    common::signal::SignalManager, settings::dir::DataDirManager, users::manager::UserManager,
+    blob::manager::EmlBlobManager, common::signal::SignalManager,
+    indexer::manager::EnvelopeIndexManager, settings::dir::DataDirManager,
+    users::manager::UserManager,
 };
 
 #[global_allocator]
@@ -70,6 +72,8 @@ async fn initialize() -> BichonResult<()> {
     SignalManager::initialize().await?;
     DataDirManager::initialize().await?;
     UserManager::initialize().await?;
+    EmlBlobManager::initialize().await?;
+    EnvelopeIndexManager::initialize().await?;
     RustMailerTls::initialize().await?;
     EmailClientExecutors::initialize().await?;
     PeriodicTasks::start_background_tasks();
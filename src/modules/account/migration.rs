@@ -32,10 +32,14 @@ use crate::{
             since::{DateSince, RelativeDate},
             state::AccountRunningState,
         },
+        blob::manager::EML_BLOB_MANAGER,
         cache::imap::mailbox::MailBox,
         database::{list_all_impl, with_transaction},
+        envelope::{
+            fingerprint::MessageFingerprint, location::MessageLocation, thread::ThreadNode,
+        },
         error::BichonResult,
-        indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
         users::{role::DEFAULT_ACCOUNT_MANAGER_ROLE_ID, UserModel, DEFAULT_ADMIN_USER_ID},
     },
     utc_now,
@@ -45,6 +49,7 @@ use crate::id;
 use crate::modules::account::payload::AccountCreateRequest;
 use crate::modules::account::payload::AccountUpdateRequest;
 use crate::modules::account::payload::MinimalAccount;
+use crate::modules::cache::imap::idle::IDLE_WATCHERS;
 use crate::modules::cache::imap::task::SYNC_TASKS;
 use crate::modules::context::controller::SYNC_CONTROLLER;
 use crate::modules::context::executors::MAIL_CONTEXT;
@@ -59,7 +64,7 @@ use crate::modules::oauth2::token::OAuth2AccessToken;
 use crate::modules::rest::response::DataPage;
 use crate::raise_error;
 
-pub type AccountModel = AccountV3;
+pub type AccountModel = AccountV4;
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Enum)]
 pub enum AccountType {
@@ -159,6 +164,43 @@ impl AccountV3 {
     fn pk(&self) -> String {
         format!("{}_{}", self.created_at, self.id)
     }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+#[native_model(id = 4, version = 4, from = AccountV3)]
+#[native_db(primary_key(pk -> String))]
+pub struct AccountV4 {
+    #[secondary_key(unique)]
+    pub id: u64,
+    pub imap: Option<ImapConfig>,
+    pub enabled: bool,
+    #[oai(validator(custom = "crate::modules::common::validator::EmailValidator"))]
+    pub email: String,
+    pub name: Option<String>,
+    pub capabilities: Option<Vec<String>>,
+    pub date_since: Option<DateSince>,
+    pub date_before: Option<RelativeDate>,
+    pub folder_limit: Option<u32>,
+    pub sync_folders: Option<Vec<String>>,
+    pub account_type: AccountType,
+    pub sync_interval_min: Option<i64>,
+    pub sync_batch_size: Option<u32>,
+    pub known_folders: Option<BTreeSet<String>>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub created_by: u64, //user id
+    pub use_proxy: Option<u64>,
+    pub use_dangerous: bool,
+    pub pgp_key: Option<String>,
+    /// Folders watched with IMAP IDLE, new mail in them is archived as soon as the
+    /// server announces it instead of at the next sync interval.
+    pub idle_folders: Option<Vec<String>>,
+}
+
+impl AccountV4 {
+    fn pk(&self) -> String {
+        format!("{}_{}", self.created_at, self.id)
+    }
 
     pub fn new(user_id: u64, request: AccountCreateRequest) -> BichonResult<Self> {
         Ok(Self {
@@ -182,12 +224,13 @@ impl AccountV3 {
             created_by: user_id,
             sync_batch_size: request.sync_batch_size,
             date_before: request.date_before,
+            idle_folders: request.idle_folders,
         })
     }
 
     pub async fn check_account_exists(account_id: u64) -> BichonResult<AccountModel> {
         let account =
-            secondary_find_impl::<AccountModel>(DB_MANAGER.meta_db(), AccountV3Key::id, account_id)
+            secondary_find_impl::<AccountModel>(DB_MANAGER.meta_db(), AccountV4Key::id, account_id)
                 .await?
                 .ok_or_else(|| {
                     raise_error!(
@@ -210,7 +253,7 @@ impl AccountV3 {
     }
 
     pub async fn find(account_id: u64) -> BichonResult<Option<AccountModel>> {
-        secondary_find_impl::<AccountModel>(DB_MANAGER.meta_db(), AccountV3Key::id, account_id)
+        secondary_find_impl::<AccountModel>(DB_MANAGER.meta_db(), AccountV4Key::id, account_id)
             .await
     }
 
@@ -263,12 +306,17 @@ impl AccountV3 {
         if validate {
             request.validate_update_request(&account)?;
         }
+        let idle_folders_changed = request.idle_folders.is_some();
         update_impl(
             DB_MANAGER.meta_db(),
             move |_| Ok(account),
             move |current| Self::apply_update_fields(current, request),
         )
         .await?;
+        if idle_folders_changed {
+            // The pool is rebuilt on next use, sized around the new IDLE connections.
+            MAIL_CONTEXT.clean_account(account_id).await?;
+        }
 
         Ok(())
     }
@@ -288,7 +336,7 @@ impl AccountV3 {
 
     async fn delete_account(account_id: u64) -> BichonResult<()> {
         delete_impl(DB_MANAGER.meta_db(), move|rw|{
-            rw.get().secondary::<AccountModel>(AccountV3Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            rw.get().secondary::<AccountModel>(AccountV4Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
             .ok_or_else(||raise_error!(format!("The account entity with id={account_id} that you want to delete was not found."), ErrorCode::ResourceNotFound))
         }).await
     }
@@ -296,6 +344,7 @@ impl AccountV3 {
     async fn cleanup_account_resources_sequential(account: &AccountModel) -> BichonResult<()> {
         if matches!(account.account_type, AccountType::IMAP) {
             SYNC_TASKS.stop(account.id).await?;
+            IDLE_WATCHERS.stop(account.id).await?;
             AccountRunningState::delete(account.id).await?;
             MAIL_CONTEXT.clean_account(account.id).await?;
         }
@@ -305,9 +354,12 @@ impl AccountV3 {
         ENVELOPE_INDEX_MANAGER
             .delete_account_envelopes(account.id)
             .await?;
-        EML_INDEX_MANAGER
+        EML_BLOB_MANAGER
             .delete_account_envelopes(account.id)
             .await?;
+        ThreadNode::clean(account.id).await?;
+        MessageLocation::clean(account.id).await?;
+        MessageFingerprint::clean(account.id).await?;
         Self::delete_account(account.id).await?;
         info!("Sequential cleanup completed for account: {}", account.id);
         Ok(())
@@ -318,7 +370,7 @@ impl AccountV3 {
         sync_folders: Vec<String>,
     ) -> BichonResult<()> {
         update_impl(DB_MANAGER.meta_db(), move |rw| {
-            rw.get().secondary::<AccountModel>(AccountV3Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            rw.get().secondary::<AccountModel>(AccountV4Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
             .ok_or_else(|| raise_error!(format!("When trying to update account sync_folders, the corresponding record was not found. account_id={}", account_id), ErrorCode::ResourceNotFound))
         }, |current|{
             let mut updated = current.clone();
@@ -333,7 +385,7 @@ impl AccountV3 {
         known_folders: BTreeSet<String>,
     ) -> BichonResult<()> {
         update_impl(DB_MANAGER.meta_db(), move |rw| {
-            rw.get().secondary::<AccountModel>(AccountV3Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            rw.get().secondary::<AccountModel>(AccountV4Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
             .ok_or_else(|| raise_error!(format!("When trying to update account known_folders, the corresponding record was not found. account_id={}", account_id), ErrorCode::ResourceNotFound))
         }, |current|{
             let mut updated = current.clone();
@@ -348,7 +400,7 @@ impl AccountV3 {
         capabilities: Vec<String>,
     ) -> BichonResult<()> {
         update_impl(DB_MANAGER.meta_db(), move |rw| {
-            rw.get().secondary::<AccountModel>(AccountV3Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            rw.get().secondary::<AccountModel>(AccountV4Key::id, account_id).map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
             .ok_or_else(|| raise_error!(format!("When trying to update account capabilities, the corresponding record was not found. account_id={}", account_id), ErrorCode::ResourceNotFound))
         }, |current|{
             let mut updated = current.clone();
@@ -377,7 +429,7 @@ impl AccountV3 {
     }
 
     pub async fn count() -> BichonResult<usize> {
-        count_by_unique_secondary_key_impl::<AccountModel>(DB_MANAGER.meta_db(), AccountV3Key::id)
+        count_by_unique_secondary_key_impl::<AccountModel>(DB_MANAGER.meta_db(), AccountV4Key::id)
             .await
     }
 
@@ -453,6 +505,10 @@ impl AccountV3 {
                 new.sync_batch_size = Some(*sync_batch_size);
             }
 
+            if let Some(idle_folders) = request.idle_folders {
+                new.idle_folders = Some(idle_folders);
+            }
+
             if let Some(use_proxy) = request.use_proxy {
                 new.use_proxy = Some(use_proxy);
             }
@@ -577,3 +633,58 @@ impl From<AccountV2> for AccountV3 {
         }
     }
 }
+
+impl From<AccountV4> for AccountV3 {
+    fn from(value: AccountV4) -> Self {
+        Self {
+            id: value.id,
+            imap: value.imap,
+            enabled: value.enabled,
+            email: value.email,
+            name: value.name,
+            capabilities: value.capabilities,
+            date_since: value.date_since,
+            date_before: value.date_before,
+            folder_limit: value.folder_limit,
+            sync_folders: value.sync_folders,
+            account_type: value.account_type,
+            sync_interval_min: value.sync_interval_min,
+            sync_batch_size: value.sync_batch_size,
+            known_folders: value.known_folders,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            created_by: value.created_by,
+            use_proxy: value.use_proxy,
+            use_dangerous: value.use_dangerous,
+            pgp_key: value.pgp_key,
+        }
+    }
+}
+
+impl From<AccountV3> for AccountV4 {
+    fn from(value: AccountV3) -> Self {
+        Self {
+            id: value.id,
+            imap: value.imap,
+            enabled: value.enabled,
+            email: value.email,
+            name: value.name,
+            capabilities: value.capabilities,
+            date_since: value.date_since,
+            date_before: value.date_before,
+            folder_limit: value.folder_limit,
+            sync_folders: value.sync_folders,
+            account_type: value.account_type,
+            sync_interval_min: value.sync_interval_min,
+            sync_batch_size: value.sync_batch_size,
+            known_folders: value.known_folders,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            created_by: value.created_by,
+            use_proxy: value.use_proxy,
+            use_dangerous: value.use_dangerous,
+            pgp_key: value.pgp_key,
+            idle_folders: None,
+        }
+    }
+}
@@ -19,8 +19,10 @@
 use crate::modules::account::entity::ImapConfig;
 use crate::modules::account::migration::{AccountModel, AccountType};
 use crate::modules::account::since::{DateSince, RelativeDate};
+use crate::modules::cache::imap::idle::MAX_IDLE_FOLDERS;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::BichonResult;
+use crate::modules::imap::capabilities::has_capability;
 use crate::{raise_error, validate_email};
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
@@ -41,6 +43,8 @@ pub struct AccountCreateRequest {
     pub sync_interval_min: Option<i64>,
     #[oai(validator(minimum(value = "30"), maximum(value = "200")))]
     pub sync_batch_size: Option<u32>,
+    /// Folders watched with IMAP IDLE, at most 3.
+    pub idle_folders: Option<Vec<String>>,
     pub use_proxy: Option<u64>,
     pub use_dangerous: bool,
     pub pgp_key: Option<String>,
@@ -81,6 +85,9 @@ impl AccountCreateRequest {
                         ErrorCode::InvalidParameter
                     ));
                 }
+                if let Some(folders) = &self.idle_folders {
+                    validate_idle_folders(folders)?;
+                }
             }
             AccountType::NoSync => {}
         }
@@ -146,6 +153,12 @@ pub struct AccountUpdateRequest {
     pub sync_interval_min: Option<i64>,
     #[oai(validator(minimum(value = "30"), maximum(value = "200")))]
     pub sync_batch_size: Option<u32>,
+    /// Folders watched with IMAP IDLE, at most 3.
+    ///
+    /// New mail in these folders is archived as soon as the server announces it,
+    /// instead of at the next sync interval. Each folder keeps a connection open,
+    /// so the server must support IDLE. An empty list turns push sync off.
+    pub idle_folders: Option<Vec<String>>,
     /// Optional proxy ID for establishing the connection to external APIs (e.g., Gmail, Outlook).
     /// - If `None` or not provided, the client will connect directly to the API server.
     /// - If `Some(proxy_id)`, the client will use the pre-configured proxy with the given ID for API requests.
@@ -192,11 +205,34 @@ impl AccountUpdateRequest {
                 ));
                 }
             }
+            if let Some(folders) = self.idle_folders.as_ref() {
+                validate_idle_folders(folders)?;
+                let lacks_idle = account
+                    .capabilities
+                    .as_ref()
+                    .is_some_and(|_| !has_capability(account, "IDLE"));
+                if !folders.is_empty() && lacks_idle {
+                    return Err(raise_error!(
+                        "The IMAP server of this account does not support IDLE".into(),
+                        ErrorCode::Incompatible
+                    ));
+                }
+            }
         }
         Ok(())
     }
 }
 
+fn validate_idle_folders(folders: &[String]) -> BichonResult<()> {
+    if folders.len() > MAX_IDLE_FOLDERS {
+        return Err(raise_error!(
+            format!("At most {MAX_IDLE_FOLDERS} folders can be watched with IDLE"),
+            ErrorCode::InvalidParameter
+        ));
+    }
+    Ok(())
+}
+
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
 
 pub struct MinimalAccount {
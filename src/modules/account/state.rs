@@ -31,6 +31,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 const ERROR_COUNT_PER_ACCOUNT: usize = 30;
+const COLLISION_COUNT_PER_ACCOUNT: usize = 30;
+
+pub type AccountRunningState = AccountRunningStateV3;
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
 pub struct MailboxBatchProgress {
@@ -38,10 +41,26 @@ pub struct MailboxBatchProgress {
     pub current_batch: u32,
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 #[native_model(id = 2, version = 1)]
 #[native_db]
-pub struct AccountRunningState {
+pub struct AccountRunningStateV1 {
+    #[primary_key]
+    pub account_id: u64,
+    pub last_incremental_sync_start: i64,
+    pub last_incremental_sync_end: Option<i64>,
+    pub errors: Vec<AccountError>,
+    pub is_initial_sync_completed: bool,
+    pub progress: Option<BTreeMap<String, MailboxBatchProgress>>,
+    pub initial_sync_start_time: Option<i64>,
+    pub initial_sync_end_time: Option<i64>,
+    pub initial_sync_failed_time: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 2, version = 2, from = AccountRunningStateV1)]
+#[native_db]
+pub struct AccountRunningStateV2 {
     #[primary_key]
     pub account_id: u64,
     pub last_incremental_sync_start: i64,
@@ -52,6 +71,38 @@ pub struct AccountRunningState {
     pub initial_sync_start_time: Option<i64>,
     pub initial_sync_end_time: Option<i64>,
     pub initial_sync_failed_time: Option<i64>,
+    /// The most recent messages that were archived separately because another
+    /// message with different content had the same Message-ID.
+    pub message_id_collisions: Vec<MessageIdCollision>,
+    /// Number of such messages since the account was added.
+    pub message_id_collision_count: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+#[oai(rename = "AccountRunningState")]
+#[native_model(id = 2, version = 3, from = AccountRunningStateV2)]
+#[native_db]
+pub struct AccountRunningStateV3 {
+    #[primary_key]
+    pub account_id: u64,
+    pub last_incremental_sync_start: i64,
+    pub last_incremental_sync_end: Option<i64>,
+    pub errors: Vec<AccountError>,
+    pub is_initial_sync_completed: bool,
+    pub progress: Option<BTreeMap<String, MailboxBatchProgress>>,
+    pub initial_sync_start_time: Option<i64>,
+    pub initial_sync_end_time: Option<i64>,
+    pub initial_sync_failed_time: Option<i64>,
+    /// The most recent messages that were archived separately because another
+    /// message with different content had the same Message-ID.
+    pub message_id_collisions: Vec<MessageIdCollision>,
+    /// Number of such messages since the account was added.
+    pub message_id_collision_count: u64,
+    /// Number of archived messages found to be deleted on the server, by
+    /// mailbox name. The archived copies are kept.
+    pub vanished_counts: BTreeMap<String, u64>,
+    /// When the archive was last compared with the messages on the server.
+    pub last_reconciled_at: Option<i64>,
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
@@ -60,6 +111,91 @@ pub struct AccountError {
     pub at: i64,
 }
 
+/// A message whose Message-ID was already taken by a different message.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct MessageIdCollision {
+    pub message_id: String,
+    /// The id the message was archived under instead.
+    pub envelope_id: u64,
+    /// The id of the message that had the Message-ID first.
+    pub colliding_envelope_id: u64,
+    pub mailbox_id: u64,
+    pub uid: u32,
+    pub at: i64,
+}
+
+impl From<AccountRunningStateV1> for AccountRunningStateV2 {
+    fn from(value: AccountRunningStateV1) -> Self {
+        Self {
+            account_id: value.account_id,
+            last_incremental_sync_start: value.last_incremental_sync_start,
+            last_incremental_sync_end: value.last_incremental_sync_end,
+            errors: value.errors,
+            is_initial_sync_completed: value.is_initial_sync_completed,
+            progress: value.progress,
+            initial_sync_start_time: value.initial_sync_start_time,
+            initial_sync_end_time: value.initial_sync_end_time,
+            initial_sync_failed_time: value.initial_sync_failed_time,
+            message_id_collisions: Vec::new(),
+            message_id_collision_count: 0,
+        }
+    }
+}
+
+impl From<AccountRunningStateV2> for AccountRunningStateV1 {
+    fn from(value: AccountRunningStateV2) -> Self {
+        Self {
+            account_id: value.account_id,
+            last_incremental_sync_start: value.last_incremental_sync_start,
+            last_incremental_sync_end: value.last_incremental_sync_end,
+            errors: value.errors,
+            is_initial_sync_completed: value.is_initial_sync_completed,
+            progress: value.progress,
+            initial_sync_start_time: value.initial_sync_start_time,
+            initial_sync_end_time: value.initial_sync_end_time,
+            initial_sync_failed_time: value.initial_sync_failed_time,
+        }
+    }
+}
+
+impl From<AccountRunningStateV2> for AccountRunningStateV3 {
+    fn from(value: AccountRunningStateV2) -> Self {
+        Self {
+            account_id: value.account_id,
+            last_incremental_sync_start: value.last_incremental_sync_start,
+            last_incremental_sync_end: value.last_incremental_sync_end,
+            errors: value.errors,
+            is_initial_sync_completed: value.is_initial_sync_completed,
+            progress: value.progress,
+            initial_sync_start_time: value.initial_sync_start_time,
+            initial_sync_end_time: value.initial_sync_end_time,
+            initial_sync_failed_time: value.initial_sync_failed_time,
+            message_id_collisions: value.message_id_collisions,
+            message_id_collision_count: value.message_id_collision_count,
+            vanished_counts: BTreeMap::new(),
+            last_reconciled_at: None,
+        }
+    }
+}
+
+impl From<AccountRunningStateV3> for AccountRunningStateV2 {
+    fn from(value: AccountRunningStateV3) -> Self {
+        Self {
+            account_id: value.account_id,
+            last_incremental_sync_start: value.last_incremental_sync_start,
+            last_incremental_sync_end: value.last_incremental_sync_end,
+            errors: value.errors,
+            is_initial_sync_completed: value.is_initial_sync_completed,
+            progress: value.progress,
+            initial_sync_start_time: value.initial_sync_start_time,
+            initial_sync_end_time: value.initial_sync_end_time,
+            initial_sync_failed_time: value.initial_sync_failed_time,
+            message_id_collisions: value.message_id_collisions,
+            message_id_collision_count: value.message_id_collision_count,
+        }
+    }
+}
+
 impl AccountRunningState {
     pub async fn add(account_id: u64) -> BichonResult<()> {
         let info = AccountRunningState {
@@ -72,6 +208,10 @@ impl AccountRunningState {
             initial_sync_start_time: Some(utc_now!()),
             initial_sync_end_time: None,
             initial_sync_failed_time: None,
+            message_id_collisions: vec![],
+            message_id_collision_count: 0,
+            vanished_counts: BTreeMap::new(),
+            last_reconciled_at: None,
         };
         upsert_impl(DB_MANAGER.envelope_db(), info).await
     }
@@ -245,6 +385,58 @@ impl AccountRunningState {
         .await
     }
 
+    /// Records a Message-ID collision. Accounts without a running state, such
+    /// as those only used for imports, are skipped.
+    pub async fn record_collision(
+        account_id: u64,
+        collision: MessageIdCollision,
+    ) -> BichonResult<()> {
+        if Self::get(account_id).await?.is_none() {
+            return Ok(());
+        }
+        Self::update_account_running_state(account_id, move |current| {
+            let mut updated = current.clone();
+            updated.append_collision(collision);
+            Ok(updated)
+        })
+        .await
+    }
+
+    /// Adds archived messages found to be deleted on the server to the count of
+    /// their mailbox. Accounts without a running state are skipped.
+    pub async fn record_vanished(
+        account_id: u64,
+        mailbox_name: String,
+        count: u64,
+    ) -> BichonResult<()> {
+        if count == 0 || Self::get(account_id).await?.is_none() {
+            return Ok(());
+        }
+        Self::update_account_running_state(account_id, move |current| {
+            let mut updated = current.clone();
+            *updated.vanished_counts.entry(mailbox_name).or_default() += count;
+            Ok(updated)
+        })
+        .await
+    }
+
+    pub async fn set_reconciled(account_id: u64) -> BichonResult<()> {
+        Self::update_account_running_state(account_id, move |current| {
+            let mut updated = current.clone();
+            updated.last_reconciled_at = Some(utc_now!());
+            Ok(updated)
+        })
+        .await
+    }
+
+    pub fn append_collision(&mut self, collision: MessageIdCollision) {
+        self.message_id_collisions.push(collision);
+        self.message_id_collision_count += 1;
+        if self.message_id_collisions.len() > COLLISION_COUNT_PER_ACCOUNT {
+            self.message_id_collisions.remove(0);
+        }
+    }
+
     pub fn append_error_log(&mut self, error: String) {
         let new_error = AccountError {
             error,
@@ -337,4 +529,22 @@ mod tests {
         assert_eq!(account_state.errors[0].error, "Error 2"); // The first error is removed
         assert_eq!(account_state.errors[19].error, "Error 21"); // The last inserted error
     }
+
+    #[test]
+    fn test_collision_limit() {
+        let mut account_state = AccountRunningState::default();
+        for uid in 1..=40 {
+            account_state.append_collision(MessageIdCollision {
+                message_id: String::from("<ticket@x.com>"),
+                uid,
+                ..Default::default()
+            });
+        }
+        assert_eq!(account_state.message_id_collision_count, 40);
+        assert_eq!(
+            account_state.message_id_collisions.len(),
+            COLLISION_COUNT_PER_ACCOUNT
+        );
+        assert_eq!(account_state.message_id_collisions[0].uid, 11);
+    }
 }
@@ -0,0 +1,80 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::blob::mime::EmlPart;
+use native_db::*;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+/// A stored blob, keyed by the hex-encoded SHA-256 digest of its content.
+///
+/// `refcount` counts the records pointing at this blob; the file is removed
+/// once it drops to zero.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 3, version = 1)]
+#[native_db]
+pub struct BlobRecord {
+    #[primary_key]
+    pub hash: String,
+    pub size: u64,
+    pub refcount: u64,
+    pub created_at: i64,
+}
+
+/// Maps an archived email (envelope id) to the blobs holding its raw EML.
+///
+/// `hash` is the message skeleton, with the attachment bodies listed in
+/// `parts` cut out; see `split_attachments`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 4, version = 1)]
+#[native_db]
+pub struct EmlRecord {
+    /// The envelope id, see `Envelope::id`.
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    #[secondary_key]
+    pub mailbox_id: u64,
+    pub hash: String,
+    /// Size of the original message in bytes.
+    pub size: u64,
+    pub parts: Vec<EmlPart>,
+}
+
+impl EmlRecord {
+    /// All blobs referenced by this record, one entry per reference.
+    pub fn blob_hashes(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.hash.as_str()).chain(self.parts.iter().map(|p| p.hash.as_str()))
+    }
+}
+
+pub const BLOB_STATS_ID: u64 = 1;
+
+/// Running totals for the blob store, kept in a single row.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 5, version = 1)]
+#[native_db]
+pub struct BlobStats {
+    #[primary_key]
+    pub id: u64,
+    /// Total size of all archived messages as originally received.
+    pub logical_bytes: u64,
+    /// Total size of all blob files actually stored.
+    pub stored_bytes: u64,
+}
@@ -0,0 +1,139 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
+use crate::modules::error::{code::ErrorCode, BichonResult};
+use crate::modules::indexer::fields::{F_ACCOUNT_ID, F_EML, F_ID, F_MAILBOX_ID};
+use crate::modules::settings::dir::DATA_DIR_MANAGER;
+use crate::raise_error;
+use tantivy::schema::Value;
+use tantivy::{Index, TantivyDocument};
+use tracing::{info, warn};
+
+/// Where a pre-blob-store EML index is moved while it is being migrated.
+const LEGACY_EML_DIR: &str = "eml.legacy";
+const PROGRESS_LOG_INTERVAL: u64 = 10_000;
+
+/// Moves raw EML data out of the Tantivy index that older versions kept in
+/// `eml_dir` and into the blob store.
+///
+/// The legacy index is first renamed to `eml.legacy`, then every live document
+/// is copied into the blob store, and finally the legacy index is removed.
+/// Adding an EML is idempotent, so an interrupted migration simply starts over
+/// on the next launch.
+pub async fn migrate_legacy_eml_index() -> BichonResult<()> {
+    let eml_dir = &DATA_DIR_MANAGER.eml_dir;
+    let legacy_dir = DATA_DIR_MANAGER.root_dir.join(LEGACY_EML_DIR);
+
+    if eml_dir.join("meta.json").exists() {
+        if legacy_dir.exists() {
+            return Err(raise_error!(
+                format!(
+                    "Found a legacy EML index in both {:?} and {:?}; remove one of them and restart.",
+                    eml_dir, legacy_dir
+                ),
+                ErrorCode::InternalError
+            ));
+        }
+        info!(
+            "Found a legacy EML index in {:?}, moving it to {:?} for migration",
+            eml_dir, legacy_dir
+        );
+        std::fs::rename(eml_dir, &legacy_dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    }
+
+    std::fs::create_dir_all(eml_dir)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+    if !legacy_dir.exists() {
+        return Ok(());
+    }
+
+    let migrated = copy_legacy_index(&legacy_dir).await?;
+    info!(
+        "Migrated {} emails from the legacy EML index into the blob store",
+        migrated
+    );
+    if let Err(e) = std::fs::remove_dir_all(&legacy_dir) {
+        warn!(
+            "Failed to remove legacy EML index {:?}, please remove it manually: {:#?}",
+            legacy_dir, e
+        );
+    }
+    Ok(())
+}
+
+async fn copy_legacy_index(legacy_dir: &std::path::Path) -> BichonResult<u64> {
+    let index = Index::open_in_dir(legacy_dir)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let schema = index.schema();
+    let field = |name: &str| {
+        schema.get_field(name).map_err(|e| {
+            raise_error!(
+                format!("legacy EML index is missing field '{}': {:#?}", name, e),
+                ErrorCode::InternalError
+            )
+        })
+    };
+    let f_account_id = field(F_ACCOUNT_ID)?;
+    let f_mailbox_id = field(F_MAILBOX_ID)?;
+    let f_eml = field(F_EML)?;
+
+    let reader = index
+        .reader()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let searcher = reader.searcher();
+    let total = searcher.num_docs();
+    info!("Migrating {} emails from the legacy EML index", total);
+
+    let mut migrated = 0u64;
+    for segment_reader in searcher.segment_readers() {
+        // The legacy id field was indexed as a fast field only, it is not in the doc store.
+        let id_column = segment_reader
+            .fast_fields()
+            .u64(F_ID)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let store_reader = segment_reader
+            .get_store_reader(1)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        for doc_id in segment_reader.doc_ids_alive() {
+            let doc: TantivyDocument = store_reader
+                .get(doc_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let u64_value = |field| doc.get_first(field).and_then(|v| v.as_u64());
+            let (Some(eid), Some(account_id), Some(mailbox_id), Some(eml)) = (
+                id_column.first(doc_id),
+                u64_value(f_account_id),
+                u64_value(f_mailbox_id),
+                doc.get_first(f_eml).and_then(|v| v.as_bytes()),
+            ) else {
+                warn!("Skipping an incomplete document in the legacy EML index");
+                continue;
+            };
+            EML_BLOB_MANAGER
+                .add(account_id, mailbox_id, eid, eml)
+                .await?;
+            migrated += 1;
+            if migrated.is_multiple_of(PROGRESS_LOG_INTERVAL) {
+                info!("Legacy EML migration progress: {}/{}", migrated, total);
+            }
+        }
+    }
+    Ok(migrated)
+}
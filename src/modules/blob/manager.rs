@@ -0,0 +1,492 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::blob::entity::{BlobRecord, BlobStats, EmlRecord, EmlRecordKey, BLOB_STATS_ID};
+use crate::modules::blob::legacy::migrate_legacy_eml_index;
+use crate::modules::blob::mime::{join_parts, split_attachments, EmlPart};
+use crate::modules::blob::store::BlobStore;
+use crate::modules::context::Initialize;
+use crate::modules::database::manager::DB_MANAGER;
+use crate::modules::database::{async_find_impl, with_transaction_result};
+use crate::modules::envelope::location::find_locations;
+use crate::modules::error::{code::ErrorCode, BichonResult};
+use crate::modules::settings::dir::DATA_DIR_MANAGER;
+use crate::{raise_error, utc_now};
+use itertools::Itertools;
+use mail_parser::{MessageParser, MimeHeaders};
+use native_db::transaction::RwTransaction;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::ops::Bound;
+use std::sync::LazyLock;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+pub static EML_BLOB_MANAGER: LazyLock<EmlBlobManager> = LazyLock::new(EmlBlobManager::new);
+
+/// Stores raw EML content in a content-addressed `BlobStore` under
+/// `DataDirManager::eml_dir`.
+///
+/// Each archived email has an `EmlRecord` pointing at the blobs that hold its
+/// bytes, and each blob has a `BlobRecord` counting those pointers. Blob files
+/// are removed when their last reference goes away.
+///
+/// Attachment bodies are split out of the message at ingest and stored as
+/// separate blobs, so an attachment received many times is stored once. The
+/// original message is rebuilt byte-for-byte on read.
+pub struct EmlBlobManager {
+    store: BlobStore,
+    /// Serializes reference changes with the matching file writes and removals,
+    /// so a blob is never unlinked while a concurrent writer re-references it.
+    lock: Mutex<()>,
+}
+
+impl Initialize for EmlBlobManager {
+    async fn initialize() -> BichonResult<()> {
+        migrate_legacy_eml_index().await
+    }
+}
+
+impl EmlBlobManager {
+    fn new() -> Self {
+        Self {
+            store: BlobStore::new(DATA_DIR_MANAGER.eml_dir.clone()),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn add(
+        &self,
+        account_id: u64,
+        mailbox_id: u64,
+        eid: u64,
+        data: &[u8],
+    ) -> BichonResult<()> {
+        let split = split_attachments(data);
+        let record = EmlRecord {
+            id: eid,
+            account_id,
+            mailbox_id,
+            hash: BlobStore::digest(&split.skeleton),
+            size: data.len() as u64,
+            parts: split.parts.iter().map(|(part, _)| part.clone()).collect(),
+        };
+        let mut blob_sizes: HashMap<String, u64> = HashMap::new();
+        blob_sizes.insert(record.hash.clone(), split.skeleton.len() as u64);
+        for (part, content) in &split.parts {
+            blob_sizes.insert(part.hash.clone(), content.len() as u64);
+        }
+
+        let _guard = self.lock.lock().await;
+        self.store.write(&record.hash, &split.skeleton).await?;
+        for (part, content) in &split.parts {
+            self.store.write(&part.hash, content).await?;
+        }
+        let released = with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let previous: Option<EmlRecord> = rw
+                .get()
+                .primary(eid)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+            let mut delta = StatsDelta::default();
+            let mut released = Vec::new();
+            match previous {
+                Some(previous)
+                    if previous.hash == record.hash && previous.parts == record.parts => {}
+                previous => {
+                    for hash in record.blob_hashes() {
+                        if retain(rw, hash, blob_sizes[hash])? {
+                            delta.stored_bytes += blob_sizes[hash] as i64;
+                        }
+                    }
+                    delta.logical_bytes += record.size as i64;
+                    if let Some(previous) = previous {
+                        delta.logical_bytes -= previous.size as i64;
+                        for hash in previous.blob_hashes() {
+                            if let Some(removed) = release(rw, hash)? {
+                                delta.stored_bytes -= removed.size as i64;
+                                released.push(removed.hash);
+                            }
+                        }
+                    }
+                }
+            }
+
+            rw.upsert(record)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            apply_stats(rw, delta)?;
+            Ok(released)
+        })
+        .await?;
+
+        for hash in released {
+            self.store.remove(&hash).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn stats(&self) -> BichonResult<BlobStats> {
+        let stats: Option<BlobStats> =
+            async_find_impl(DB_MANAGER.envelope_db(), BLOB_STATS_ID).await?;
+        Ok(stats.unwrap_or_default())
+    }
+
+    pub async fn find(&self, account_id: u64, eid: u64) -> BichonResult<Option<EmlRecord>> {
+        let record: Option<EmlRecord> = async_find_impl(DB_MANAGER.envelope_db(), eid).await?;
+        Ok(record.filter(|r| r.account_id == account_id))
+    }
+
+    /// Number of stored emails.
+    pub async fn count(&self) -> BichonResult<u64> {
+        let db = DB_MANAGER.envelope_db().clone();
+        tokio::task::spawn_blocking(move || {
+            let r = db
+                .r_transaction()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            r.len()
+                .primary::<EmlRecord>()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+        })
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+    }
+
+    /// Lists up to `limit` records in id order, starting after `after`.
+    pub async fn list_after(
+        &self,
+        after: Option<u64>,
+        limit: usize,
+    ) -> BichonResult<Vec<EmlRecord>> {
+        let db = DB_MANAGER.envelope_db().clone();
+        tokio::task::spawn_blocking(move || {
+            let r = db
+                .r_transaction()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let scan = r
+                .scan()
+                .primary::<EmlRecord>()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let start = match after {
+                Some(id) => Bound::Excluded(id),
+                None => Bound::Unbounded,
+            };
+            scan.range((start, Bound::Unbounded))
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .take(limit)
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+        })
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+    }
+
+    /// Reads the full EML of `record`.
+    pub async fn load(&self, record: &EmlRecord) -> BichonResult<Vec<u8>> {
+        let skeleton = self.store.read(&record.hash).await?;
+        if record.parts.is_empty() {
+            return Ok(skeleton);
+        }
+        let mut contents = Vec::with_capacity(record.parts.len());
+        for part in &record.parts {
+            contents.push(self.store.read(&part.hash).await?);
+        }
+        let parts: Vec<(&EmlPart, &[u8])> = record
+            .parts
+            .iter()
+            .zip(contents.iter())
+            .map(|(part, content)| (part, content.as_slice()))
+            .collect();
+        Ok(join_parts(&skeleton, &parts))
+    }
+
+    pub async fn get(&self, account_id: u64, eid: u64) -> BichonResult<Option<Vec<u8>>> {
+        match self.find(account_id, eid).await? {
+            Some(record) => Ok(Some(self.load(&record).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Opens the stored EML for reading. Messages stored without split-out
+    /// attachments are streamed straight from the blob file.
+    pub async fn get_reader(
+        &self,
+        account_id: u64,
+        eid: u64,
+    ) -> BichonResult<Box<dyn AsyncRead + Send + Unpin>> {
+        let record = self.find(account_id, eid).await?.ok_or_else(|| {
+            raise_error!(
+                format!("Email not found: account_id={}, eid={}", account_id, eid),
+                ErrorCode::ResourceNotFound
+            )
+        })?;
+        if record.parts.is_empty() {
+            return Ok(Box::new(self.store.open(&record.hash).await?));
+        }
+        Ok(Box::new(Cursor::new(self.load(&record).await?)))
+    }
+
+    pub async fn get_attachment(
+        &self,
+        account_id: u64,
+        eid: u64,
+        file_name: &str,
+    ) -> BichonResult<File> {
+        let data = self.get(account_id, eid).await?.ok_or_else(|| {
+            raise_error!(
+                format!("Email not found: account_id={}, eid={}", account_id, eid),
+                ErrorCode::ResourceNotFound
+            )
+        })?;
+        let message = MessageParser::default().parse(&data).ok_or_else(|| {
+            raise_error!(
+                format!(
+                    "Failed to parse email: account_id={}, eid={}",
+                    account_id, eid
+                ),
+                ErrorCode::InternalError
+            )
+        })?;
+        let target_attachment = message
+            .attachments()
+            .find(|p| p.attachment_name().is_some_and(|name| name == file_name));
+        let content = match target_attachment {
+            Some(att) => att.contents(),
+            None => {
+                return Err(raise_error!(
+                    "Attachment not found".into(),
+                    ErrorCode::ResourceNotFound
+                ))
+            }
+        };
+        let mut path = DATA_DIR_MANAGER.temp_dir.clone();
+        path.push(format!("{eid}.{file_name}.eml"));
+        {
+            let mut file = File::create(&path)
+                .await
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            file.write_all(content)
+                .await
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+        let file = File::open(&path)
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(file)
+    }
+
+    pub async fn delete_account_envelopes(&self, account_id: u64) -> BichonResult<()> {
+        self.delete_records(move |rw| {
+            rw.scan()
+                .secondary::<EmlRecord>(EmlRecordKey::account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+        })
+        .await
+    }
+
+    pub async fn delete_mailbox_envelopes(
+        &self,
+        account_id: u64,
+        mailbox_ids: Vec<u64>,
+    ) -> BichonResult<()> {
+        if mailbox_ids.is_empty() {
+            tracing::warn!("delete_mailbox_envelopes: mailbox_ids is empty, nothing to delete");
+            return Ok(());
+        }
+        self.delete_records(move |rw| {
+            let mut records = Vec::new();
+            for mailbox_id in &mailbox_ids {
+                let found: Vec<EmlRecord> = rw
+                    .scan()
+                    .secondary::<EmlRecord>(EmlRecordKey::mailbox_id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .start_with(*mailbox_id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .try_collect()
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                for record in found.into_iter().filter(|r| r.account_id == account_id) {
+                    // Messages that are still filed in another mailbox are kept.
+                    let other = find_locations(rw, record.id)?
+                        .into_iter()
+                        .find(|l| !mailbox_ids.contains(&l.mailbox_id));
+                    match other {
+                        Some(location) => {
+                            rw.upsert(EmlRecord {
+                                mailbox_id: location.mailbox_id,
+                                ..record
+                            })
+                            .map_err(|e| {
+                                raise_error!(format!("{:#?}", e), ErrorCode::InternalError)
+                            })?;
+                        }
+                        None => records.push(record),
+                    }
+                }
+            }
+            Ok(records)
+        })
+        .await
+    }
+
+    pub async fn delete_email_multi_account(
+        &self,
+        deletes: &HashMap<u64, Vec<u64>>, // HashMap<account_id, envelope_ids>
+    ) -> BichonResult<()> {
+        if deletes.is_empty() {
+            tracing::warn!("delete_email_multi_account: deletes is empty, nothing to delete");
+            return Ok(());
+        }
+        let deletes = deletes.clone();
+        self.delete_records(move |rw| {
+            let mut records = Vec::new();
+            for (account_id, envelope_ids) in deletes {
+                let unique_ids: HashSet<u64> = envelope_ids.into_iter().collect();
+                for eid in unique_ids {
+                    let record: Option<EmlRecord> = rw
+                        .get()
+                        .primary(eid)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                    if let Some(record) = record.filter(|r| r.account_id == account_id) {
+                        records.push(record);
+                    }
+                }
+            }
+            Ok(records)
+        })
+        .await
+    }
+
+    /// Removes the selected records and drops their blob references, deleting
+    /// blob files that are no longer referenced.
+    async fn delete_records(
+        &self,
+        select: impl FnOnce(&RwTransaction) -> BichonResult<Vec<EmlRecord>> + Send + 'static,
+    ) -> BichonResult<()> {
+        let _guard = self.lock.lock().await;
+        let released = with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let records = select(rw)?;
+            let mut delta = StatsDelta::default();
+            let mut released = Vec::new();
+            for record in records {
+                delta.logical_bytes -= record.size as i64;
+                for hash in record.blob_hashes() {
+                    if let Some(removed) = release(rw, hash)? {
+                        delta.stored_bytes -= removed.size as i64;
+                        released.push(removed.hash);
+                    }
+                }
+                rw.remove(record)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+            apply_stats(rw, delta)?;
+            Ok(released)
+        })
+        .await?;
+
+        for hash in released {
+            self.store.remove(&hash).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct StatsDelta {
+    logical_bytes: i64,
+    stored_bytes: i64,
+}
+
+fn apply_stats(rw: &RwTransaction, delta: StatsDelta) -> BichonResult<()> {
+    if delta.logical_bytes == 0 && delta.stored_bytes == 0 {
+        return Ok(());
+    }
+    let current: Option<BlobStats> = rw
+        .get()
+        .primary(BLOB_STATS_ID)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let current = current.unwrap_or(BlobStats {
+        id: BLOB_STATS_ID,
+        ..Default::default()
+    });
+    let updated = BlobStats {
+        id: BLOB_STATS_ID,
+        logical_bytes: current
+            .logical_bytes
+            .saturating_add_signed(delta.logical_bytes),
+        stored_bytes: current
+            .stored_bytes
+            .saturating_add_signed(delta.stored_bytes),
+    };
+    rw.upsert(updated)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(())
+}
+
+/// Adds one reference to `hash`. Returns `true` when the blob was not
+/// referenced before.
+fn retain(rw: &RwTransaction, hash: &str, size: u64) -> BichonResult<bool> {
+    let current: Option<BlobRecord> = rw
+        .get()
+        .primary(hash.to_string())
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    match current {
+        Some(current) => {
+            let mut updated = current.clone();
+            updated.refcount += 1;
+            rw.update(current, updated)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(false)
+        }
+        None => {
+            rw.insert(BlobRecord {
+                hash: hash.to_string(),
+                size,
+                refcount: 1,
+                created_at: utc_now!(),
+            })
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(true)
+        }
+    }
+}
+
+/// Drops one reference to `hash`. Returns the blob record when the blob is no
+/// longer referenced and its file can be removed.
+fn release(rw: &RwTransaction, hash: &str) -> BichonResult<Option<BlobRecord>> {
+    let current: Option<BlobRecord> = rw
+        .get()
+        .primary(hash.to_string())
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let Some(current) = current else {
+        return Ok(None);
+    };
+    if current.refcount <= 1 {
+        rw.remove(current.clone())
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        return Ok(Some(current));
+    }
+    let mut updated = current.clone();
+    updated.refcount -= 1;
+    rw.update(current, updated)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(None)
+}
@@ -0,0 +1,209 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::blob::store::BlobStore;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mail_parser::{Encoding, MessageParser};
+use serde::{Deserialize, Serialize};
+
+/// Attachment bodies smaller than this stay inline in the message skeleton.
+const MIN_SPLIT_PART_SIZE: usize = 4096;
+
+/// How an attachment body blob maps back onto the bytes of the original MIME part.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub enum PartEncoding {
+    /// The blob holds the part body exactly as it appeared in the message.
+    Raw,
+    /// The blob holds the decoded content, which is re-encoded as base64 with
+    /// the original line layout when the message is rebuilt.
+    Base64 {
+        line_length: u32,
+        crlf: bool,
+        trailing_newline: bool,
+    },
+}
+
+/// An attachment body cut out of a message, to be spliced back at `offset`
+/// in the skeleton.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct EmlPart {
+    pub offset: u64,
+    pub hash: String,
+    pub encoding: PartEncoding,
+}
+
+/// The result of `split_attachments`: the message with attachment bodies
+/// removed, and the removed bodies in skeleton order.
+pub struct SplitEml {
+    pub skeleton: Vec<u8>,
+    pub parts: Vec<(EmlPart, Vec<u8>)>,
+}
+
+/// Cuts attachment bodies out of a raw message so identical attachments can
+/// be stored once.
+///
+/// Base64 bodies are stored decoded when re-encoding them reproduces the
+/// original bytes, so the same file sent by clients with different line
+/// wrapping still deduplicates. The split is verified by rebuilding the message
+/// and is abandoned if the result differs from `raw` in any byte.
+pub fn split_attachments(raw: &[u8]) -> SplitEml {
+    let unsplit = || SplitEml {
+        skeleton: raw.to_vec(),
+        parts: Vec::new(),
+    };
+    let Some(message) = MessageParser::default().parse(raw) else {
+        return unsplit();
+    };
+
+    let mut ranges: Vec<(usize, usize, Encoding)> = message
+        .attachments()
+        .map(|p| (p.offset_body as usize, p.offset_end as usize, p.encoding))
+        .filter(|(start, end, _)| start < end && *end <= raw.len())
+        .filter(|(start, end, _)| end - start >= MIN_SPLIT_PART_SIZE)
+        .collect();
+    ranges.sort_by_key(|(start, _, _)| *start);
+
+    let mut skeleton = Vec::with_capacity(raw.len());
+    let mut parts = Vec::with_capacity(ranges.len());
+    let mut cursor = 0;
+    for (start, end, encoding) in ranges {
+        if start < cursor {
+            // Overlapping ranges (nested parts); keep the outer one.
+            continue;
+        }
+        skeleton.extend_from_slice(&raw[cursor..start]);
+        let body = &raw[start..end];
+        let (encoding, content) = match encoding {
+            Encoding::Base64 => match decode_base64_body(body) {
+                Some((encoding, decoded)) => (encoding, decoded),
+                None => (PartEncoding::Raw, body.to_vec()),
+            },
+            _ => (PartEncoding::Raw, body.to_vec()),
+        };
+        parts.push((
+            EmlPart {
+                offset: skeleton.len() as u64,
+                hash: BlobStore::digest(&content),
+                encoding,
+            },
+            content,
+        ));
+        cursor = end;
+    }
+    skeleton.extend_from_slice(&raw[cursor..]);
+
+    let split = SplitEml { skeleton, parts };
+    if split.parts.is_empty() || split.join() != raw {
+        return unsplit();
+    }
+    split
+}
+
+impl SplitEml {
+    pub fn join(&self) -> Vec<u8> {
+        let parts: Vec<(&EmlPart, &[u8])> =
+            self.parts.iter().map(|(p, c)| (p, c.as_slice())).collect();
+        join_parts(&self.skeleton, &parts)
+    }
+}
+
+/// Rebuilds the original message from its skeleton and attachment bodies.
+pub fn join_parts(skeleton: &[u8], parts: &[(&EmlPart, &[u8])]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(
+        skeleton.len() + parts.iter().map(|(_, c)| c.len()).sum::<usize>() * 4 / 3,
+    );
+    let mut cursor = 0usize;
+    for (part, content) in parts {
+        let offset = (part.offset as usize).min(skeleton.len());
+        output.extend_from_slice(&skeleton[cursor..offset]);
+        match &part.encoding {
+            PartEncoding::Raw => output.extend_from_slice(content),
+            PartEncoding::Base64 {
+                line_length,
+                crlf,
+                trailing_newline,
+            } => encode_base64_body(
+                &mut output,
+                content,
+                *line_length as usize,
+                *crlf,
+                *trailing_newline,
+            ),
+        }
+        cursor = offset;
+    }
+    output.extend_from_slice(&skeleton[cursor..]);
+    output
+}
+
+fn decode_base64_body(body: &[u8]) -> Option<(PartEncoding, Vec<u8>)> {
+    let first_line_end = body.iter().position(|b| *b == b'\n');
+    let (line_length, crlf) = match first_line_end {
+        Some(pos) if pos > 0 && body[pos - 1] == b'\r' => (pos - 1, true),
+        Some(pos) => (pos, false),
+        None => (body.len(), false),
+    };
+    if line_length == 0 {
+        return None;
+    }
+    let trailing_newline = body.ends_with(b"\n");
+    let compact: Vec<u8> = body
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    let decoded = STANDARD.decode(compact).ok()?;
+
+    let mut reencoded = Vec::with_capacity(body.len());
+    encode_base64_body(
+        &mut reencoded,
+        &decoded,
+        line_length,
+        crlf,
+        trailing_newline,
+    );
+    if reencoded != body {
+        return None;
+    }
+    Some((
+        PartEncoding::Base64 {
+            line_length: line_length as u32,
+            crlf,
+            trailing_newline,
+        },
+        decoded,
+    ))
+}
+
+fn encode_base64_body(
+    output: &mut Vec<u8>,
+    content: &[u8],
+    line_length: usize,
+    crlf: bool,
+    trailing_newline: bool,
+) {
+    let encoded = STANDARD.encode(content);
+    let newline: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+    let mut lines = encoded.as_bytes().chunks(line_length.max(1)).peekable();
+    while let Some(line) = lines.next() {
+        output.extend_from_slice(line);
+        if lines.peek().is_some() || trailing_newline {
+            output.extend_from_slice(newline);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::id;
+use crate::modules::error::{code::ErrorCode, BichonResult};
+use crate::raise_error;
+use ring::digest::{digest, SHA256};
+use std::path::PathBuf;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+/// A content-addressed file store.
+///
+/// Every blob lives at `<root>/<h[0..2]>/<h[2..4]>/<h>`, where `h` is the
+/// hex-encoded SHA-256 digest of its content. Writing the same content twice
+/// is a no-op, which is what makes deduplication and idempotent re-ingest work.
+/// The store itself does not track references, see `EmlBlobManager`.
+#[derive(Debug)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    pub fn digest(data: &[u8]) -> String {
+        hex::encode(digest(&SHA256, data).as_ref())
+    }
+
+    pub fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash)
+    }
+
+    pub fn exists(&self, hash: &str) -> bool {
+        self.path(hash).exists()
+    }
+
+    /// Writes `data` under `hash` unless the blob already exists.
+    ///
+    /// The content is written to a temporary file in the target directory and
+    /// renamed into place, so readers never observe a partially written blob.
+    pub async fn write(&self, hash: &str, data: &[u8]) -> BichonResult<()> {
+        let path = self.path(hash);
+        if path.exists() {
+            return Ok(());
+        }
+        let dir = path.parent().ok_or_else(|| {
+            raise_error!(
+                format!("invalid blob path {:?}", path),
+                ErrorCode::InternalError
+            )
+        })?;
+        fs::create_dir_all(dir)
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+        let tmp_path = dir.join(format!("{hash}.{}.tmp", id!(64)));
+        let result = async {
+            let mut file = File::create(&tmp_path).await?;
+            file.write_all(data).await?;
+            file.sync_all().await?;
+            fs::rename(&tmp_path, &path).await
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError));
+        }
+        Ok(())
+    }
+
+    pub async fn open(&self, hash: &str) -> BichonResult<File> {
+        File::open(self.path(hash)).await.map_err(|e| {
+            raise_error!(
+                format!("Failed to open blob {}: {:#?}", hash, e),
+                ErrorCode::InternalError
+            )
+        })
+    }
+
+    pub async fn read(&self, hash: &str) -> BichonResult<Vec<u8>> {
+        fs::read(self.path(hash)).await.map_err(|e| {
+            raise_error!(
+                format!("Failed to read blob {}: {:#?}", hash, e),
+                ErrorCode::InternalError
+            )
+        })
+    }
+
+    pub async fn remove(&self, hash: &str) -> BichonResult<()> {
+        match fs::remove_file(self.path(hash)).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError)),
+        }
+    }
+}
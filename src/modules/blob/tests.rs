@@ -0,0 +1,95 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::blob::mime::{join_parts, split_attachments, PartEncoding};
+use crate::modules::blob::store::BlobStore;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+#[tokio::test]
+async fn blob_store_is_content_addressed() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::new(dir.path().to_path_buf());
+
+    let data = b"From: a@example.com\r\nSubject: hello\r\n\r\nbody\r\n";
+    let hash = BlobStore::digest(data);
+    assert_eq!(hash.len(), 64);
+    assert_eq!(
+        store.path(&hash),
+        dir.path().join(&hash[0..2]).join(&hash[2..4]).join(&hash)
+    );
+
+    store.write(&hash, data).await.unwrap();
+    // Writing the same content again is a no-op.
+    store.write(&hash, data).await.unwrap();
+    assert_eq!(store.read(&hash).await.unwrap(), data);
+
+    store.remove(&hash).await.unwrap();
+    assert!(!store.exists(&hash));
+    // Removing a missing blob is not an error.
+    store.remove(&hash).await.unwrap();
+}
+
+fn message_with_attachment(subject: &str, attachment: &[u8]) -> Vec<u8> {
+    let encoded = STANDARD.encode(attachment);
+    let mut body = String::new();
+    for line in encoded.as_bytes().chunks(76) {
+        body.push_str(std::str::from_utf8(line).unwrap());
+        body.push_str("\r\n");
+    }
+    format!(
+        "From: a@example.com\r\nSubject: {subject}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: multipart/mixed; boundary=\"b1\"\r\n\r\n\
+         --b1\r\nContent-Type: text/plain\r\n\r\nsee attached\r\n\
+         --b1\r\nContent-Type: application/octet-stream\r\n\
+         Content-Disposition: attachment; filename=\"data.bin\"\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n{body}--b1--\r\n"
+    )
+    .into_bytes()
+}
+
+#[test]
+fn attachments_are_split_and_rebuilt_exactly() {
+    let attachment: Vec<u8> = (0..20_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let first = message_with_attachment("first", &attachment);
+    let second = message_with_attachment("second", &attachment);
+
+    let split_first = split_attachments(&first);
+    let split_second = split_attachments(&second);
+    assert_eq!(split_first.parts.len(), 1);
+    assert_eq!(split_second.parts.len(), 1);
+
+    let (part, content) = &split_first.parts[0];
+    assert!(matches!(part.encoding, PartEncoding::Base64 { .. }));
+    assert_eq!(content, &attachment);
+    // The same attachment in a different message maps to the same blob.
+    assert_eq!(part.hash, split_second.parts[0].0.hash);
+    assert_eq!(split_first.join(), first);
+
+    let parts: Vec<_> = split_second
+        .parts
+        .iter()
+        .map(|(part, content)| (part, content.as_slice()))
+        .collect();
+    assert_eq!(join_parts(&split_second.skeleton, &parts), second);
+
+    // Small messages are stored as-is.
+    let small = message_with_attachment("small", b"tiny");
+    let split_small = split_attachments(&small);
+    assert!(split_small.parts.is_empty());
+    assert_eq!(split_small.skeleton, small);
+}
@@ -0,0 +1,318 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::account::migration::{AccountModel, AccountType};
+use crate::modules::account::state::AccountRunningState;
+use crate::modules::cache::imap::mailbox::MailBox;
+use crate::modules::common::periodic::{PeriodicTask, TaskHandle};
+use crate::modules::common::signal::SIGNAL_MANAGER;
+use crate::modules::context::executors::MAIL_CONTEXT;
+use crate::modules::envelope::location::MessageLocation;
+use crate::modules::error::code::ErrorCode;
+use crate::modules::error::BichonResult;
+use crate::modules::imap::capabilities::has_capability;
+use crate::modules::imap::manager::ImapConnectionManager;
+use crate::raise_error;
+use async_imap::extensions::idle::IdleResponse;
+use dashmap::DashMap;
+use imap_proto::{MailboxDatum, Response};
+use std::{sync::LazyLock, time::Duration};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Each watched folder keeps a connection of its own, taken out of the
+/// account's connection budget, see `build_imap_pool`.
+pub const MAX_IDLE_FOLDERS: usize = 3;
+/// RFC 2177 lets servers drop a client idling for more than 29 minutes, so
+/// IDLE is re-issued before that.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(25 * 60);
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+pub static IDLE_WATCHERS: LazyLock<IdleWatchers> = LazyLock::new(IdleWatchers::new);
+
+struct Watcher {
+    stop: watch::Sender<bool>,
+    join_handle: JoinHandle<()>,
+}
+
+/// Push sync: new mail in the `idle_folders` of an account is archived as soon
+/// as the server announces it, instead of at the next sync interval.
+pub struct IdleWatchers {
+    tasks: DashMap<u64, TaskHandle>,
+    watchers: DashMap<(u64, String), Watcher>,
+}
+
+impl IdleWatchers {
+    pub fn new() -> Self {
+        Self {
+            tasks: DashMap::new(),
+            watchers: DashMap::new(),
+        }
+    }
+
+    /// The folders of the account are re-read periodically, so changes to
+    /// `idle_folders` or to the account status apply without a restart.
+    pub async fn start(&self, account_id: u64, email: String) {
+        let task_name = format!("account-idle-task-{}-{}", account_id, &email);
+        let periodic_task = PeriodicTask::new(&task_name);
+        let task = move |param: Option<u64>| {
+            let account_id = param.unwrap();
+            Box::pin(async move { IDLE_WATCHERS.reconcile(account_id).await })
+        };
+        let handler = periodic_task.start(task, Some(account_id), RECONCILE_INTERVAL, true, true);
+        self.tasks.insert(account_id, handler);
+    }
+
+    pub async fn stop(&self, account_id: u64) -> BichonResult<()> {
+        if let Some((_, handler)) = self.tasks.remove(&account_id) {
+            handler.cancel().await;
+        }
+        self.stop_watchers(account_id, |_| true).await;
+        Ok(())
+    }
+
+    async fn reconcile(&self, account_id: u64) -> BichonResult<()> {
+        let account = AccountModel::get(account_id).await?;
+        let folders = watched_folders(&account);
+        self.stop_watchers(account_id, |folder| !folders.iter().any(|f| f == folder))
+            .await;
+        for folder in folders {
+            let key = (account_id, folder.clone());
+            if self.watchers.contains_key(&key) {
+                continue;
+            }
+            let (stop, stopped) = watch::channel(false);
+            let join_handle = tokio::spawn(watch_folder(account_id, folder, stopped));
+            self.watchers.insert(key, Watcher { stop, join_handle });
+        }
+        Ok(())
+    }
+
+    async fn stop_watchers(&self, account_id: u64, predicate: impl Fn(&str) -> bool) {
+        let keys: Vec<(u64, String)> = self
+            .watchers
+            .iter()
+            .filter(|entry| entry.key().0 == account_id && predicate(&entry.key().1))
+            .map(|entry| entry.key().clone())
+            .collect();
+        for key in keys {
+            if let Some((_, watcher)) = self.watchers.remove(&key) {
+                let _ = watcher.stop.send(true);
+                let _ = watcher.join_handle.await;
+            }
+        }
+    }
+}
+
+impl Default for IdleWatchers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn watched_folders(account: &AccountModel) -> Vec<String> {
+    if !account.enabled || !matches!(account.account_type, AccountType::IMAP) {
+        return Vec::new();
+    }
+    let folders = account.idle_folders.clone().unwrap_or_default();
+    if !folders.is_empty() && !has_capability(account, "IDLE") {
+        debug!(
+            "Account {}: server does not support IDLE, idle_folders are ignored.",
+            account.id
+        );
+        return Vec::new();
+    }
+    folders.into_iter().take(MAX_IDLE_FOLDERS).collect()
+}
+
+/// Resolves once the watcher is removed or the server shuts down.
+struct StopSignal {
+    stopped: watch::Receiver<bool>,
+    shutdown: broadcast::Receiver<()>,
+}
+
+impl StopSignal {
+    fn is_stopped(&self) -> bool {
+        *self.stopped.borrow()
+    }
+
+    async fn wait(&mut self) {
+        tokio::select! {
+            _ = self.stopped.wait_for(|stopped| *stopped) => {}
+            _ = self.shutdown.recv() => {}
+        }
+    }
+}
+
+async fn watch_folder(account_id: u64, folder: String, stopped: watch::Receiver<bool>) {
+    let mut stop = StopSignal {
+        stopped,
+        shutdown: SIGNAL_MANAGER.subscribe(),
+    };
+    let mut backoff = INITIAL_BACKOFF;
+    while !stop.is_stopped() {
+        match idle_session(account_id, &folder, &mut stop, &mut backoff).await {
+            Ok(()) => break,
+            Err(e) => {
+                warn!(
+                    "Account {}: IDLE on mailbox '{}' failed, reconnecting in {} seconds: {:?}",
+                    account_id,
+                    folder,
+                    backoff.as_secs(),
+                    e
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = stop.wait() => break,
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+    info!(
+        "Account {}: stopped watching mailbox '{}'.",
+        account_id, folder
+    );
+}
+
+/// Idles on a dedicated connection until stopped, fetching new mail whenever
+/// the server reports EXISTS.
+async fn idle_session(
+    account_id: u64,
+    folder: &str,
+    stop: &mut StopSignal,
+    backoff: &mut Duration,
+) -> BichonResult<()> {
+    let mailbox = MailBox::list_all(account_id)
+        .await?
+        .into_iter()
+        .find(|m| m.name == folder)
+        .ok_or_else(|| {
+            raise_error!(
+                format!("Mailbox '{}' has not been synced yet", folder),
+                ErrorCode::ResourceNotFound
+            )
+        })?;
+    let mut session = ImapConnectionManager::new(account_id).build().await?;
+    session
+        .examine(mailbox.encoded_name())
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+    *backoff = INITIAL_BACKOFF;
+    info!(
+        "Account {}: watching mailbox '{}' with IDLE.",
+        account_id, folder
+    );
+
+    // Catches up on mail received while the folder was not watched.
+    let mut has_new_mail = true;
+    loop {
+        if has_new_mail {
+            fetch_new_mail(account_id, &mailbox).await?;
+        }
+        let mut idle = session.idle();
+        idle.init()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+        let (wait, _interrupt) = idle.wait_with_timeout(IDLE_TIMEOUT);
+        let response = tokio::select! {
+            response = wait => Some(response),
+            _ = stop.wait() => None,
+        };
+        session = idle
+            .done()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+
+        let Some(response) = response else {
+            let _ = session.logout().await;
+            return Ok(());
+        };
+        has_new_mail = match response
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
+        {
+            IdleResponse::NewData(data) => matches!(
+                data.parsed(),
+                Response::MailboxData(MailboxDatum::Exists(_))
+            ),
+            IdleResponse::Timeout | IdleResponse::ManualInterrupt => false,
+        };
+    }
+}
+
+async fn fetch_new_mail(account_id: u64, mailbox: &MailBox) -> BichonResult<()> {
+    // Until the initial sync completes, new mail is left to the sync task.
+    let initial_sync_completed = AccountRunningState::get(account_id)
+        .await?
+        .is_some_and(|state| state.is_initial_sync_completed);
+    if !initial_sync_completed {
+        return Ok(());
+    }
+    let Some(max_uid) = MessageLocation::max_uid(account_id, mailbox.id).await? else {
+        return Ok(());
+    };
+    let account = AccountModel::get(account_id).await?;
+    let before_date = account
+        .date_before
+        .as_ref()
+        .map(|r| r.calculate_date())
+        .transpose()?;
+    let executor = MAIL_CONTEXT.imap(account_id).await?;
+    executor
+        .fetch_new_mail(&account, mailbox, max_uid + 1, before_date.as_deref())
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{watched_folders, MAX_IDLE_FOLDERS};
+    use crate::modules::account::migration::{AccountModel, AccountType};
+
+    fn account(capabilities: &[&str], folders: &[&str]) -> AccountModel {
+        AccountModel {
+            id: 1,
+            enabled: true,
+            account_type: AccountType::IMAP,
+            capabilities: Some(capabilities.iter().map(|c| c.to_string()).collect()),
+            idle_folders: Some(folders.iter().map(|f| f.to_string()).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_watched_folders() {
+        assert_eq!(
+            watched_folders(&account(&["IMAP4rev1", "IDLE"], &["INBOX", "Sent"])),
+            vec!["INBOX", "Sent"]
+        );
+        // Not advertised by the server.
+        assert!(watched_folders(&account(&["IMAP4rev1"], &["INBOX"])).is_empty());
+        let disabled = AccountModel {
+            enabled: false,
+            ..account(&["IDLE"], &["INBOX"])
+        };
+        assert!(watched_folders(&disabled).is_empty());
+
+        let folders = ["A", "B", "C", "D", "E"];
+        let watched = watched_folders(&account(&["idle"], &folders));
+        assert_eq!(watched, folders[..MAX_IDLE_FOLDERS]);
+    }
+}
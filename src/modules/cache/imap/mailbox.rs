@@ -35,10 +35,30 @@ use native_model::{native_model, Model};
 use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub type MailBox = MailBoxV2;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
 #[native_model(id = 1, version = 1)]
 #[native_db]
-pub struct MailBox {
+pub struct MailBoxV1 {
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    pub name: String,
+    pub delimiter: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub exists: u32,
+    pub unseen: Option<u32>,
+    pub uid_next: Option<u32>,
+    pub uid_validity: Option<u32>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+#[oai(rename = "MailBox")]
+#[native_model(id = 1, version = 2, from = MailBoxV1)]
+#[native_db]
+pub struct MailBoxV2 {
     /// The unique identifier for the mailbox
     #[primary_key]
     pub id: u64,
@@ -65,6 +85,42 @@ pub struct MailBox {
     /// The validity identifier for UIDs in this mailbox, used to ensure UID consistency across sessions.
     /// If `None`, the IMAP server has not provided this information.
     pub uid_validity: Option<u32>,
+    /// The HIGHESTMODSEQ of the mailbox when it was last synced, on servers with
+    /// CONDSTORE. `None` if the server does not keep mod-sequences for it.
+    pub highest_modseq: Option<u64>,
+}
+
+impl From<MailBoxV1> for MailBoxV2 {
+    fn from(value: MailBoxV1) -> Self {
+        Self {
+            id: value.id,
+            account_id: value.account_id,
+            name: value.name,
+            delimiter: value.delimiter,
+            attributes: value.attributes,
+            exists: value.exists,
+            unseen: value.unseen,
+            uid_next: value.uid_next,
+            uid_validity: value.uid_validity,
+            highest_modseq: None,
+        }
+    }
+}
+
+impl From<MailBoxV2> for MailBoxV1 {
+    fn from(value: MailBoxV2) -> Self {
+        Self {
+            id: value.id,
+            account_id: value.account_id,
+            name: value.name,
+            delimiter: value.delimiter,
+            attributes: value.attributes,
+            exists: value.exists,
+            unseen: value.unseen,
+            uid_next: value.uid_next,
+            uid_validity: value.uid_validity,
+        }
+    }
 }
 
 impl MailBox {
@@ -111,8 +167,12 @@ impl MailBox {
     // }
 
     pub async fn list_all(account_id: u64) -> BichonResult<Vec<MailBox>> {
-        filter_by_secondary_key_impl(DB_MANAGER.envelope_db(), MailBoxKey::account_id, account_id)
-            .await
+        filter_by_secondary_key_impl(
+            DB_MANAGER.envelope_db(),
+            MailBoxV2Key::account_id,
+            account_id,
+        )
+        .await
     }
 
     pub async fn batch_insert(mailboxes: &[MailBox]) -> BichonResult<()> {
@@ -127,7 +187,7 @@ impl MailBox {
         batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
             let mailboxes: Vec<MailBox> = rw
                 .scan()
-                .secondary::<MailBox>(MailBoxKey::account_id)
+                .secondary::<MailBox>(MailBoxV2Key::account_id)
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
                 .start_with(account_id)
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
@@ -19,19 +19,42 @@
 
 use std::sync::LazyLock;
 
-use crate::modules::{account::state::AccountRunningState, database::ModelsAdapter};
+use crate::modules::{
+    account::state::{AccountRunningStateV1, AccountRunningStateV2, AccountRunningStateV3},
+    blob::entity::{BlobRecord, BlobStats, EmlRecord},
+    database::ModelsAdapter,
+    envelope::{
+        fingerprint::MessageFingerprint,
+        location::{MailboxMaxUid, MessageLocationV1, MessageLocationV2, MessageLocationV3},
+        thread::{ThreadNodeV1, ThreadNodeV2},
+    },
+};
 use ahash::{AHashMap, AHashSet};
-use mailbox::MailBox;
+use mailbox::{MailBox, MailBoxV1, MailBoxV2};
 use native_db::Models;
 
+pub mod idle;
 pub mod mailbox;
 pub mod sync;
 pub mod task;
 
 pub static MAILBOX_MODELS: LazyLock<Models> = LazyLock::new(|| {
     let mut adapter = ModelsAdapter::new();
-    adapter.register_model::<MailBox>();
-    adapter.register_model::<AccountRunningState>();
+    adapter.register_model::<MailBoxV1>();
+    adapter.register_model::<MailBoxV2>();
+    adapter.register_model::<AccountRunningStateV1>();
+    adapter.register_model::<AccountRunningStateV2>();
+    adapter.register_model::<AccountRunningStateV3>();
+    adapter.register_model::<BlobRecord>();
+    adapter.register_model::<EmlRecord>();
+    adapter.register_model::<BlobStats>();
+    adapter.register_model::<ThreadNodeV1>();
+    adapter.register_model::<ThreadNodeV2>();
+    adapter.register_model::<MessageLocationV1>();
+    adapter.register_model::<MessageLocationV2>();
+    adapter.register_model::<MessageLocationV3>();
+    adapter.register_model::<MailboxMaxUid>();
+    adapter.register_model::<MessageFingerprint>();
     adapter.models
 });
 
@@ -23,12 +23,17 @@ use crate::{
             imap::{
                 find_intersecting_mailboxes, find_missing_mailboxes,
                 mailbox::MailBox,
-                sync::rebuild::{rebuild_mailbox_cache, rebuild_mailbox_cache_by_date},
+                sync::{
+                    rebuild::{rebuild_mailbox_cache, rebuild_mailbox_cache_by_date},
+                    reconcile::record_vanished,
+                },
             },
             SEMAPHORE,
         },
         context::executors::MAIL_CONTEXT,
+        envelope::location::MessageLocation,
         error::{code::ErrorCode, BichonError, BichonResult},
+        imap::{capabilities::has_capability, executor::ImapExecutor, gmail::is_gmail},
         indexer::manager::ENVELOPE_INDEX_MANAGER,
     },
     raise_error,
@@ -37,6 +42,10 @@ use std::time::Instant;
 use tracing::{debug, error, info, warn};
 
 pub const DEFAULT_BATCH_SIZE: u32 = 50;
+/// Messages whose Gmail labels are fetched at once.
+const LABEL_BATCH_SIZE: usize = 1000;
+/// Messages whose flags are fetched at once.
+const FLAG_BATCH_SIZE: usize = 1000;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum FetchDirection {
@@ -395,10 +404,13 @@ async fn perform_incremental_sync(
     local_mailbox: &MailBox,
     remote_mailbox: &MailBox,
 ) -> BichonResult<()> {
+    if let Some(modseq) = changes_since(account, local_mailbox, remote_mailbox) {
+        if let Some(max_uid) = MessageLocation::max_uid(account.id, local_mailbox.id).await? {
+            return sync_changes(account, local_mailbox, remote_mailbox, modseq, max_uid).await;
+        }
+    }
     if remote_mailbox.exists > 0 {
-        let local_max_uid = ENVELOPE_INDEX_MANAGER
-            .get_max_uid(account.id, local_mailbox.id)
-            .await?;
+        let local_max_uid = MessageLocation::max_uid(account.id, local_mailbox.id).await?;
         match local_max_uid {
             Some(max_uid) => {
                 let executor = MAIL_CONTEXT.imap(account.id).await?;
@@ -408,6 +420,7 @@ async fn perform_incremental_sync(
                     .map(|r| r.calculate_date())
                     .transpose()?;
 
+                // Without CONDSTORE, flag changes are picked up by `reconcile_if_due`.
                 executor
                     .fetch_new_mail(account, local_mailbox, max_uid + 1, before_date.as_deref())
                     .await?;
@@ -438,3 +451,136 @@ async fn perform_incremental_sync(
 
     Ok(())
 }
+
+/// The mod-sequence to ask the server for changes from, when both the server and
+/// the last sync of the mailbox support CONDSTORE. A `date_before` window keeps the
+/// UID SEARCH path, since it filters new messages by date.
+pub fn changes_since(
+    account: &AccountModel,
+    local_mailbox: &MailBox,
+    remote_mailbox: &MailBox,
+) -> Option<u64> {
+    if account.date_before.is_some() || remote_mailbox.highest_modseq.is_none() {
+        return None;
+    }
+    if !has_capability(account, "CONDSTORE") && !has_capability(account, "QRESYNC") {
+        return None;
+    }
+    local_mailbox.highest_modseq
+}
+
+//only fetch the messages changed since the last sync, as reported by the server
+async fn sync_changes(
+    account: &AccountModel,
+    local_mailbox: &MailBox,
+    remote_mailbox: &MailBox,
+    modseq: u64,
+    max_uid: u64,
+) -> BichonResult<()> {
+    if remote_mailbox.highest_modseq == Some(modseq) {
+        debug!(
+            "Account {}: Mailbox '{}' unchanged since modseq {}, skipping.",
+            account.id, local_mailbox.name, modseq
+        );
+        return Ok(());
+    }
+    let executor = MAIL_CONTEXT.imap(account.id).await?;
+    let changes = executor
+        .fetch_changes(local_mailbox, modseq, has_capability(account, "QRESYNC"))
+        .await?;
+    record_vanished(account.id, local_mailbox, changes.vanished).await?;
+    let (new, changed): (Vec<_>, Vec<_>) = changes
+        .changed
+        .into_iter()
+        .partition(|(uid, _)| *uid as u64 > max_uid);
+    if is_gmail(account) {
+        let uids = changed.iter().map(|(uid, _)| *uid).collect();
+        update_labels(&executor, account.id, local_mailbox, uids).await?;
+    }
+    update_flags(account.id, local_mailbox.id, changed).await?;
+    let new_uids: Vec<u32> = new.into_iter().map(|(uid, _)| uid).collect();
+    if !new_uids.is_empty() {
+        info!(
+            "[account {}][mailbox {}] {} envelopes need to be fetched",
+            account.id,
+            local_mailbox.name,
+            new_uids.len()
+        );
+    }
+    executor
+        .retrieve_uids(account, local_mailbox, new_uids)
+        .await
+}
+
+/// Brings the flags of archived messages up to date with the server.
+async fn update_flags(
+    account_id: u64,
+    mailbox_id: u64,
+    flags: Vec<(u32, Vec<String>)>,
+) -> BichonResult<()> {
+    if flags.is_empty() {
+        return Ok(());
+    }
+    let changed = MessageLocation::update_flags(account_id, mailbox_id, flags).await?;
+    if !changed.is_empty() {
+        debug!(
+            "Account {}: flags of {} messages changed in mailbox {}.",
+            account_id,
+            changed.len(),
+            mailbox_id
+        );
+        ENVELOPE_INDEX_MANAGER.update_flags(changed).await;
+    }
+    Ok(())
+}
+
+/// Compares the flags of the archived messages with `uids` with those on the
+/// server, for servers that do not report which messages changed.
+pub async fn refresh_flags(
+    executor: &ImapExecutor,
+    account_id: u64,
+    mailbox: &MailBox,
+    mut uids: Vec<u32>,
+) -> BichonResult<()> {
+    if uids.is_empty() {
+        return Ok(());
+    }
+    uids.sort();
+    let mut flags = Vec::with_capacity(uids.len());
+    for uid_set in generate_uid_sequence_hashset(uids, FLAG_BATCH_SIZE, false) {
+        flags.extend(executor.fetch_flags(mailbox, &uid_set).await?);
+    }
+    // One update for all batches, each update reads all locations of the mailbox.
+    update_flags(account_id, mailbox.id, flags).await
+}
+
+/// Gmail reports a message as changed when its labels change, so the changed
+/// messages get the tags of their labels refreshed.
+async fn update_labels(
+    executor: &ImapExecutor,
+    account_id: u64,
+    mailbox: &MailBox,
+    mut uids: Vec<u32>,
+) -> BichonResult<()> {
+    if uids.is_empty() {
+        return Ok(());
+    }
+    uids.sort();
+    let envelope_ids = MessageLocation::envelope_ids(account_id, mailbox.id, uids.clone()).await?;
+    for uid_set in generate_uid_sequence_hashset(uids, LABEL_BATCH_SIZE, false) {
+        let updates: Vec<(u64, Vec<String>)> = executor
+            .fetch_gmail_attributes(mailbox, &uid_set)
+            .await?
+            .into_iter()
+            .filter_map(|(uid, attributes)| {
+                envelope_ids
+                    .get(&uid)
+                    .map(|envelope_id| (*envelope_id, attributes.tags()))
+            })
+            .collect();
+        if !updates.is_empty() {
+            ENVELOPE_INDEX_MANAGER.update_labels(updates).await;
+        }
+    }
+    Ok(())
+}
@@ -30,6 +30,7 @@ use crate::{
 };
 use flow::reconcile_mailboxes;
 use rebuild::{rebuild_cache, rebuild_cache_by_date};
+use reconcile::reconcile_if_due;
 use std::time::Instant;
 use sync_folders::get_sync_folders;
 use sync_type::{determine_sync_type, SyncType};
@@ -37,6 +38,7 @@ use tracing::debug;
 
 pub mod flow;
 pub mod rebuild;
+pub mod reconcile;
 pub mod sync_folders;
 pub mod sync_type;
 
@@ -113,6 +115,7 @@ pub async fn execute_imap_sync(account: &AccountModel) -> BichonResult<()> {
     AccountRunningState::set_incremental_sync_start(account.id).await?;
     let local_mailboxes = MailBox::list_all(account_id).await?;
     reconcile_mailboxes(account, &remote_mailboxes, &local_mailboxes).await?;
+    reconcile_if_due(account, &remote_mailboxes).await?;
     let elapsed_time = start_time.elapsed().as_secs();
     debug!(
         "Account{{{}}} Incremental sync completed: {} seconds elapsed.",
@@ -19,6 +19,7 @@
 use crate::{
     modules::{
         account::migration::AccountModel,
+        blob::manager::EML_BLOB_MANAGER,
         cache::{
             imap::{
                 mailbox::MailBox,
@@ -27,7 +28,7 @@ use crate::{
             SEMAPHORE,
         },
         error::{code::ErrorCode, BichonError, BichonResult},
-        indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
     },
     raise_error,
 };
@@ -152,7 +153,7 @@ pub async fn rebuild_mailbox_cache(
     ENVELOPE_INDEX_MANAGER
         .delete_mailbox_envelopes(account.id, vec![local_mailbox.id])
         .await?;
-    EML_INDEX_MANAGER
+    EML_BLOB_MANAGER
         .delete_mailbox_envelopes(account.id, vec![local_mailbox.id])
         .await?;
     if remote_mailbox.exists == 0 {
@@ -183,7 +184,7 @@ pub async fn rebuild_mailbox_cache_by_date(
     ENVELOPE_INDEX_MANAGER
         .delete_mailbox_envelopes(account.id, vec![local_mailbox_id])
         .await?;
-    EML_INDEX_MANAGER
+    EML_BLOB_MANAGER
         .delete_mailbox_envelopes(account.id, vec![local_mailbox_id])
         .await?;
     if remote.exists == 0 {
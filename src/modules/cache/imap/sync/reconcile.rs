@@ -0,0 +1,215 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::{
+    modules::{
+        account::{migration::AccountModel, state::AccountRunningState},
+        cache::imap::{
+            find_intersecting_mailboxes,
+            mailbox::MailBox,
+            sync::flow::{changes_since, refresh_flags},
+        },
+        context::executors::MAIL_CONTEXT,
+        envelope::location::MessageLocation,
+        error::BichonResult,
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
+    },
+    utc_now,
+};
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+/// How often the archived UIDs of every mailbox are compared with those on the
+/// server, to notice messages deleted there and flags changed without CONDSTORE.
+const RECONCILE_INTERVAL_MS: i64 = 6 * 60 * 60 * 1000;
+
+/// Looks for archived messages that were deleted on the server, at most once
+/// every `RECONCILE_INTERVAL_MS`. Servers without QRESYNC do not report
+/// expunged messages, so the UIDs of each mailbox are listed and compared.
+/// Without CONDSTORE the flags of the messages still there are compared too.
+pub async fn reconcile_if_due(
+    account: &AccountModel,
+    remote_mailboxes: &[MailBox],
+) -> BichonResult<()> {
+    let Some(state) = AccountRunningState::get(account.id).await? else {
+        return Ok(());
+    };
+    if !state.is_initial_sync_completed {
+        return Ok(());
+    }
+    if let Some(at) = state.last_reconciled_at {
+        if utc_now!() - at < RECONCILE_INTERVAL_MS {
+            return Ok(());
+        }
+    }
+    // Mailboxes rebuilt by the sync have a new uid_validity by now.
+    let local_mailboxes = MailBox::list_all(account.id).await?;
+    for (local_mailbox, remote_mailbox) in
+        find_intersecting_mailboxes(&local_mailboxes, remote_mailboxes)
+    {
+        if local_mailbox.uid_validity.is_none()
+            || local_mailbox.uid_validity != remote_mailbox.uid_validity
+        {
+            continue;
+        }
+        if let Err(e) = reconcile_mailbox(account, &local_mailbox, &remote_mailbox).await {
+            warn!(
+                "Account {}: Failed to look for messages deleted from mailbox '{}' on the server: {:#?}",
+                account.id, local_mailbox.name, e
+            );
+        }
+    }
+    AccountRunningState::set_reconciled(account.id).await
+}
+
+async fn reconcile_mailbox(
+    account: &AccountModel,
+    local_mailbox: &MailBox,
+    remote_mailbox: &MailBox,
+) -> BichonResult<()> {
+    let archived = MessageLocation::live_uids(account.id, local_mailbox.id).await?;
+    if archived.is_empty() {
+        return Ok(());
+    }
+    let executor = MAIL_CONTEXT.imap(account.id).await?;
+    let on_server: HashSet<u32> = executor
+        .uid_search(&remote_mailbox.encoded_name(), "ALL")
+        .await?;
+    let Some(vanished) = vanished_uids(archived.clone(), &on_server, remote_mailbox.exists) else {
+        return Ok(());
+    };
+    record_vanished(account.id, local_mailbox, vanished).await?;
+    if changes_since(account, local_mailbox, remote_mailbox).is_none() {
+        let present = archived
+            .into_iter()
+            .filter(|uid| on_server.contains(uid))
+            .collect();
+        refresh_flags(&executor, account.id, local_mailbox, present).await?;
+    }
+    Ok(())
+}
+
+/// The archived UIDs that are not on the server, `None` when the server's answer
+/// is not to be trusted.
+fn vanished_uids(archived: Vec<u32>, on_server: &HashSet<u32>, exists: u32) -> Option<Vec<u32>> {
+    // An empty answer for a mailbox that has messages.
+    if on_server.is_empty() && exists > 0 {
+        return None;
+    }
+    Some(
+        archived
+            .into_iter()
+            .filter(|uid| !on_server.contains(uid))
+            .collect(),
+    )
+}
+
+/// Marks the archived messages with the given UIDs as deleted from the mailbox
+/// on the server. The archived copies are kept, the messages gone from all of
+/// their mailboxes get `deleted_on_server_at` in the index.
+pub async fn record_vanished(
+    account_id: u64,
+    mailbox: &MailBox,
+    uids: Vec<u32>,
+) -> BichonResult<()> {
+    if uids.is_empty() {
+        return Ok(());
+    }
+    let now = utc_now!();
+    let (count, gone) = MessageLocation::mark_vanished(account_id, mailbox.id, uids, now).await?;
+    if count == 0 {
+        return Ok(());
+    }
+    info!(
+        "Account {}: {} archived messages were deleted from mailbox '{}' on the server.",
+        account_id, count, mailbox.name
+    );
+    AccountRunningState::record_vanished(account_id, mailbox.name.clone(), count).await?;
+    if !gone.is_empty() {
+        ENVELOPE_INDEX_MANAGER
+            .mark_deleted_on_server(gone.into_iter().map(|id| (id, now)).collect())
+            .await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{record_vanished, vanished_uids};
+    use crate::modules::account::state::AccountRunningState;
+    use crate::modules::cache::imap::mailbox::MailBox;
+    use crate::modules::envelope::location::{deleted_on_server_at, MessageLocation};
+    use crate::modules::indexer::envelope::Envelope;
+    use crate::modules::settings::cli::use_test_root_dir;
+    use crate::utc_now;
+
+    #[test]
+    fn test_vanished_uids() {
+        let on_server = HashSet::from([1, 3]);
+        assert_eq!(
+            vanished_uids(vec![1, 2, 3, 4], &on_server, 2),
+            Some(vec![2, 4])
+        );
+        // The mailbox has messages, but none were listed.
+        assert_eq!(vanished_uids(vec![1, 2], &HashSet::new(), 2), None);
+        assert_eq!(
+            vanished_uids(vec![1, 2], &HashSet::new(), 0),
+            Some(vec![1, 2])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vanished_from_one_of_two_mailboxes() {
+        use_test_root_dir();
+        let (account_id, envelope_id) = (25_001, 25_101);
+        AccountRunningState::add(account_id).await.unwrap();
+        let inbox = MailBox {
+            id: 1,
+            name: "INBOX".into(),
+            ..Default::default()
+        };
+        for (mailbox_id, uid) in [(inbox.id, 4), (2, 9)] {
+            let mut envelope = Envelope {
+                id: envelope_id,
+                account_id,
+                mailbox_id,
+                uid,
+                ..Default::default()
+            };
+            MessageLocation::save(&mut envelope).await.unwrap();
+        }
+
+        // Still in the other mailbox.
+        record_vanished(account_id, &inbox, vec![4]).await.unwrap();
+        let locations = MessageLocation::list(envelope_id).await.unwrap();
+        assert!(locations
+            .iter()
+            .any(|l| l.mailbox_id == inbox.id && l.vanished_at.is_some()));
+        assert_eq!(deleted_on_server_at(&locations), None);
+
+        let at = utc_now!();
+        let vanished = MessageLocation::mark_vanished(account_id, 2, vec![9], at)
+            .await
+            .unwrap();
+        assert_eq!(vanished, (1, vec![envelope_id]));
+        let locations = MessageLocation::list(envelope_id).await.unwrap();
+        assert_eq!(deleted_on_server_at(&locations), Some(at));
+    }
+}
@@ -26,6 +26,7 @@ use crate::{
         cache::imap::mailbox::{AttributeEnum, MailBox},
         context::executors::MAIL_CONTEXT,
         error::{code::ErrorCode, BichonResult},
+        imap::gmail::is_gmail,
         mailbox::list::convert_names_to_mailboxes,
     },
     raise_error,
@@ -62,6 +63,7 @@ pub async fn get_sync_folders(account: &AccountModel) -> BichonResult<Vec<MailBo
     )
     .await?;
     let account = AccountModel::get(account.id).await?;
+    let gmail = is_gmail(&account);
     let subscribed = &account.sync_folders.unwrap_or_default();
     let is_noselect = |mailbox: &MailBox| {
         mailbox
@@ -78,6 +80,31 @@ pub async fn get_sync_folders(account: &AccountModel) -> BichonResult<Vec<MailBo
                 .any(|attr| matches!(attr.attr, AttributeEnum::Sent))
     };
 
+    // Every message of a Gmail account is in All Mail, its labels are fetched
+    // along with it instead of syncing the label folders.
+    if gmail {
+        let all_mail = mailboxes.iter().find(|(mailbox, _)| {
+            !is_noselect(mailbox)
+                && mailbox
+                    .attributes
+                    .iter()
+                    .any(|attr| matches!(attr.attr, AttributeEnum::All))
+        });
+        match all_mail {
+            Some((mailbox, name)) => {
+                debug!(
+                    "Account {}: Gmail extensions supported, syncing only '{}'",
+                    account.id, mailbox.name
+                );
+                return convert_names_to_mailboxes(account.id, vec![name]).await;
+            }
+            None => warn!(
+                "Account {}: Gmail extensions supported but no All Mail folder found, syncing the selected folders.",
+                account.id
+            ),
+        }
+    }
+
     let mut matched_mailboxes: Vec<&Name> = if !subscribed.is_empty() {
         mailboxes
             .iter()
@@ -17,7 +17,10 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
-use crate::modules::{cache::imap::task::SYNC_TASKS, error::BichonResult};
+use crate::modules::{
+    cache::imap::{idle::IDLE_WATCHERS, task::SYNC_TASKS},
+    error::BichonResult,
+};
 use std::{sync::LazyLock, time::Duration};
 use tokio::sync::mpsc;
 use tracing::{error, info};
@@ -65,7 +68,10 @@ impl SyncController {
             "Account syncer starting for account: {}-{}.",
             account_id, email
         );
-        SYNC_TASKS.start_account_sync_task(account_id, email).await;
+        SYNC_TASKS
+            .start_account_sync_task(account_id, email.clone())
+            .await;
+        IDLE_WATCHERS.start(account_id, email).await;
         tokio::time::sleep(Duration::from_millis(100)).await;
         Ok(Some(()))
     }
@@ -26,6 +26,7 @@ use crate::{
     bichon_version,
     modules::{
         account::migration::AccountModel,
+        blob::manager::EML_BLOB_MANAGER,
         common::auth::ClientContext,
         error::{code::ErrorCode, BichonResult},
         indexer::{manager::ENVELOPE_INDEX_MANAGER, schema::SchemaTools},
@@ -41,6 +42,7 @@ pub struct DashboardStats {
     pub email_count: u64,                      // Total number of emails
     pub total_size_bytes: u64,                 // Total size of all emails (in bytes)
     pub storage_usage_bytes: u64,              // Actual storage used (in bytes)
+    pub dedup_saved_bytes: u64,                // Bytes saved by attachment deduplication
     pub index_usage_bytes: u64,                // Index storage size (in bytes)
     pub recent_activity: Vec<TimeBucket>,      // Email activity over recent days
     pub top_senders: Vec<Group>,               // Top 10 senders
@@ -84,10 +86,16 @@ impl DashboardStats {
             stat.storage_usage_bytes = get_total_size(&DATA_DIR_MANAGER.eml_dir)
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
 
+            let blob_stats = EML_BLOB_MANAGER.stats().await?;
+            stat.dedup_saved_bytes = blob_stats
+                .logical_bytes
+                .saturating_sub(blob_stats.stored_bytes);
+
             stat.index_usage_bytes = get_total_size(&DATA_DIR_MANAGER.envelope_dir)
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
         } else {
             stat.storage_usage_bytes = 0;
+            stat.dedup_saved_bytes = 0;
             stat.index_usage_bytes = 0;
         }
 
@@ -0,0 +1,320 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{Cursor, Read};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use calamine::{open_workbook_auto_from_rs, Reader as _};
+use mail_parser::{Message, MessagePart, MimeHeaders};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use tracing::debug;
+
+/// Attachments larger than this are not handed to the extractors.
+const MAX_ATTACHMENT_SIZE: usize = 32 * 1024 * 1024;
+/// Upper bound on the attachment text indexed for a single message.
+const MAX_ATTACHMENT_TEXT: usize = 1024 * 1024;
+/// Upper bound on a single XML entry read out of an office document.
+const MAX_XML_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AttachmentKind {
+    Pdf,
+    Docx,
+    Odt,
+    Spreadsheet,
+    Text,
+}
+
+/// Extracts the searchable text of all attachments of `message`, including the
+/// bodies of attached messages.
+///
+/// Extraction is best effort: attachments in unknown formats, or that fail to
+/// parse, are skipped.
+pub fn extract_attachment_text(message: &Message<'_>) -> String {
+    let mut output = String::new();
+    collect_attachment_text(message, &mut output);
+    output
+}
+
+fn collect_attachment_text(message: &Message<'_>, output: &mut String) {
+    for part in message.attachments() {
+        if output.len() >= MAX_ATTACHMENT_TEXT {
+            break;
+        }
+        if let Some(nested) = part.message() {
+            if let Some(text) = nested.body_text(0) {
+                push_text(output, &text);
+            }
+            collect_attachment_text(nested, output);
+            continue;
+        }
+        if let Some(text) = extract_part_text(part) {
+            push_text(output, &text);
+        }
+    }
+}
+
+fn push_text(output: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    let remaining = MAX_ATTACHMENT_TEXT.saturating_sub(output.len());
+    if text.len() <= remaining {
+        output.push_str(text);
+    } else {
+        let mut end = remaining;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.push_str(&text[..end]);
+    }
+}
+
+fn extract_part_text(part: &MessagePart<'_>) -> Option<String> {
+    let data = part.contents();
+    if data.is_empty() || data.len() > MAX_ATTACHMENT_SIZE {
+        return None;
+    }
+    let kind = detect_kind(part)?;
+    let name = part.attachment_name().unwrap_or_default();
+    // The parsers below are not hardened against malformed input; a broken
+    // attachment must not take the sync task down with it.
+    let result = catch_unwind(AssertUnwindSafe(|| match kind {
+        AttachmentKind::Pdf => pdf_extract::extract_text_from_mem(data).ok(),
+        AttachmentKind::Docx => extract_docx_text(data),
+        AttachmentKind::Odt => extract_odt_text(data),
+        AttachmentKind::Spreadsheet => extract_spreadsheet_text(data),
+        AttachmentKind::Text => Some(
+            part.text_contents()
+                .map(String::from)
+                .unwrap_or_else(|| String::from_utf8_lossy(data).into_owned()),
+        ),
+    }));
+    match result {
+        Ok(Some(text)) => Some(text),
+        Ok(None) => {
+            debug!(
+                "Could not extract text from {:?} attachment '{}'",
+                kind, name
+            );
+            None
+        }
+        Err(_) => {
+            debug!(
+                "Text extraction panicked on {:?} attachment '{}'",
+                kind, name
+            );
+            None
+        }
+    }
+}
+
+fn detect_kind(part: &MessagePart<'_>) -> Option<AttachmentKind> {
+    if let Some(content_type) = part.content_type() {
+        let subtype = content_type
+            .subtype()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match content_type.ctype().to_ascii_lowercase().as_str() {
+            "text" if matches!(subtype.as_str(), "plain" | "csv" | "tab-separated-values") => {
+                return Some(AttachmentKind::Text)
+            }
+            "application" => match subtype.as_str() {
+                "pdf" | "x-pdf" => return Some(AttachmentKind::Pdf),
+                "vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+                    return Some(AttachmentKind::Docx)
+                }
+                "vnd.oasis.opendocument.text" => return Some(AttachmentKind::Odt),
+                "vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+                | "vnd.ms-excel.sheet.macroenabled.12"
+                | "vnd.ms-excel"
+                | "vnd.oasis.opendocument.spreadsheet" => return Some(AttachmentKind::Spreadsheet),
+                "csv" => return Some(AttachmentKind::Text),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    // Many clients send everything as application/octet-stream, fall back to
+    // the file extension.
+    let name = part.attachment_name()?;
+    let extension = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    match extension.as_str() {
+        "pdf" => Some(AttachmentKind::Pdf),
+        "docx" => Some(AttachmentKind::Docx),
+        "odt" => Some(AttachmentKind::Odt),
+        "xlsx" | "xlsm" | "xls" | "ods" => Some(AttachmentKind::Spreadsheet),
+        "txt" | "csv" | "tsv" => Some(AttachmentKind::Text),
+        _ => None,
+    }
+}
+
+fn read_zip_entry(data: &[u8], name: &str) -> Option<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).ok()?;
+    let entry = archive.by_name(name).ok()?;
+    let mut content = Vec::new();
+    entry
+        .take(MAX_XML_ENTRY_SIZE)
+        .read_to_end(&mut content)
+        .ok()?;
+    Some(content)
+}
+
+fn extract_docx_text(data: &[u8]) -> Option<String> {
+    let xml = read_zip_entry(data, "word/document.xml")?;
+    xml_text(&xml, Some(b"t"), &[b"p"], &[b"br", b"cr"])
+}
+
+fn extract_odt_text(data: &[u8]) -> Option<String> {
+    let xml = read_zip_entry(data, "content.xml")?;
+    xml_text(&xml, None, &[b"p", b"h"], &[b"line-break"])
+}
+
+/// Collects the character data of an office XML document.
+///
+/// When `text_tag` is set only text inside elements with that local name is
+/// kept, which skips field codes and other markup that is not visible content.
+/// A newline is emitted at the end of each `paragraph_tags` element and for
+/// each empty `break_tags` element.
+fn xml_text(
+    xml: &[u8],
+    text_tag: Option<&[u8]>,
+    paragraph_tags: &[&[u8]],
+    break_tags: &[&[u8]],
+) -> Option<String> {
+    let mut reader = Reader::from_reader(xml);
+    let mut output = String::new();
+    let mut inside_text = text_tag.is_none();
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) if Some(e.local_name().as_ref()) == text_tag => inside_text = true,
+            Event::End(e) => {
+                let name = e.local_name();
+                if Some(name.as_ref()) == text_tag {
+                    inside_text = false;
+                } else if paragraph_tags.contains(&name.as_ref()) {
+                    output.push('\n');
+                }
+            }
+            Event::Empty(e) => {
+                let name = e.local_name();
+                if name.as_ref() == b"tab" {
+                    output.push('\t');
+                } else if name.as_ref() == b"s" {
+                    output.push(' ');
+                } else if break_tags.contains(&name.as_ref()) {
+                    output.push('\n');
+                }
+            }
+            Event::Text(e) if inside_text => output.push_str(&e.decode().ok()?),
+            Event::CData(e) if inside_text => output.push_str(&e.decode().ok()?),
+            Event::GeneralRef(e) if inside_text => {
+                if let Ok(Some(c)) = e.resolve_char_ref() {
+                    output.push(c);
+                } else if let Some(resolved) = resolve_predefined_entity(&e.decode().ok()?) {
+                    output.push_str(resolved);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Some(output)
+}
+
+fn extract_spreadsheet_text(data: &[u8]) -> Option<String> {
+    let mut workbook = open_workbook_auto_from_rs(Cursor::new(data)).ok()?;
+    let mut output = String::new();
+    for sheet in workbook.sheet_names() {
+        let Ok(range) = workbook.worksheet_range(&sheet) else {
+            continue;
+        };
+        output.push_str(&sheet);
+        output.push('\n');
+        for row in range.rows() {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|cell| cell.to_string())
+                .filter(|cell| !cell.is_empty())
+                .collect();
+            if !cells.is_empty() {
+                output.push_str(&cells.join("\t"));
+                output.push('\n');
+            }
+            if output.len() >= MAX_ATTACHMENT_TEXT {
+                return Some(output);
+            }
+        }
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::extract_attachment_text;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use mail_parser::MessageParser;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    fn docx(document_xml: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("word/document.xml", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(document_xml.as_bytes()).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_extracts_docx_and_csv_attachments() {
+        let document = docx(
+            r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+            <w:p><w:r><w:t>Termination clause</w:t></w:r><w:r><w:t xml:space="preserve"> &amp; notice</w:t></w:r></w:p>
+            <w:p><w:r><w:instrText>PAGE</w:instrText><w:t>Second paragraph</w:t></w:r></w:p>
+            </w:body></w:document>"#,
+        );
+        let eml = format!(
+            "From: a@example.com\r\nSubject: contract\r\nMIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed; boundary=\"b1\"\r\n\r\n\
+             --b1\r\nContent-Type: text/plain\r\n\r\nsee attached\r\n\
+             --b1\r\nContent-Type: application/octet-stream\r\n\
+             Content-Disposition: attachment; filename=\"contract.docx\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n{}\r\n\
+             --b1\r\nContent-Type: text/csv\r\n\
+             Content-Disposition: attachment; filename=\"rates.csv\"\r\n\r\n\
+             name,rate\r\nacme,42\r\n\
+             --b1--\r\n",
+            STANDARD.encode(&document)
+        );
+        let message = MessageParser::new().parse(eml.as_bytes()).unwrap();
+        let text = extract_attachment_text(&message);
+
+        assert!(text.contains("Termination clause & notice\n"));
+        assert!(text.contains("Second paragraph"));
+        assert!(!text.contains("PAGE"));
+        assert!(text.contains("acme,42"));
+        assert!(!text.contains("see attached"));
+    }
+}
@@ -16,15 +16,22 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
+
 use crate::modules::common::AddrVec;
+use crate::modules::envelope::attachment::extract_attachment_text;
 use crate::modules::envelope::utils::normalize_subject;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::BichonResult;
+use crate::modules::indexer::envelope::Envelope;
+use crate::modules::indexer::headers::extract_headers;
 use crate::modules::utils::create_hash;
-use crate::{calculate_hash, raise_error, utc_now};
-use crate::{id, modules::indexer::envelope::Envelope};
-use async_imap::types::Fetch;
+use crate::raise_error;
+use async_imap::types::{Fetch, Flag};
 use mail_parser::{HeaderName, Message, MessageParser, MimeHeaders};
+use ring::digest::{digest, SHA256};
+
+const SYNTHETIC_MESSAGE_ID_DOMAIN: &str = "synthetic.bichon";
 
 pub fn extract_envelope(fetch: &Fetch, account_id: u64, mailbox_id: u64) -> BichonResult<Envelope> {
     let internal_date = fetch
@@ -56,13 +63,13 @@ pub fn extract_envelope(fetch: &Fetch, account_id: u64, mailbox_id: u64) -> Bich
         String::new()
     };
 
+    let fingerprint = content_fingerprint(&message);
     let message_id = message
         .message_id()
         .map(String::from)
-        .unwrap_or(generate_message_id());
+        .unwrap_or_else(|| synthetic_message_id(&fingerprint));
     let in_reply_to = message.in_reply_to().as_text().map(String::from);
     let references = extract_references(&message);
-    let thread_id = compute_thread_id(in_reply_to, references, &message_id);
 
     let mut subject = message.subject().map(String::from).unwrap_or_default();
 
@@ -103,12 +110,14 @@ pub fn extract_envelope(fetch: &Fetch, account_id: u64, mailbox_id: u64) -> Bich
         .filter_map(|att| att.attachment_name())
         .map(|name| name.to_string())
         .collect();
+    let attachment_text = extract_attachment_text(&message);
     let envelope = Envelope {
         id: create_hash(account_id, &message_id),
         message_id,
         account_id,
         mailbox_id,
         uid,
+        locations: Vec::new(),
         subject,
         text,
         from,
@@ -118,15 +127,43 @@ pub fn extract_envelope(fetch: &Fetch, account_id: u64, mailbox_id: u64) -> Bich
         date,
         internal_date,
         size,
-        thread_id,
+        // Set when the message is linked, see `assign_thread`.
+        thread_id: 0,
+        gmail_message_id: None,
+        deleted_on_server_at: None,
         attachments,
         tags: None,
+        flags: flag_names(fetch),
         account_email: None,
         mailbox_name: None,
+        attachment_text,
+        references: reply_chain(in_reply_to, references),
+        fingerprint,
+        display_names: extract_display_names(&message),
+        headers: extract_headers(&message),
+        highlights: None,
+        thread_matches: None,
     };
     Ok(envelope)
 }
 
+/// The flags of a fetched message as IMAP names them, e.g. `\Seen` or `$Junk`.
+/// `\Recent` only applies to the current session and is left out.
+pub fn flag_names(fetch: &Fetch) -> Vec<String> {
+    fetch
+        .flags()
+        .filter_map(|flag| match flag {
+            Flag::Seen => Some("\\Seen".to_string()),
+            Flag::Answered => Some("\\Answered".to_string()),
+            Flag::Flagged => Some("\\Flagged".to_string()),
+            Flag::Deleted => Some("\\Deleted".to_string()),
+            Flag::Draft => Some("\\Draft".to_string()),
+            Flag::Custom(name) => Some(name.into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
 pub fn extract_envelope_from_eml(
     body: &[u8],
     account_id: u64,
@@ -152,13 +189,13 @@ pub fn extract_envelope_from_eml(
         String::new()
     };
 
+    let fingerprint = content_fingerprint(&message);
     let message_id = message
         .message_id()
         .map(String::from)
-        .unwrap_or(generate_message_id());
+        .unwrap_or_else(|| synthetic_message_id(&fingerprint));
     let in_reply_to = message.in_reply_to().as_text().map(String::from);
     let references = extract_references(&message);
-    let thread_id = compute_thread_id(in_reply_to, references, &message_id);
 
     let mut subject = message.subject().map(String::from).unwrap_or_default();
     if subject.contains('\u{FFFD}') {
@@ -198,12 +235,14 @@ pub fn extract_envelope_from_eml(
         .filter_map(|att| att.attachment_name())
         .map(|name| name.to_string())
         .collect();
+    let attachment_text = extract_attachment_text(&message);
     let envelope = Envelope {
         id: create_hash(account_id, &message_id),
         message_id,
         account_id,
         mailbox_id,
         uid,
+        locations: Vec::new(),
         subject,
         text,
         from,
@@ -213,30 +252,108 @@ pub fn extract_envelope_from_eml(
         date,
         internal_date: date,
         size,
-        thread_id,
+        // Set when the message is linked, see `assign_thread`.
+        thread_id: 0,
+        gmail_message_id: None,
+        deleted_on_server_at: None,
         attachments,
         tags: None,
+        flags: Vec::new(),
         account_email: None,
         mailbox_name: None,
+        attachment_text,
+        references: reply_chain(in_reply_to, references),
+        fingerprint,
+        display_names: extract_display_names(&message),
+        headers: extract_headers(&message),
+        highlights: None,
+        thread_matches: None,
     };
     Ok(envelope)
 }
 
-pub fn compute_thread_id(
-    in_reply_to: Option<String>,
-    references: Option<Vec<String>>,
-    message_id: &str,
-) -> u64 {
-    if in_reply_to.is_some() && references.as_ref().map_or(false, |r| !r.is_empty()) {
-        return calculate_hash!(&references.as_ref().unwrap()[0]);
+/// A stable Message-ID for a message that has none, so that archiving the same
+/// message again maps to the same envelope. `fingerprint` is the one returned
+/// by `content_fingerprint`.
+pub fn synthetic_message_id(fingerprint: &str) -> String {
+    format!("<{}@{}>", &fingerprint[..32], SYNTHETIC_MESSAGE_ID_DOMAIN)
+}
+
+/// Hex-encoded SHA-256 digest identifying the content of a message.
+///
+/// It is derived from the sender, recipients, subject and date, and from the
+/// body with line endings and trailing whitespace normalized. Transport headers
+/// such as Received are left out, since they differ between copies.
+pub fn content_fingerprint(message: &Message<'_>) -> String {
+    let mut input = String::new();
+    for (name, addresses) in [
+        ("from", message.from()),
+        ("to", message.to()),
+        ("cc", message.cc()),
+    ] {
+        let addresses: Vec<String> = addresses
+            .map(|addr| {
+                AddrVec::from(addr)
+                    .0
+                    .into_iter()
+                    .filter_map(|a| a.address)
+                    .map(|a| a.trim().to_lowercase())
+                    .collect()
+            })
+            .unwrap_or_default();
+        input.push_str(&format!("{}:{}\n", name, addresses.join(",")));
     }
-    calculate_hash!(message_id)
+    let subject = message
+        .subject()
+        .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default();
+    input.push_str(&format!("subject:{}\n", subject));
+    let date = message.date().map(|d| d.to_timestamp()).unwrap_or_default();
+    input.push_str(&format!("date:{}\n", date));
+
+    let raw = message.raw_message();
+    let offset = (message.root_part().raw_body_offset() as usize).min(raw.len());
+    let body = String::from_utf8_lossy(&raw[offset..]);
+    let body = body
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let body_hash = digest(&SHA256, body.trim_end().as_bytes());
+    input.push_str(&format!("body:{}\n", hex::encode(body_hash.as_ref())));
+
+    hex::encode(digest(&SHA256, input.as_bytes()).as_ref())
 }
 
-pub fn generate_message_id() -> String {
-    let ts = utc_now!();
-    let pid = std::process::id();
-    format!("<{:016x}.{}.{}@{}>", id!(128), ts, pid, "bichon")
+/// Whether the Message-ID is a random one given to messages without a
+/// Message-ID before they got a synthetic one, e.g.
+/// `<6f1c0b1e3a2d4c5b.1700000000000.4242@bichon>`.
+pub fn is_random_message_id(message_id: &str) -> bool {
+    let Some(local) = message_id
+        .strip_prefix('<')
+        .and_then(|id| id.strip_suffix("@bichon>"))
+    else {
+        return false;
+    };
+    let parts: Vec<&str> = local.split('.').collect();
+    parts.len() == 3
+        && parts[0].len() == 16
+        && parts[0].chars().all(|c| c.is_ascii_hexdigit())
+        && parts[1..]
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The References of a message, or its In-Reply-To when it has none, as JWZ
+/// threading does. The parent is added when References misses it.
+fn reply_chain(in_reply_to: Option<String>, references: Option<Vec<String>>) -> Vec<String> {
+    let mut chain = references.unwrap_or_default();
+    if let Some(parent) = in_reply_to {
+        if !chain.contains(&parent) {
+            chain.push(parent);
+        }
+    }
+    chain
 }
 
 fn extract_references(message: &Message<'_>) -> Option<Vec<String>> {
@@ -249,9 +366,54 @@ fn extract_references(message: &Message<'_>) -> Option<Vec<String>> {
     }
 }
 
+/// Display names of all senders and recipients by address. The first name
+/// given for an address wins.
+fn extract_display_names(message: &Message<'_>) -> HashMap<String, String> {
+    let mut names = HashMap::new();
+    for addresses in [message.from(), message.to(), message.cc(), message.bcc()]
+        .into_iter()
+        .flatten()
+    {
+        for addr in AddrVec::from(addresses).0 {
+            if let (Some(name), Some(address)) = (addr.name, addr.address) {
+                if !name.trim().is_empty() {
+                    names.entry(address).or_insert(name);
+                }
+            }
+        }
+    }
+    names
+}
+
 #[cfg(test)]
 mod test {
     use html2text::config;
+    use mail_parser::MessageParser;
+
+    use super::{content_fingerprint, is_random_message_id, synthetic_message_id};
+
+    #[test]
+    fn test_synthetic_message_id() {
+        let id = |eml: &str| {
+            synthetic_message_id(&content_fingerprint(
+                &MessageParser::new().parse(eml).unwrap(),
+            ))
+        };
+        let eml = "From: Jane <Jane@x.com>\r\nTo: john@x.com\r\nSubject: Lunch\r\n\
+                   Date: Mon, 1 Jan 2024 10:00:00 +0000\r\n\r\nAt noon?  \r\n";
+        let copy = "Received: from mx.x.com\nFrom: jane@x.com\nTo: john@x.com\n\
+                    Subject: Lunch\nDate: Mon, 1 Jan 2024 10:00:00 +0000\n\nAt noon?\n\n";
+        let other = "From: jane@x.com\nTo: john@x.com\nSubject: Lunch\n\
+                     Date: Mon, 1 Jan 2024 10:00:00 +0000\n\nAt one?\n";
+        assert_eq!(id(eml), id(eml));
+        assert_eq!(id(eml), id(copy));
+        assert_ne!(id(eml), id(other));
+        assert!(id(eml).ends_with("@synthetic.bichon>"));
+        assert!(!is_random_message_id(&id(eml)));
+        assert!(is_random_message_id(
+            "<6f1c0b1e3a2d4c5b.1700000000000.4242@bichon>"
+        ));
+    }
 
     #[test]
     fn test_various_html_with_overflow_enabled() {
@@ -0,0 +1,259 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use crate::modules::account::state::{AccountRunningState, MessageIdCollision};
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
+use crate::modules::database::manager::DB_MANAGER;
+use crate::modules::database::{async_find_impl, batch_delete_impl, upsert_impl};
+use crate::modules::envelope::extractor::content_fingerprint;
+use crate::modules::error::code::ErrorCode;
+use crate::modules::error::BichonResult;
+use crate::modules::indexer::envelope::Envelope;
+use crate::modules::utils::create_hash;
+use crate::{raise_error, utc_now};
+use itertools::Itertools;
+use mail_parser::MessageParser;
+use native_db::*;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// The content fingerprint of an archived message, see `content_fingerprint`.
+///
+/// Envelope ids are derived from the Message-ID, which is not always unique:
+/// buggy mailers, spam and some ticketing systems reuse them. The fingerprint
+/// tells a copy of an archived message from a different message.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 8, version = 1)]
+#[native_db]
+pub struct MessageFingerprint {
+    /// The envelope id, see `Envelope::id`.
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    pub fingerprint: String,
+}
+
+impl MessageFingerprint {
+    async fn find(envelope_id: u64) -> BichonResult<Option<MessageFingerprint>> {
+        async_find_impl(DB_MANAGER.envelope_db(), envelope_id).await
+    }
+
+    async fn save(account_id: u64, envelope_id: u64, fingerprint: String) -> BichonResult<()> {
+        upsert_impl(
+            DB_MANAGER.envelope_db(),
+            MessageFingerprint {
+                id: envelope_id,
+                account_id,
+                fingerprint,
+            },
+        )
+        .await
+    }
+
+    /// Removes the fingerprints of the envelopes, keyed by account id.
+    pub async fn remove_envelopes(deletes: &HashMap<u64, Vec<u64>>) -> BichonResult<()> {
+        let deletes = deletes.clone();
+        batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
+            let mut fingerprints = Vec::new();
+            for (account_id, envelope_ids) in deletes {
+                for envelope_id in envelope_ids.into_iter().unique() {
+                    let found: Option<MessageFingerprint> = rw
+                        .get()
+                        .primary(envelope_id)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                    fingerprints.extend(found.filter(|f| f.account_id == account_id));
+                }
+            }
+            Ok(fingerprints)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn clean(account_id: u64) -> BichonResult<()> {
+        batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
+            let fingerprints: Vec<MessageFingerprint> = rw
+                .scan()
+                .secondary::<MessageFingerprint>(MessageFingerprintKey::account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(fingerprints)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Gives the envelope an id of its own when an archived message with different
+/// content has the same Message-ID, so that neither replaces the other. The
+/// collision is recorded in the running state of the account.
+///
+/// Must be called before the EML and the envelope are stored.
+pub async fn resolve_collision(envelope: &mut Envelope) -> BichonResult<()> {
+    let account_id = envelope.account_id;
+    let Some(archived) = archived_fingerprint(account_id, envelope.id).await? else {
+        return MessageFingerprint::save(account_id, envelope.id, envelope.fingerprint.clone())
+            .await;
+    };
+    if archived == envelope.fingerprint {
+        return Ok(());
+    }
+
+    let colliding_envelope_id = envelope.id;
+    envelope.id = collision_id(account_id, &envelope.message_id, &envelope.fingerprint);
+    // This message was seen before, the collision is already recorded.
+    if archived_fingerprint(account_id, envelope.id)
+        .await?
+        .is_some()
+    {
+        return Ok(());
+    }
+    warn!(
+        account_id = account_id,
+        message_id = %envelope.message_id,
+        envelope_id = envelope.id,
+        colliding_envelope_id = colliding_envelope_id,
+        "Message-ID is already used by a different message, archiving it separately"
+    );
+    MessageFingerprint::save(account_id, envelope.id, envelope.fingerprint.clone()).await?;
+    AccountRunningState::record_collision(
+        account_id,
+        MessageIdCollision {
+            message_id: envelope.message_id.clone(),
+            envelope_id: envelope.id,
+            colliding_envelope_id,
+            mailbox_id: envelope.mailbox_id,
+            uid: envelope.uid,
+            at: utc_now!(),
+        },
+    )
+    .await
+}
+
+/// The fingerprint of the message archived under `envelope_id`, if any.
+async fn archived_fingerprint(account_id: u64, envelope_id: u64) -> BichonResult<Option<String>> {
+    let Some(record) = EML_BLOB_MANAGER.find(account_id, envelope_id).await? else {
+        return Ok(None);
+    };
+    if let Some(found) = MessageFingerprint::find(envelope_id).await? {
+        return Ok(Some(found.fingerprint));
+    }
+    // Archived before fingerprints were recorded.
+    let eml = EML_BLOB_MANAGER.load(&record).await?;
+    let message = MessageParser::new().parse(&eml).ok_or_else(|| {
+        raise_error!(
+            format!("Failed to parse archived email {}", envelope_id),
+            ErrorCode::InternalError
+        )
+    })?;
+    let fingerprint = content_fingerprint(&message);
+    MessageFingerprint::save(account_id, envelope_id, fingerprint.clone()).await?;
+    Ok(Some(fingerprint))
+}
+
+/// Envelope id of a message whose Message-ID collides with another message.
+/// Stable, so that archiving the message again does not add another copy.
+fn collision_id(account_id: u64, message_id: &str, fingerprint: &str) -> u64 {
+    create_hash(
+        account_id,
+        &format!("{}#{}", message_id, &fingerprint[..16]),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::resolve_collision;
+    use crate::modules::account::state::AccountRunningState;
+    use crate::modules::blob::manager::EML_BLOB_MANAGER;
+    use crate::modules::envelope::extractor::extract_envelope_from_eml;
+    use crate::modules::envelope::location::MessageLocation;
+    use crate::modules::envelope::thread::{assign_thread, ThreadNode};
+    use crate::modules::indexer::analyzer::register_analyzers;
+    use crate::modules::indexer::schema::SchemaTools;
+    use crate::modules::settings::cli::use_test_root_dir;
+    use tantivy::{collector::Count, query::AllQuery, Index, IndexWriter, Term};
+
+    fn ticket(body: &str) -> Vec<u8> {
+        format!(
+            "From: help@x.com\r\nTo: b@y.com\r\nSubject: Ticket update\r\n\
+             Date: Mon, 1 Jan 2024 10:00:00 +0000\r\nMessage-ID: <ticket@x.com>\r\n\r\n{body}\r\n"
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_colliding_message_ids() {
+        use_test_root_dir();
+        let (account_id, mailbox_id) = (20_001, 1);
+        AccountRunningState::add(account_id).await.unwrap();
+        let f = SchemaTools::envelope_fields();
+        let index = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_analyzers(&index);
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+
+        // The third message is the first one archived again.
+        let mut envelopes = Vec::new();
+        for (uid, body) in [(1, "Opened."), (2, "Closed."), (3, "Opened.")] {
+            let eml = ticket(body);
+            let mut envelope = extract_envelope_from_eml(&eml, account_id, mailbox_id).unwrap();
+            envelope.uid = uid;
+            resolve_collision(&mut envelope).await.unwrap();
+            assign_thread(&mut envelope).await.unwrap();
+            EML_BLOB_MANAGER
+                .add(account_id, mailbox_id, envelope.id, &eml)
+                .await
+                .unwrap();
+            MessageLocation::save(&mut envelope).await.unwrap();
+            writer.delete_term(Term::from_field_u64(f.f_id, envelope.id));
+            writer
+                .add_document(envelope.to_document(mailbox_id).unwrap())
+                .unwrap();
+            envelopes.push(envelope);
+        }
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        assert_eq!(searcher.search(&AllQuery, &Count).unwrap(), 2);
+
+        let (first, second) = (&envelopes[0], &envelopes[1]);
+        assert_ne!(first.id, second.id);
+        assert_eq!(envelopes[2].id, first.id);
+        let state = AccountRunningState::get(account_id).await.unwrap().unwrap();
+        assert_eq!(state.message_id_collision_count, 1);
+        assert_eq!(state.message_id_collisions[0].envelope_id, second.id);
+        assert_eq!(
+            state.message_id_collisions[0].colliding_envelope_id,
+            first.id
+        );
+
+        // Both are in the thread under the node of their Message-ID.
+        assert_eq!(first.thread_id, second.thread_id);
+        let nodes = ThreadNode::list_thread(account_id, first.thread_id)
+            .await
+            .unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].id, first.id);
+        assert_eq!(nodes[0].envelope_ids, vec![first.id, second.id]);
+    }
+}
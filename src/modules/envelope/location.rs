@@ -0,0 +1,737 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::modules::database::manager::DB_MANAGER;
+use crate::modules::database::{
+    batch_delete_impl, filter_by_secondary_key_impl, with_transaction, with_transaction_result,
+};
+use crate::modules::error::code::ErrorCode;
+use crate::modules::error::BichonResult;
+use crate::modules::indexer::envelope::Envelope;
+use crate::modules::indexer::fields::{F_ACCOUNT_ID, F_ID, F_MAILBOX_ID, F_UID};
+use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
+use crate::modules::utils::create_hash;
+use crate::raise_error;
+use itertools::Itertools;
+use native_db::transaction::RwTransaction;
+use native_db::*;
+use native_model::{native_model, Model};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// A mailbox an archived message is filed in, with its UID there.
+///
+/// A message is archived once per account, see `Envelope::id`, but can be in
+/// several mailboxes at the same time, e.g. INBOX and a label folder.
+pub type MessageLocation = MessageLocationV3;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 7, version = 1)]
+#[native_db]
+pub struct MessageLocationV1 {
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub envelope_id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    #[secondary_key]
+    pub mailbox_id: u64,
+    pub uid: u32,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 7, version = 2, from = MessageLocationV1)]
+#[native_db]
+pub struct MessageLocationV2 {
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub envelope_id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    #[secondary_key]
+    pub mailbox_id: u64,
+    pub uid: u32,
+    /// Flags and keywords of the message in this mailbox, `None` until they
+    /// are fetched from the server.
+    pub flags: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 7, version = 3, from = MessageLocationV2)]
+#[native_db]
+pub struct MessageLocationV3 {
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub envelope_id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    #[secondary_key]
+    pub mailbox_id: u64,
+    pub uid: u32,
+    /// Flags and keywords of the message in this mailbox, `None` until they
+    /// are fetched from the server.
+    pub flags: Option<Vec<String>>,
+    /// When the message was found to be gone from this mailbox on the server.
+    pub vanished_at: Option<i64>,
+}
+
+impl From<MessageLocationV1> for MessageLocationV2 {
+    fn from(value: MessageLocationV1) -> Self {
+        Self {
+            id: value.id,
+            envelope_id: value.envelope_id,
+            account_id: value.account_id,
+            mailbox_id: value.mailbox_id,
+            uid: value.uid,
+            flags: None,
+        }
+    }
+}
+
+impl From<MessageLocationV2> for MessageLocationV1 {
+    fn from(value: MessageLocationV2) -> Self {
+        Self {
+            id: value.id,
+            envelope_id: value.envelope_id,
+            account_id: value.account_id,
+            mailbox_id: value.mailbox_id,
+            uid: value.uid,
+        }
+    }
+}
+
+impl From<MessageLocationV2> for MessageLocationV3 {
+    fn from(value: MessageLocationV2) -> Self {
+        Self {
+            id: value.id,
+            envelope_id: value.envelope_id,
+            account_id: value.account_id,
+            mailbox_id: value.mailbox_id,
+            uid: value.uid,
+            flags: value.flags,
+            vanished_at: None,
+        }
+    }
+}
+
+impl From<MessageLocationV3> for MessageLocationV2 {
+    fn from(value: MessageLocationV3) -> Self {
+        Self {
+            id: value.id,
+            envelope_id: value.envelope_id,
+            account_id: value.account_id,
+            mailbox_id: value.mailbox_id,
+            uid: value.uid,
+            flags: value.flags,
+        }
+    }
+}
+
+/// The highest UID archived from a mailbox, so that incremental syncs don't
+/// have to go through all locations of the mailbox to find it. Kept when the
+/// archived messages are deleted, so that they are not downloaded again.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 12, version = 1)]
+#[native_db]
+pub struct MailboxMaxUid {
+    /// Hash of the account and mailbox id.
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    pub mailbox_id: u64,
+    pub uid: u32,
+}
+
+impl MailboxMaxUid {
+    fn id(account_id: u64, mailbox_id: u64) -> u64 {
+        create_hash(account_id, &mailbox_id.to_string())
+    }
+}
+
+/// A location of an envelope as returned by the API.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct MailboxLocation {
+    pub mailbox_id: u64,
+    pub mailbox_name: Option<String>,
+    pub uid: u32,
+    /// When the message was found to be gone from the mailbox on the server.
+    pub vanished_at: Option<i64>,
+}
+
+impl MessageLocation {
+    pub fn new(account_id: u64, envelope_id: u64, mailbox_id: u64, uid: u32) -> Self {
+        Self {
+            id: create_hash(envelope_id, &mailbox_id.to_string()),
+            envelope_id,
+            account_id,
+            mailbox_id,
+            uid,
+            flags: None,
+            vanished_at: None,
+        }
+    }
+
+    /// Records the mailbox and UID of the envelope as one of its locations and
+    /// loads all of them into `envelope.locations`.
+    pub async fn save(envelope: &mut Envelope) -> BichonResult<()> {
+        let location = Self {
+            flags: Some(envelope.flags.clone()),
+            ..Self::new(
+                envelope.account_id,
+                envelope.id,
+                envelope.mailbox_id,
+                envelope.uid,
+            )
+        };
+        let locations = with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let envelope_id = location.envelope_id;
+            raise_max_uid(rw, location.account_id, location.mailbox_id, location.uid)?;
+            rw.upsert(location)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            find_locations(rw, envelope_id)
+        })
+        .await?;
+        envelope.locations = locations.iter().map(MailboxLocation::from).collect();
+        Ok(())
+    }
+
+    pub async fn list(envelope_id: u64) -> BichonResult<Vec<MessageLocation>> {
+        filter_by_secondary_key_impl(
+            DB_MANAGER.envelope_db(),
+            MessageLocationV3Key::envelope_id,
+            envelope_id,
+        )
+        .await
+    }
+
+    /// The mailbox ids of each of the envelopes. Envelopes without locations
+    /// are left out.
+    pub async fn mailboxes_of(envelope_ids: Vec<u64>) -> BichonResult<HashMap<u64, Vec<u64>>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let mut result = HashMap::new();
+            for envelope_id in envelope_ids {
+                let locations = find_locations(rw, envelope_id)?;
+                if !locations.is_empty() {
+                    result.insert(
+                        envelope_id,
+                        locations.into_iter().map(|l| l.mailbox_id).collect(),
+                    );
+                }
+            }
+            Ok(result)
+        })
+        .await
+    }
+
+    /// The highest UID archived from a mailbox.
+    pub async fn max_uid(account_id: u64, mailbox_id: u64) -> BichonResult<Option<u64>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let found: Option<MailboxMaxUid> = rw
+                .get()
+                .primary(MailboxMaxUid::id(account_id, mailbox_id))
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            if let Some(found) = found {
+                return Ok(Some(found.uid as u64));
+            }
+            // Archived before the highest UIDs were recorded.
+            let max_uid = find_in_mailbox(rw, account_id, mailbox_id)?
+                .into_iter()
+                .map(|l| l.uid)
+                .max();
+            if let Some(uid) = max_uid {
+                raise_max_uid(rw, account_id, mailbox_id, uid)?;
+            }
+            Ok(max_uid.map(u64::from))
+        })
+        .await
+    }
+
+    /// Removes the locations in the given mailboxes and returns the locations
+    /// left for every envelope that had one of them. An empty list means the
+    /// envelope is no longer in any mailbox.
+    pub async fn remove_mailboxes(
+        account_id: u64,
+        mailbox_ids: Vec<u64>,
+    ) -> BichonResult<HashMap<u64, Vec<MessageLocation>>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let mut envelope_ids = HashSet::new();
+            for mailbox_id in &mailbox_ids {
+                let max_uid: Option<MailboxMaxUid> = rw
+                    .get()
+                    .primary(MailboxMaxUid::id(account_id, *mailbox_id))
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                if let Some(max_uid) = max_uid {
+                    rw.remove(max_uid)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                }
+                for location in find_in_mailbox(rw, account_id, *mailbox_id)? {
+                    envelope_ids.insert(location.envelope_id);
+                    rw.remove(location)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                }
+            }
+            let mut remaining = HashMap::with_capacity(envelope_ids.len());
+            for envelope_id in envelope_ids {
+                remaining.insert(envelope_id, find_locations(rw, envelope_id)?);
+            }
+            Ok(remaining)
+        })
+        .await
+    }
+
+    /// Moves the locations of an envelope to another envelope of the same
+    /// message and returns all locations of the latter.
+    pub async fn move_to(
+        account_id: u64,
+        from_envelope_id: u64,
+        to_envelope_id: u64,
+    ) -> BichonResult<Vec<MessageLocation>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            for location in find_locations(rw, from_envelope_id)? {
+                let moved = Self {
+                    flags: location.flags.clone(),
+                    vanished_at: location.vanished_at,
+                    ..Self::new(
+                        account_id,
+                        to_envelope_id,
+                        location.mailbox_id,
+                        location.uid,
+                    )
+                };
+                rw.remove(location)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                rw.upsert(moved)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+            find_locations(rw, to_envelope_id)
+        })
+        .await
+    }
+
+    /// Records the flags the server reported for messages of a mailbox, by UID,
+    /// and returns the envelopes whose flags changed with their new flags. UIDs
+    /// that were not archived are ignored.
+    pub async fn update_flags(
+        account_id: u64,
+        mailbox_id: u64,
+        flags: Vec<(u32, Vec<String>)>,
+    ) -> BichonResult<Vec<(u64, Vec<String>)>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let mut locations: HashMap<u32, MessageLocation> =
+                find_in_mailbox(rw, account_id, mailbox_id)?
+                    .into_iter()
+                    .map(|l| (l.uid, l))
+                    .collect();
+            let mut changed = Vec::new();
+            for (uid, flags) in flags {
+                let Some(location) = locations.remove(&uid) else {
+                    continue;
+                };
+                if location.flags.as_ref() == Some(&flags) {
+                    continue;
+                }
+                changed.push((location.envelope_id, flags.clone()));
+                rw.upsert(MessageLocation {
+                    flags: Some(flags),
+                    ..location
+                })
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+            Ok(changed)
+        })
+        .await
+    }
+
+    /// The envelopes archived from a mailbox under the given UIDs, by UID.
+    pub async fn envelope_ids(
+        account_id: u64,
+        mailbox_id: u64,
+        uids: Vec<u32>,
+    ) -> BichonResult<HashMap<u32, u64>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let uids: HashSet<u32> = uids.into_iter().collect();
+            Ok(find_in_mailbox(rw, account_id, mailbox_id)?
+                .into_iter()
+                .filter(|l| uids.contains(&l.uid))
+                .map(|l| (l.uid, l.envelope_id))
+                .collect())
+        })
+        .await
+    }
+
+    /// The UIDs of the messages archived from a mailbox that were still there on
+    /// the server when last checked.
+    pub async fn live_uids(account_id: u64, mailbox_id: u64) -> BichonResult<Vec<u32>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            Ok(find_in_mailbox(rw, account_id, mailbox_id)?
+                .into_iter()
+                .filter(|l| l.vanished_at.is_none())
+                .map(|l| l.uid)
+                .collect())
+        })
+        .await
+    }
+
+    /// Records that the messages with the given UIDs are gone from the mailbox
+    /// on the server. Returns how many archived messages were not known to be
+    /// gone yet, and those of them that are no longer in any mailbox.
+    pub async fn mark_vanished(
+        account_id: u64,
+        mailbox_id: u64,
+        uids: Vec<u32>,
+        at: i64,
+    ) -> BichonResult<(u64, Vec<u64>)> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            let uids: HashSet<u32> = uids.into_iter().collect();
+            let mut count = 0;
+            let mut gone = Vec::new();
+            for location in find_in_mailbox(rw, account_id, mailbox_id)? {
+                if location.vanished_at.is_some() || !uids.contains(&location.uid) {
+                    continue;
+                }
+                let envelope_id = location.envelope_id;
+                rw.upsert(MessageLocation {
+                    vanished_at: Some(at),
+                    ..location
+                })
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                count += 1;
+                if deleted_on_server_at(&find_locations(rw, envelope_id)?).is_some() {
+                    gone.push(envelope_id);
+                }
+            }
+            Ok((count, gone))
+        })
+        .await
+    }
+
+    /// Removes all locations of the envelopes, keyed by account id.
+    pub async fn remove_envelopes(deletes: &HashMap<u64, Vec<u64>>) -> BichonResult<()> {
+        let deletes = deletes.clone();
+        batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
+            let mut locations = Vec::new();
+            for (account_id, envelope_ids) in deletes {
+                for envelope_id in envelope_ids.into_iter().unique() {
+                    locations.extend(
+                        find_locations(rw, envelope_id)?
+                            .into_iter()
+                            .filter(|l| l.account_id == account_id),
+                    );
+                }
+            }
+            Ok(locations)
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn clean(account_id: u64) -> BichonResult<()> {
+        batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
+            let locations: Vec<MessageLocation> = rw
+                .scan()
+                .secondary::<MessageLocation>(MessageLocationV3Key::account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(locations)
+        })
+        .await?;
+        batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
+            let max_uids: Vec<MailboxMaxUid> = rw
+                .scan()
+                .secondary::<MailboxMaxUid>(MailboxMaxUidKey::account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(max_uids)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn is_empty() -> BichonResult<bool> {
+        with_transaction_result(DB_MANAGER.envelope_db(), |rw| {
+            let first = rw
+                .scan()
+                .primary::<MessageLocation>()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .all()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .next();
+            Ok(first.is_none())
+        })
+        .await
+    }
+
+    async fn insert_batch(locations: Vec<MessageLocation>) -> BichonResult<()> {
+        with_transaction(DB_MANAGER.envelope_db(), move |rw| {
+            for location in locations {
+                raise_max_uid(rw, location.account_id, location.mailbox_id, location.uid)?;
+                rw.upsert(location)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl From<&MessageLocation> for MailboxLocation {
+    fn from(location: &MessageLocation) -> Self {
+        Self {
+            mailbox_id: location.mailbox_id,
+            mailbox_name: None,
+            uid: location.uid,
+            vanished_at: location.vanished_at,
+        }
+    }
+}
+
+/// The flags of the message in the mailbox, empty when they are not known.
+pub fn flags_in(locations: &[MessageLocation], mailbox_id: u64) -> Vec<String> {
+    locations
+        .iter()
+        .find(|l| l.mailbox_id == mailbox_id)
+        .and_then(|l| l.flags.clone())
+        .unwrap_or_default()
+}
+
+/// When the message was found to be gone from the last of its mailboxes on the
+/// server, `None` while it is still in one of them.
+pub fn deleted_on_server_at(locations: &[MessageLocation]) -> Option<i64> {
+    if locations.iter().any(|l| l.vanished_at.is_none()) {
+        return None;
+    }
+    locations.iter().filter_map(|l| l.vanished_at).max()
+}
+
+pub fn find_locations(rw: &RwTransaction, envelope_id: u64) -> BichonResult<Vec<MessageLocation>> {
+    rw.scan()
+        .secondary::<MessageLocation>(MessageLocationV3Key::envelope_id)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .start_with(envelope_id)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .try_collect()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+}
+
+/// Records `uid` as the highest UID archived from the mailbox, unless a higher
+/// one was archived before.
+fn raise_max_uid(
+    rw: &RwTransaction,
+    account_id: u64,
+    mailbox_id: u64,
+    uid: u32,
+) -> BichonResult<()> {
+    let id = MailboxMaxUid::id(account_id, mailbox_id);
+    let current: Option<MailboxMaxUid> = rw
+        .get()
+        .primary(id)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    if current.is_some_and(|current| current.uid >= uid) {
+        return Ok(());
+    }
+    rw.upsert(MailboxMaxUid {
+        id,
+        account_id,
+        mailbox_id,
+        uid,
+    })
+    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(())
+}
+
+fn find_in_mailbox(
+    rw: &RwTransaction,
+    account_id: u64,
+    mailbox_id: u64,
+) -> BichonResult<Vec<MessageLocation>> {
+    let locations: Vec<MessageLocation> = rw
+        .scan()
+        .secondary::<MessageLocation>(MessageLocationV3Key::mailbox_id)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .start_with(mailbox_id)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .try_collect()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(locations
+        .into_iter()
+        .filter(|l| l.account_id == account_id)
+        .collect())
+}
+
+/// Archives from before locations were tracked only know the last mailbox each
+/// message was seen in. Records that one as its location when there are no
+/// locations yet.
+pub async fn seed_message_locations() -> BichonResult<()> {
+    if !MessageLocation::is_empty().await? {
+        return Ok(());
+    }
+    let searcher = ENVELOPE_INDEX_MANAGER.create_searcher()?;
+    if searcher.num_docs() == 0 {
+        return Ok(());
+    }
+    info!(
+        "Recording the mailboxes of {} archived emails",
+        searcher.num_docs()
+    );
+    let mut locations = Vec::with_capacity(searcher.num_docs() as usize);
+    for segment_reader in searcher.segment_readers() {
+        let fast_fields = segment_reader.fast_fields();
+        let column = |field| {
+            fast_fields
+                .u64(field)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+        };
+        let ids = column(F_ID)?;
+        let accounts = column(F_ACCOUNT_ID)?;
+        let mailboxes = column(F_MAILBOX_ID)?;
+        let uids = column(F_UID)?;
+        for doc_id in segment_reader.doc_ids_alive() {
+            if let (Some(id), Some(account_id), Some(mailbox_id), Some(uid)) = (
+                ids.first(doc_id),
+                accounts.first(doc_id),
+                mailboxes.first(doc_id),
+                uids.first(doc_id),
+            ) {
+                locations.push(MessageLocation::new(account_id, id, mailbox_id, uid as u32));
+            }
+        }
+    }
+    // One transaction, so that an interrupted run starts over.
+    MessageLocation::insert_batch(locations).await
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::MessageLocation;
+    use crate::modules::database::manager::DB_MANAGER;
+    use crate::modules::database::with_transaction;
+    use crate::modules::indexer::envelope::Envelope;
+    use crate::modules::settings::cli::use_test_root_dir;
+
+    async fn save(account_id: u64, id: u64, mailbox_id: u64, uid: u32, flags: &[&str]) {
+        let mut envelope = Envelope {
+            id,
+            account_id,
+            mailbox_id,
+            uid,
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+            ..Default::default()
+        };
+        MessageLocation::save(&mut envelope).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_uid() {
+        use_test_root_dir();
+        let account_id = 18_001;
+        for (id, uid) in [(18_101, 5), (18_102, 9), (18_103, 7)] {
+            save(account_id, id, 1, uid, &[]).await;
+        }
+        let max_uid = |mailbox_id| MessageLocation::max_uid(account_id, mailbox_id);
+        assert_eq!(max_uid(1).await.unwrap(), Some(9));
+        assert_eq!(max_uid(2).await.unwrap(), None);
+
+        // Deleted messages are not downloaded again.
+        MessageLocation::remove_envelopes(&HashMap::from([(account_id, vec![18_102])]))
+            .await
+            .unwrap();
+        assert_eq!(max_uid(1).await.unwrap(), Some(9));
+        MessageLocation::remove_mailboxes(account_id, vec![1])
+            .await
+            .unwrap();
+        assert_eq!(max_uid(1).await.unwrap(), None);
+
+        // Locations stored before the highest UIDs were recorded.
+        with_transaction(DB_MANAGER.envelope_db(), move |rw| {
+            rw.upsert(MessageLocation::new(account_id, 18_104, 2, 3))
+                .unwrap();
+            Ok(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(max_uid(2).await.unwrap(), Some(3));
+        save(account_id, 18_105, 2, 2, &[]).await;
+        assert_eq!(max_uid(2).await.unwrap(), Some(3));
+
+        MessageLocation::clean(account_id).await.unwrap();
+        assert_eq!(max_uid(2).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_update_flags() {
+        use_test_root_dir();
+        let account_id = 18_002;
+        save(account_id, 18_201, 1, 1, &["\\Seen"]).await;
+        save(account_id, 18_202, 1, 2, &[]).await;
+
+        let changed = MessageLocation::update_flags(
+            account_id,
+            1,
+            vec![
+                (1, vec!["\\Seen".into()]),
+                (2, vec!["\\Flagged".into()]),
+                (3, vec!["\\Seen".into()]),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(changed, vec![(18_202, vec!["\\Flagged".to_string()])]);
+        let locations = MessageLocation::list(18_202).await.unwrap();
+        assert_eq!(locations[0].flags, Some(vec!["\\Flagged".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_mark_vanished() {
+        use_test_root_dir();
+        let account_id = 18_003;
+        save(account_id, 18_301, 1, 1, &[]).await;
+        save(account_id, 18_302, 1, 2, &[]).await;
+
+        let vanished = MessageLocation::mark_vanished(account_id, 1, vec![1, 3], 100)
+            .await
+            .unwrap();
+        assert_eq!(vanished, (1, vec![18_301]));
+        // Already known to be gone.
+        let vanished = MessageLocation::mark_vanished(account_id, 1, vec![1], 200)
+            .await
+            .unwrap();
+        assert_eq!(vanished, (0, vec![]));
+
+        assert_eq!(
+            MessageLocation::live_uids(account_id, 1).await.unwrap(),
+            vec![2]
+        );
+        let locations = MessageLocation::list(18_301).await.unwrap();
+        assert_eq!(locations[0].vanished_at, Some(100));
+    }
+}
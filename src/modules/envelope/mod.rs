@@ -16,5 +16,9 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod attachment;
 pub mod extractor;
+pub mod fingerprint;
+pub mod location;
+pub mod thread;
 pub mod utils;
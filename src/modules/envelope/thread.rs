@@ -0,0 +1,731 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::modules::database::manager::DB_MANAGER;
+use crate::modules::database::{
+    batch_delete_impl, filter_by_secondary_key_impl, with_transaction_result,
+};
+use crate::modules::error::code::ErrorCode;
+use crate::modules::error::BichonResult;
+use crate::modules::indexer::envelope::Envelope;
+use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
+use crate::modules::utils::create_hash;
+use crate::{calculate_hash, raise_error};
+use itertools::Itertools;
+use native_db::transaction::RwTransaction;
+use native_db::*;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+/// A message without references is only put in the thread of an earlier message
+/// with the same subject when they are at most this far apart.
+const SUBJECT_THREAD_WINDOW_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+/// Guards the walks up the tree against a corrupted parent chain.
+const MAX_THREAD_DEPTH: usize = 10_000;
+
+const REPLY_PREFIXES: &[&str] = &[
+    "re", "aw", "sv", "antw", "odp", "vs", "ref", "r", "fw", "fwd", "wg", "tr", "rv", "enc",
+];
+
+/// A container of the JWZ threading algorithm: one per Message-ID seen in an
+/// account, either as an archived message or in the references of one.
+///
+/// The tree is kept across syncs so that threads can be joined when a missing
+/// message arrives later, no matter in which mailbox of the account.
+pub type ThreadNode = ThreadNodeV2;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 6, version = 1)]
+#[native_db]
+pub struct ThreadNodeV1 {
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    pub message_id: String,
+    pub parent: Option<u64>,
+    #[secondary_key]
+    pub thread_id: u64,
+    pub present: bool,
+    #[secondary_key]
+    pub subject_key: String,
+    pub reply: bool,
+    pub date: i64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[native_model(id = 6, version = 2, from = ThreadNodeV1)]
+#[native_db]
+pub struct ThreadNodeV2 {
+    /// Hash of the account and Message-ID. The envelope id of the first message
+    /// archived with the Message-ID, see `Envelope::id`.
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    pub account_id: u64,
+    pub message_id: String,
+    pub parent: Option<u64>,
+    /// Hash of the Message-ID of the root of the tree.
+    #[secondary_key]
+    pub thread_id: u64,
+    /// `false` for messages that are only known from references.
+    pub present: bool,
+    /// `account_id:subject` with the reply prefixes removed, empty when the
+    /// message is not present or has no subject.
+    #[secondary_key]
+    pub subject_key: String,
+    /// Whether the subject had a reply or forward prefix.
+    pub reply: bool,
+    pub date: i64,
+    /// The archived envelopes with this Message-ID. More than one when different
+    /// messages share the Message-ID, see `resolve_collision`.
+    pub envelope_ids: Vec<u64>,
+}
+
+impl From<ThreadNodeV1> for ThreadNodeV2 {
+    fn from(value: ThreadNodeV1) -> Self {
+        Self {
+            id: value.id,
+            account_id: value.account_id,
+            message_id: value.message_id,
+            parent: value.parent,
+            thread_id: value.thread_id,
+            present: value.present,
+            subject_key: value.subject_key,
+            reply: value.reply,
+            date: value.date,
+            envelope_ids: if value.present {
+                vec![value.id]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl From<ThreadNodeV2> for ThreadNodeV1 {
+    fn from(value: ThreadNodeV2) -> Self {
+        Self {
+            id: value.id,
+            account_id: value.account_id,
+            message_id: value.message_id,
+            parent: value.parent,
+            thread_id: value.thread_id,
+            present: value.present,
+            subject_key: value.subject_key,
+            reply: value.reply,
+            date: value.date,
+        }
+    }
+}
+
+impl ThreadNode {
+    fn placeholder(account_id: u64, message_id: &str) -> Self {
+        Self {
+            id: create_hash(account_id, message_id),
+            account_id,
+            message_id: message_id.to_string(),
+            parent: None,
+            thread_id: calculate_hash!(message_id),
+            present: false,
+            subject_key: String::new(),
+            reply: false,
+            date: 0,
+            envelope_ids: Vec::new(),
+        }
+    }
+
+    /// The nodes of a thread, including the placeholders.
+    pub async fn list_thread(account_id: u64, thread_id: u64) -> BichonResult<Vec<ThreadNode>> {
+        let nodes: Vec<ThreadNode> = filter_by_secondary_key_impl(
+            DB_MANAGER.envelope_db(),
+            ThreadNodeV2Key::thread_id,
+            thread_id,
+        )
+        .await?;
+        Ok(nodes
+            .into_iter()
+            .filter(|node| node.account_id == account_id)
+            .collect())
+    }
+
+    /// The nodes of the threads the messages are linked in, including the
+    /// placeholders. For Gmail these can differ from the conversation Gmail
+    /// reports, see `link_thread`.
+    pub async fn list_threads_of(
+        account_id: u64,
+        message_ids: Vec<String>,
+    ) -> BichonResult<Vec<ThreadNode>> {
+        with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+            thread_nodes(rw, account_id, &message_ids)
+        })
+        .await
+    }
+
+    pub async fn clean(account_id: u64) -> BichonResult<()> {
+        batch_delete_impl(DB_MANAGER.envelope_db(), move |rw| {
+            let nodes: Vec<ThreadNode> = rw
+                .scan()
+                .secondary::<ThreadNode>(ThreadNodeV2Key::account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(account_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(nodes)
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+/// Links the envelope into the conversation tree of its account and sets its
+/// `thread_id`.
+///
+/// When the message joins threads that were apart so far, for example because
+/// it is the missing parent of earlier replies, the envelopes already indexed
+/// under the old thread ids are moved to the new one.
+pub async fn assign_thread(envelope: &mut Envelope) -> BichonResult<()> {
+    let (thread_id, moved) = link_envelope(envelope).await?;
+    envelope.thread_id = thread_id;
+    if !moved.is_empty() {
+        ENVELOPE_INDEX_MANAGER.update_thread_ids(moved).await;
+    }
+    Ok(())
+}
+
+/// Links the envelope into the conversation tree of its account without
+/// changing any thread id. For messages in the conversation Gmail reports,
+/// whose replies are still arranged by the tree.
+pub async fn link_thread(envelope: &Envelope) -> BichonResult<()> {
+    link_envelope(envelope).await?;
+    Ok(())
+}
+
+async fn link_envelope(envelope: &Envelope) -> BichonResult<(u64, Vec<(u64, u64)>)> {
+    let node = message_node(envelope);
+    let envelope_id = envelope.id;
+    let references = envelope.references.clone();
+    with_transaction_result(DB_MANAGER.envelope_db(), move |rw| {
+        link_message(rw, node, envelope_id, &references)
+    })
+    .await
+}
+
+/// The node of an archived message, before it is linked.
+fn message_node(envelope: &Envelope) -> ThreadNode {
+    let (base, reply) = base_subject(&envelope.subject);
+    ThreadNode {
+        present: true,
+        subject_key: if base.is_empty() {
+            String::new()
+        } else {
+            format!("{}:{}", envelope.account_id, base)
+        },
+        reply,
+        date: if envelope.date > 0 {
+            envelope.date
+        } else {
+            envelope.internal_date
+        },
+        ..ThreadNode::placeholder(envelope.account_id, &envelope.message_id)
+    }
+}
+
+/// Stores `message`, the node of envelope `envelope_id`, in the tree under the
+/// last of its references, or by subject when it has none. Returns its thread id
+/// and the other envelopes that changed thread.
+fn link_message(
+    rw: &RwTransaction,
+    message: ThreadNode,
+    envelope_id: u64,
+    references: &[String],
+) -> BichonResult<(u64, Vec<(u64, u64)>)> {
+    let mut graph = ThreadGraph::new(rw, message.account_id);
+    let id = graph.node(&message.message_id)?;
+    {
+        let node = graph.get_mut(id);
+        node.present = true;
+        node.subject_key = message.subject_key;
+        node.reply = message.reply;
+        node.date = message.date;
+        if !node.envelope_ids.contains(&envelope_id) {
+            node.envelope_ids.push(envelope_id);
+        }
+    }
+    graph.dirty.insert(id);
+
+    let mut chain = Vec::with_capacity(references.len());
+    for reference in references.iter().filter(|r| **r != message.message_id) {
+        let node = graph.node(reference)?;
+        if !chain.contains(&node) {
+            chain.push(node);
+        }
+    }
+    // Links known from earlier messages win over the ones implied by these
+    // References, except for the parent of this message.
+    for pair in chain.windows(2) {
+        if graph.get(pair[1]).parent.is_none() {
+            graph.set_parent(pair[1], pair[0])?;
+        }
+    }
+    match chain.last() {
+        Some(&parent) => {
+            graph.set_parent(id, parent)?;
+        }
+        None => graph.link_by_subject(id)?,
+    }
+
+    let moved = graph.renumber(envelope_id)?;
+    graph.save()?;
+    Ok((graph.get(id).thread_id, moved))
+}
+
+fn thread_nodes(
+    rw: &RwTransaction,
+    account_id: u64,
+    message_ids: &[String],
+) -> BichonResult<Vec<ThreadNode>> {
+    let mut thread_ids = HashSet::new();
+    for message_id in message_ids {
+        let node: Option<ThreadNode> = rw
+            .get()
+            .primary(create_hash(account_id, message_id))
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        if let Some(node) = node {
+            thread_ids.insert(node.thread_id);
+        }
+    }
+    let mut nodes = Vec::new();
+    for thread_id in thread_ids {
+        let members: Vec<ThreadNode> = rw
+            .scan()
+            .secondary::<ThreadNode>(ThreadNodeV2Key::thread_id)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            .start_with(thread_id)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            .try_collect()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        nodes.extend(members.into_iter().filter(|n| n.account_id == account_id));
+    }
+    Ok(nodes)
+}
+
+/// The nodes of an account touched while linking one message.
+struct ThreadGraph<'a> {
+    rw: &'a RwTransaction<'a>,
+    account_id: u64,
+    nodes: HashMap<u64, ThreadNode>,
+    /// Thread ids of the nodes whose parent changed, before the change.
+    touched_threads: HashSet<u64>,
+    dirty: HashSet<u64>,
+}
+
+impl<'a> ThreadGraph<'a> {
+    fn new(rw: &'a RwTransaction<'a>, account_id: u64) -> Self {
+        Self {
+            rw,
+            account_id,
+            nodes: HashMap::new(),
+            touched_threads: HashSet::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    fn get(&self, id: u64) -> &ThreadNode {
+        &self.nodes[&id]
+    }
+
+    fn get_mut(&mut self, id: u64) -> &mut ThreadNode {
+        self.nodes.get_mut(&id).expect("thread node is loaded")
+    }
+
+    /// Loads the node of a Message-ID, creating a placeholder for unknown ones.
+    fn node(&mut self, message_id: &str) -> BichonResult<u64> {
+        let id = create_hash(self.account_id, message_id);
+        if !self.load(id)? {
+            self.nodes
+                .insert(id, ThreadNode::placeholder(self.account_id, message_id));
+            self.dirty.insert(id);
+        }
+        Ok(id)
+    }
+
+    /// Loads a stored node, returns `false` if there is none.
+    fn load(&mut self, id: u64) -> BichonResult<bool> {
+        if self.nodes.contains_key(&id) {
+            return Ok(true);
+        }
+        let node: Option<ThreadNode> = self
+            .rw
+            .get()
+            .primary(id)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(match node {
+            Some(node) => {
+                self.nodes.insert(id, node);
+                true
+            }
+            None => false,
+        })
+    }
+
+    fn parent(&mut self, id: u64) -> BichonResult<Option<u64>> {
+        let parent = self.get(id).parent;
+        match parent {
+            Some(parent) if self.load(parent)? => Ok(Some(parent)),
+            _ => Ok(None),
+        }
+    }
+
+    fn root(&mut self, id: u64) -> BichonResult<u64> {
+        let mut current = id;
+        for _ in 0..MAX_THREAD_DEPTH {
+            match self.parent(current)? {
+                Some(parent) => current = parent,
+                None => return Ok(current),
+            }
+        }
+        Err(raise_error!(
+            format!("thread of node {} is too deep or has a loop", id),
+            ErrorCode::InternalError
+        ))
+    }
+
+    fn is_ancestor(&mut self, ancestor: u64, id: u64) -> BichonResult<bool> {
+        let mut current = id;
+        for _ in 0..MAX_THREAD_DEPTH {
+            if current == ancestor {
+                return Ok(true);
+            }
+            match self.parent(current)? {
+                Some(parent) => current = parent,
+                None => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Makes `parent` the parent of `child`, unless that would create a loop.
+    fn set_parent(&mut self, child: u64, parent: u64) -> BichonResult<bool> {
+        if self.get(child).parent == Some(parent) {
+            return Ok(true);
+        }
+        if self.is_ancestor(child, parent)? {
+            return Ok(false);
+        }
+        let node = self.get_mut(child);
+        node.parent = Some(parent);
+        let thread_id = node.thread_id;
+        self.touched_threads.insert(thread_id);
+        self.dirty.insert(child);
+        Ok(true)
+    }
+
+    /// Subject fallback for a message without references: a reply goes under
+    /// the earliest thread started with the same subject, and an original
+    /// adopts the replies to it that arrived first.
+    fn link_by_subject(&mut self, id: u64) -> BichonResult<()> {
+        let node = self.get(id).clone();
+        if node.subject_key.is_empty() {
+            return Ok(());
+        }
+        let candidates: Vec<ThreadNode> = self
+            .rw
+            .scan()
+            .secondary::<ThreadNode>(ThreadNodeV2Key::subject_key)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            .start_with(node.subject_key.clone())
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            .try_collect()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let candidates: Vec<ThreadNode> = candidates
+            .into_iter()
+            .filter(|c| {
+                c.id != id
+                    && c.present
+                    && c.parent.is_none()
+                    && c.subject_key == node.subject_key
+                    && (c.date - node.date).abs() <= SUBJECT_THREAD_WINDOW_MS
+            })
+            .collect();
+
+        if node.reply {
+            if let Some(root) = candidates
+                .iter()
+                .filter(|c| c.date <= node.date)
+                .min_by_key(|c| c.date)
+            {
+                let root = root.id;
+                self.load(root)?;
+                self.set_parent(id, root)?;
+            }
+        } else {
+            for reply in candidates.iter().filter(|c| c.reply && c.date >= node.date) {
+                let reply = reply.id;
+                self.load(reply)?;
+                self.set_parent(reply, id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Recomputes the thread id of every node in the touched threads and
+    /// returns the envelopes other than `envelope_id` that moved to another one.
+    fn renumber(&mut self, envelope_id: u64) -> BichonResult<Vec<(u64, u64)>> {
+        let threads: Vec<u64> = self.touched_threads.iter().copied().collect();
+        for thread_id in threads {
+            let members: Vec<ThreadNode> = self
+                .rw
+                .scan()
+                .secondary::<ThreadNode>(ThreadNodeV2Key::thread_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(thread_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            for member in members {
+                if member.account_id == self.account_id {
+                    self.nodes.entry(member.id).or_insert(member);
+                }
+            }
+        }
+
+        let mut moved = Vec::new();
+        let ids: Vec<u64> = self.nodes.keys().copied().collect();
+        for node_id in ids {
+            let root = self.root(node_id)?;
+            let thread_id = calculate_hash!(&self.get(root).message_id);
+            let node = self.get_mut(node_id);
+            if node.thread_id != thread_id {
+                node.thread_id = thread_id;
+                moved.extend(
+                    node.envelope_ids
+                        .iter()
+                        .filter(|id| **id != envelope_id)
+                        .map(|id| (*id, thread_id)),
+                );
+                self.dirty.insert(node_id);
+            }
+        }
+        Ok(moved)
+    }
+
+    fn save(&mut self) -> BichonResult<()> {
+        for id in self.dirty.drain() {
+            let node = self.nodes[&id].clone();
+            self.rw
+                .upsert(node)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+        Ok(())
+    }
+}
+
+/// The subject without reply and forward prefixes or mailing list tags,
+/// lowercased, and whether any prefix was removed.
+pub fn base_subject(subject: &str) -> (String, bool) {
+    let mut rest = subject.trim();
+    let mut reply = false;
+    loop {
+        if let Some(tagged) = rest.strip_prefix('[') {
+            if let Some(end) = tagged.find(']') {
+                let after = tagged[end + 1..].trim_start();
+                if !after.is_empty() {
+                    rest = after;
+                    continue;
+                }
+            }
+        }
+        if let Some(colon) = rest.find(':') {
+            let prefix = rest[..colon]
+                .trim_end()
+                .trim_end_matches(|c: char| c.is_ascii_digit() || "[]()".contains(c));
+            if REPLY_PREFIXES.contains(&prefix.to_lowercase().as_str()) {
+                rest = rest[colon + 1..].trim_start();
+                reply = true;
+                continue;
+            }
+        }
+        break;
+    }
+    let base = rest
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    (base, reply)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{base_subject, link_message, message_node, ThreadNode};
+    use crate::calculate_hash;
+    use crate::modules::cache::imap::MAILBOX_MODELS;
+    use crate::modules::envelope::extractor::extract_envelope_from_eml;
+    use crate::modules::indexer::envelope::Envelope;
+    use crate::modules::settings::cli::use_test_root_dir;
+    use crate::modules::utils::create_hash;
+    use native_db::{Builder, Database};
+
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+    fn message(
+        id: u64,
+        message_id: &str,
+        subject: &str,
+        date: i64,
+        references: &[&str],
+    ) -> Envelope {
+        Envelope {
+            id,
+            account_id: 1,
+            message_id: message_id.into(),
+            subject: subject.into(),
+            date,
+            references: references.iter().map(|r| r.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Links the message and returns its thread id and the envelopes that moved.
+    fn link(db: &Database, envelope: &Envelope) -> (u64, Vec<(u64, u64)>) {
+        let rw = db.rw_transaction().unwrap();
+        let result = link_message(
+            &rw,
+            message_node(envelope),
+            envelope.id,
+            &envelope.references,
+        )
+        .unwrap();
+        rw.commit().unwrap();
+        result
+    }
+
+    fn node(db: &Database, account_id: u64, message_id: &str) -> ThreadNode {
+        let r = db.r_transaction().unwrap();
+        r.get()
+            .primary(create_hash(account_id, message_id))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_colliding_envelopes_move_together() {
+        let db = Builder::new().create_in_memory(&MAILBOX_MODELS).unwrap();
+        // Two different messages with the Message-ID of a reply, then the
+        // original they reply to, which adopts them by subject.
+        let (reply, _) = link(&db, &message(10, "r@x", "Re: Hello", 2 * DAY_MS, &[]));
+        let (collided, moved) = link(&db, &message(11, "r@x", "Re: Hello", 3 * DAY_MS, &[]));
+        assert_eq!(reply, collided);
+        assert!(moved.is_empty());
+        assert_eq!(node(&db, 1, "r@x").envelope_ids, vec![10, 11]);
+
+        let (thread_id, mut moved) = link(&db, &message(1, "o@x", "Hello", DAY_MS, &[]));
+        assert_eq!(thread_id, calculate_hash!("o@x"));
+        moved.sort();
+        assert_eq!(moved, vec![(10, thread_id), (11, thread_id)]);
+    }
+
+    #[test]
+    fn test_late_parent_moves_replies() {
+        let db = Builder::new().create_in_memory(&MAILBOX_MODELS).unwrap();
+        // The reply only knows its parent, which has not arrived yet.
+        let (thread_id, _) = link(&db, &message(3, "c@x", "Re: Hello", 3 * DAY_MS, &["b@x"]));
+        assert_eq!(thread_id, calculate_hash!("b@x"));
+
+        let (thread_id, moved) = link(&db, &message(2, "b@x", "Re: Hello", 2 * DAY_MS, &["a@x"]));
+        assert_eq!(thread_id, calculate_hash!("a@x"));
+        assert_eq!(moved, vec![(3, thread_id)]);
+        assert_eq!(node(&db, 1, "b@x").parent, Some(create_hash(1, "a@x")));
+        assert_eq!(node(&db, 1, "c@x").parent, Some(create_hash(1, "b@x")));
+        assert_eq!(node(&db, 1, "c@x").thread_id, thread_id);
+    }
+
+    #[test]
+    fn test_in_reply_to_without_references() {
+        use_test_root_dir();
+        let db = Builder::new().create_in_memory(&MAILBOX_MODELS).unwrap();
+        let (thread_id, _) = link(&db, &message(1, "a@x", "Hello", DAY_MS, &[]));
+
+        let eml = b"Message-ID: <b@x>\r\n\
+In-Reply-To: <a@x>\r\n\
+From: bob@x.com\r\n\
+Subject: Something else\r\n\
+Date: Fri, 02 Jan 2025 10:00:00 +0000\r\n\
+\r\n\
+Agreed.\r\n";
+        let reply = extract_envelope_from_eml(eml, 1, 1).unwrap();
+        assert_eq!(reply.references, vec!["a@x".to_string()]);
+        let (reply_thread, moved) = link(&db, &reply);
+        assert_eq!(reply_thread, thread_id);
+        assert!(moved.is_empty());
+        assert_eq!(node(&db, 1, "b@x").parent, Some(create_hash(1, "a@x")));
+    }
+
+    #[test]
+    fn test_subject_window() {
+        let db = Builder::new().create_in_memory(&MAILBOX_MODELS).unwrap();
+        let (thread_id, _) = link(&db, &message(1, "a@x", "Hello", DAY_MS, &[]));
+
+        let (within, _) = link(&db, &message(2, "b@x", "Re: Hello", 30 * DAY_MS, &[]));
+        assert_eq!(within, thread_id);
+
+        let (outside, _) = link(&db, &message(3, "c@x", "Re: Hello", 32 * DAY_MS, &[]));
+        assert_eq!(outside, calculate_hash!("c@x"));
+        assert_eq!(node(&db, 1, "c@x").parent, None);
+    }
+
+    #[test]
+    fn test_merged_threads_report_moved_envelopes() {
+        let db = Builder::new().create_in_memory(&MAILBOX_MODELS).unwrap();
+        let (first, _) = link(&db, &message(1, "a1@x", "Re: One", DAY_MS, &["x@x"]));
+        let (second, _) = link(&db, &message(2, "b1@x", "Re: Two", DAY_MS, &["y@x"]));
+        assert_ne!(first, second);
+
+        // Its References tell that y@x is a reply to x@x.
+        let (thread_id, moved) = link(
+            &db,
+            &message(3, "m@x", "Re: Two", 2 * DAY_MS, &["x@x", "y@x"]),
+        );
+        assert_eq!(thread_id, first);
+        assert_eq!(moved, vec![(2, first)]);
+        assert_eq!(node(&db, 1, "b1@x").thread_id, first);
+        assert_eq!(node(&db, 1, "a1@x").thread_id, first);
+    }
+
+    #[test]
+    fn test_base_subject() {
+        assert_eq!(base_subject("Hello"), ("hello".into(), false));
+        assert_eq!(base_subject("Re: Hello"), ("hello".into(), true));
+        assert_eq!(
+            base_subject("RE: Fwd: re[2]: Hello  World"),
+            ("hello world".into(), true)
+        );
+        assert_eq!(base_subject("[dev] Re: Hello"), ("hello".into(), true));
+        assert_eq!(base_subject("AW:Hello"), ("hello".into(), true));
+        assert_eq!(
+            base_subject("Update: Hello"),
+            ("update: hello".into(), false)
+        );
+        assert_eq!(base_subject("[dev]"), ("[dev]".into(), false));
+        assert_eq!(base_subject(""), ("".into(), false));
+    }
+}
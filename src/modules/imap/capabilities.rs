@@ -16,6 +16,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::modules::account::migration::AccountModel;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::imap::session::SessionStream;
 use crate::{modules::error::BichonResult, raise_error};
@@ -41,6 +42,15 @@ pub fn check_capabilities(capabilities: &Capabilities) -> BichonResult<()> {
     Ok(())
 }
 
+/// Whether the server reported the capability the last time the account
+/// connected, e.g. `CONDSTORE`.
+pub fn has_capability(account: &AccountModel, capability: &str) -> bool {
+    account
+        .capabilities
+        .as_ref()
+        .is_some_and(|caps| caps.iter().any(|c| c.eq_ignore_ascii_case(capability)))
+}
+
 pub fn capability_to_string(capability: &Capability) -> String {
     match capability {
         Capability::Imap4rev1 => "IMAP4rev1".into(),
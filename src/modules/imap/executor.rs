@@ -18,22 +18,39 @@
 
 use crate::modules::account::migration::AccountModel;
 use crate::modules::account::state::AccountRunningState;
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
 use crate::modules::cache::imap::mailbox::MailBox;
 use crate::modules::cache::imap::sync::flow::{generate_uid_sequence_hashset, DEFAULT_BATCH_SIZE};
-use crate::modules::envelope::extractor::extract_envelope;
+use crate::modules::envelope::extractor::{extract_envelope, flag_names};
+use crate::modules::envelope::fingerprint::resolve_collision;
+use crate::modules::envelope::location::MessageLocation;
+use crate::modules::envelope::thread::{assign_thread, link_thread};
 use crate::modules::error::code::ErrorCode;
-use crate::modules::indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER};
-use crate::modules::indexer::schema::SchemaTools;
+use crate::modules::imap::gmail::{
+    is_gmail, parse_gmail_fetches, GmailAttributes, GMAIL_FETCH_ITEMS,
+};
+use crate::modules::imap::session::SessionStream;
+use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
 use crate::modules::{error::BichonResult, imap::manager::ImapConnectionManager};
 use crate::raise_error;
-use async_imap::types::{Mailbox, Name};
+use async_imap::types::{Fetch, Mailbox, Name, UnsolicitedResponse};
+use async_imap::Session;
 use bb8::{Pool, RunError};
 use futures::TryStreamExt;
-use std::collections::HashSet;
-use tantivy::doc;
+use imap_proto::{Response, Status};
+use std::collections::{HashMap, HashSet};
 use tracing::info;
 
-const BODY_FETCH_COMMAND: &str = "(UID INTERNALDATE RFC822.SIZE BODY.PEEK[])";
+const BODY_FETCH_COMMAND: &str = "(UID INTERNALDATE RFC822.SIZE FLAGS BODY.PEEK[])";
+
+/// What changed in a mailbox since a given mod-sequence, see `fetch_changes`.
+#[derive(Debug, Default)]
+pub struct MailboxChanges {
+    /// Messages that are new or whose flags changed, with their current flags.
+    pub changed: Vec<(u32, Vec<String>)>,
+    /// Messages expunged, only known with QRESYNC.
+    pub vanished: Vec<u32>,
+}
 
 pub struct ImapExecutor {
     account_id: u64,
@@ -117,8 +134,21 @@ impl ImapExecutor {
             "[account {}][mailbox {}] {} envelopes need to be fetched",
             account.id, mailbox.name, len
         );
+        self.retrieve_uids(account, mailbox, uid_list.into_iter().collect())
+            .await
+    }
 
-        let mut uid_vec: Vec<u32> = uid_list.into_iter().collect();
+    /// Fetches and archives the given messages of the mailbox in batches.
+    pub async fn retrieve_uids(
+        &self,
+        account: &AccountModel,
+        mailbox: &MailBox,
+        mut uid_vec: Vec<u32>,
+    ) -> BichonResult<()> {
+        if uid_vec.is_empty() {
+            return Ok(());
+        }
+        let len = uid_vec.len();
         uid_vec.sort();
         let uid_batches = generate_uid_sequence_hashset(
             uid_vec,
@@ -151,6 +181,84 @@ impl ImapExecutor {
         Ok(())
     }
 
+    /// The messages of the mailbox whose mod-sequence is above `modseq`, which
+    /// includes the messages added since then. With `qresync`, the UIDs expunged
+    /// since then are returned too; the pooled connections enable QRESYNC when
+    /// they are created.
+    pub async fn fetch_changes(
+        &self,
+        mailbox: &MailBox,
+        modseq: u64,
+        qresync: bool,
+    ) -> BichonResult<MailboxChanges> {
+        let mut session = self.get_connection().await?;
+        session
+            .examine(mailbox.encoded_name())
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+        // Left over from earlier commands on this pooled connection.
+        while session.unsolicited_responses.try_recv().is_ok() {}
+
+        let modifiers = if qresync {
+            format!("(CHANGEDSINCE {modseq} VANISHED)")
+        } else {
+            format!("(CHANGEDSINCE {modseq})")
+        };
+        let changed = session
+            .uid_fetch("1:*", format!("(UID FLAGS) {modifiers}"))
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
+            .try_collect::<Vec<Fetch>>()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+        let changed = message_flags(&changed);
+
+        let mut vanished = Vec::new();
+        while let Ok(response) = session.unsolicited_responses.try_recv() {
+            if let UnsolicitedResponse::Other(data) = response {
+                if let Response::Vanished { uids, .. } = data.parsed() {
+                    vanished.extend(uids.iter().flat_map(|range| range.clone()));
+                }
+            }
+        }
+        Ok(MailboxChanges { changed, vanished })
+    }
+
+    /// The current flags of the messages of the mailbox in `uid_set`, by UID.
+    pub async fn fetch_flags(
+        &self,
+        mailbox: &MailBox,
+        uid_set: &str,
+    ) -> BichonResult<Vec<(u32, Vec<String>)>> {
+        let mut session = self.get_connection().await?;
+        session
+            .examine(mailbox.encoded_name())
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+        let fetches = session
+            .uid_fetch(uid_set, "(UID FLAGS)")
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
+            .try_collect::<Vec<Fetch>>()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+        Ok(message_flags(&fetches))
+    }
+
+    /// The Gmail attributes of the messages of the mailbox in `uid_set`, by UID.
+    pub async fn fetch_gmail_attributes(
+        &self,
+        mailbox: &MailBox,
+        uid_set: &str,
+    ) -> BichonResult<HashMap<u32, GmailAttributes>> {
+        let mut session = self.get_connection().await?;
+        session
+            .examine(mailbox.encoded_name())
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+        gmail_attributes(&mut session, &format!("UID FETCH {uid_set}")).await
+    }
+
     pub async fn batch_retrieve_emails(
         &self,
         account_id: u64,
@@ -200,26 +308,24 @@ impl ImapExecutor {
             encoded_mailbox_name, sequence_set, page, page_size, desc
         );
 
+        let gmail = if self.is_gmail().await? {
+            gmail_attributes(&mut session, &format!("FETCH {sequence_set}")).await?
+        } else {
+            HashMap::new()
+        };
         let mut stream = session
             .fetch(sequence_set.as_str(), BODY_FETCH_COMMAND)
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
 
         let mut count = 0;
-        let fields = SchemaTools::eml_fields();
         while let Some(fetch) = stream
             .try_next()
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
         {
-            let envelope = extract_envelope(&fetch, account_id, mailbox_id)?;
-            ENVELOPE_INDEX_MANAGER
-                .add_document(envelope.id, envelope.to_document(mailbox_id)?)
-                .await;
-            let body = fetch.body().ok_or_else(|| {
-                raise_error!("missing a body".into(), ErrorCode::ImapUnexpectedResult)
-            })?;
-            EML_INDEX_MANAGER.add_document( envelope.id, doc!(fields.f_id => envelope.id, fields.f_account_id => account_id, fields.f_mailbox_id => mailbox_id, fields.f_eml => body)).await;
+            let attributes = fetch.uid.and_then(|uid| gmail.get(&uid));
+            archive_message(&fetch, account_id, mailbox_id, attributes).await?;
             count += 1;
         }
         Ok(count)
@@ -238,28 +344,32 @@ impl ImapExecutor {
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
 
+        let gmail = if self.is_gmail().await? {
+            gmail_attributes(&mut session, &format!("UID FETCH {uid_set}")).await?
+        } else {
+            HashMap::new()
+        };
         let mut stream = session
             .uid_fetch(uid_set, BODY_FETCH_COMMAND)
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
-        let fields = SchemaTools::eml_fields();
         while let Some(fetch) = stream
             .try_next()
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
         {
-            let envelope = extract_envelope(&fetch, account_id, mailbox_id)?;
-            ENVELOPE_INDEX_MANAGER
-                .add_document(envelope.id, envelope.to_document(mailbox_id)?)
-                .await;
-            let body = fetch.body().ok_or_else(|| {
-                raise_error!("missing a body".into(), ErrorCode::ImapUnexpectedResult)
-            })?;
-            EML_INDEX_MANAGER.add_document( envelope.id, doc!(fields.f_id => envelope.id, fields.f_account_id => account_id, fields.f_mailbox_id => mailbox_id, fields.f_eml => body)).await;
+            let attributes = fetch.uid.and_then(|uid| gmail.get(&uid));
+            archive_message(&fetch, account_id, mailbox_id, attributes).await?;
         }
         Ok(())
     }
 
+    /// Checked on every batch: the capabilities of an account are only known
+    /// once it connected.
+    async fn is_gmail(&self) -> BichonResult<bool> {
+        Ok(is_gmail(&AccountModel::get(self.account_id).await?))
+    }
+
     async fn get_connection(
         &self,
     ) -> BichonResult<bb8::PooledConnection<'_, ImapConnectionManager>> {
@@ -297,3 +407,95 @@ impl ImapExecutor {
         }
     }
 }
+
+fn message_flags(fetches: &[Fetch]) -> Vec<(u32, Vec<String>)> {
+    fetches
+        .iter()
+        .filter_map(|fetch| fetch.uid.map(|uid| (uid, flag_names(fetch))))
+        .collect()
+}
+
+/// Archives a fetched message. Messages from Gmail are put in the conversation
+/// Gmail reports and get their labels as tags.
+async fn archive_message(
+    fetch: &Fetch,
+    account_id: u64,
+    mailbox_id: u64,
+    gmail: Option<&GmailAttributes>,
+) -> BichonResult<()> {
+    let mut envelope = extract_envelope(fetch, account_id, mailbox_id)?;
+    resolve_collision(&mut envelope).await?;
+    match gmail {
+        Some(
+            attributes @ GmailAttributes {
+                thread_id: Some(thread_id),
+                ..
+            },
+        ) => {
+            link_thread(&envelope).await?;
+            envelope.thread_id = *thread_id;
+            envelope.gmail_message_id = attributes.message_id;
+            envelope.tags = Some(attributes.tags());
+        }
+        _ => assign_thread(&mut envelope).await?,
+    }
+    let body = fetch
+        .body()
+        .ok_or_else(|| raise_error!("missing a body".into(), ErrorCode::ImapUnexpectedResult))?;
+    EML_BLOB_MANAGER
+        .add(account_id, mailbox_id, envelope.id, body)
+        .await?;
+    MessageLocation::save(&mut envelope).await?;
+    ENVELOPE_INDEX_MANAGER
+        .add_document(envelope.id, envelope.to_document(mailbox_id)?)
+        .await;
+    Ok(())
+}
+
+/// Runs `fetch`, a `FETCH` or `UID FETCH` of a message set, for the Gmail
+/// attributes, which async-imap does not expose. The untagged responses are
+/// read up to the tagged one of the command.
+async fn gmail_attributes(
+    session: &mut Session<Box<dyn SessionStream>>,
+    fetch: &str,
+) -> BichonResult<HashMap<u32, GmailAttributes>> {
+    let command = format!("{fetch} {GMAIL_FETCH_ITEMS}");
+    let id = session
+        .run_command(&command)
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
+    let mut responses = Vec::new();
+    loop {
+        let response = session
+            .read_response()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
+            .ok_or_else(|| {
+                raise_error!(
+                    format!("Connection lost while running '{}'", command),
+                    ErrorCode::ImapCommandFailed
+                )
+            })?;
+        if let Response::Done {
+            tag,
+            status,
+            information,
+            ..
+        } = response.parsed()
+        {
+            if *tag == id {
+                if *status != Status::Ok {
+                    return Err(raise_error!(
+                        format!("'{}' failed: {:?} {:?}", command, status, information),
+                        ErrorCode::ImapCommandFailed
+                    ));
+                }
+                break;
+            }
+        }
+        responses.push(response);
+    }
+    Ok(parse_gmail_fetches(
+        responses.iter().map(|response| response.parsed()),
+    ))
+}
@@ -0,0 +1,155 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Gmail IMAP extensions, advertised as `X-GM-EXT-1`.
+//!
+//! Gmail files every message in "[Gmail]/All Mail" and shows its labels as
+//! folders, so the same message is in many mailboxes. Accounts with the
+//! extension only sync All Mail and keep the labels as tags instead.
+
+use std::collections::HashMap;
+
+use crate::decode_mailbox_name;
+use crate::modules::account::migration::AccountModel;
+use crate::modules::imap::capabilities::has_capability;
+use imap_proto::{AttributeValue, Response};
+use tantivy::schema::Facet;
+
+pub const GMAIL_EXTENSION: &str = "X-GM-EXT-1";
+pub const GMAIL_FETCH_ITEMS: &str = "(UID X-GM-MSGID X-GM-THRID X-GM-LABELS)";
+/// Parent of the tags of the labels, e.g. `/gmail/Inbox` or `/gmail/Work/Projects`.
+const LABEL_TAG_ROOT: &str = "/gmail";
+
+/// The Gmail attributes of a message.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GmailAttributes {
+    /// X-GM-MSGID, the same in every folder of the message.
+    pub message_id: Option<u64>,
+    /// X-GM-THRID, the conversation of the message.
+    pub thread_id: Option<u64>,
+    /// X-GM-LABELS, e.g. `\Inbox`, `\Important` or `Work/Projects`.
+    pub labels: Vec<String>,
+}
+
+impl GmailAttributes {
+    /// The tags of the labels of the message.
+    pub fn tags(&self) -> Vec<String> {
+        self.labels
+            .iter()
+            .filter_map(|label| label_tag(label))
+            .collect()
+    }
+}
+
+/// Whether the server reported the Gmail extensions the last time the account
+/// connected.
+pub fn is_gmail(account: &AccountModel) -> bool {
+    has_capability(account, GMAIL_EXTENSION)
+}
+
+/// The tag of a label: system labels lose their backslash and nested labels
+/// become nested tags, e.g. `\Inbox` is `/gmail/Inbox`.
+pub fn label_tag(label: &str) -> Option<String> {
+    let label = decode_mailbox_name!(label);
+    let label = label.strip_prefix('\\').unwrap_or(&label);
+    let steps: Vec<&str> = label.split('/').filter(|s| !s.is_empty()).collect();
+    if steps.is_empty() {
+        return None;
+    }
+    let root = Facet::from(LABEL_TAG_ROOT);
+    Some(Facet::from_path(root.to_path().into_iter().chain(steps)).to_string())
+}
+
+/// Whether an encoded facet of the tags field is the tag of a Gmail label.
+pub fn is_label_facet(encoded: &str) -> bool {
+    Facet::from_encoded(encoded.as_bytes().to_vec())
+        .is_ok_and(|facet| Facet::from(LABEL_TAG_ROOT).is_prefix_of(&facet))
+}
+
+/// Reads the untagged responses to a `UID FETCH` of `GMAIL_FETCH_ITEMS`, by UID.
+/// Other responses are skipped.
+pub fn parse_gmail_fetches<'a>(
+    responses: impl IntoIterator<Item = &'a Response<'a>>,
+) -> HashMap<u32, GmailAttributes> {
+    let mut result = HashMap::new();
+    for response in responses {
+        let Response::Fetch(_, values) = response else {
+            continue;
+        };
+        let mut uid = None;
+        let mut attributes = GmailAttributes::default();
+        for value in values {
+            match value {
+                AttributeValue::Uid(value) => uid = Some(*value),
+                AttributeValue::GmailMsgId(id) => attributes.message_id = Some(*id),
+                AttributeValue::GmailThrId(id) => attributes.thread_id = Some(*id),
+                AttributeValue::GmailLabels(labels) => {
+                    attributes.labels = labels.iter().map(|l| l.to_string()).collect()
+                }
+                _ => {}
+            }
+        }
+        if let Some(uid) = uid {
+            result.insert(uid, attributes);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_label_facet, label_tag, parse_gmail_fetches};
+    use imap_proto::parser::parse_response;
+    use tantivy::schema::Facet;
+
+    #[test]
+    fn test_parse_gmail_fetches() {
+        let data = b"* 1 FETCH (X-GM-THRID 1278455344230334865 X-GM-MSGID 1278455344230334866 \
+                     X-GM-LABELS (\\Inbox \\Important Work/Projects \"Muy Importante\") UID 7)\r\n\
+                     * 2 FETCH (UID 9 X-GM-MSGID 42 X-GM-THRID 41 X-GM-LABELS ())\r\n\
+                     A4 OK Success\r\n";
+        let mut rest = &data[..];
+        let mut responses = Vec::new();
+        while !rest.is_empty() {
+            let (next, response) = parse_response(rest).unwrap();
+            responses.push(response);
+            rest = next;
+        }
+        let fetches = parse_gmail_fetches(&responses);
+        assert_eq!(fetches.len(), 2);
+        let first = &fetches[&7];
+        assert_eq!(first.message_id, Some(1278455344230334866));
+        assert_eq!(first.thread_id, Some(1278455344230334865));
+        assert_eq!(
+            first.tags(),
+            vec![
+                "/gmail/Inbox",
+                "/gmail/Important",
+                "/gmail/Work/Projects",
+                "/gmail/Muy Importante"
+            ]
+        );
+        assert_eq!(fetches[&9].thread_id, Some(41));
+        assert!(fetches[&9].tags().is_empty());
+
+        assert_eq!(label_tag("&AN8-ber").as_deref(), Some("/gmail/ßber"));
+        assert!(is_label_facet(Facet::from("/gmail/Inbox").encoded_str()));
+        assert!(!is_label_facet(Facet::from("/gmail").encoded_str()));
+        assert!(!is_label_facet(Facet::from("/case/42").encoded_str()));
+    }
+}
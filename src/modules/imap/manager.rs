@@ -167,6 +167,17 @@ impl ImapConnectionManager {
                             raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed)
                         })?;
                 }
+
+                // Only allowed before a mailbox is selected, so once per connection.
+                // Lets `ImapExecutor::fetch_changes` ask for the expunged UIDs.
+                if capabilities.has_str("QRESYNC") {
+                    session
+                        .run_command_and_check_ok("ENABLE QRESYNC")
+                        .await
+                        .map_err(|e| {
+                            raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed)
+                        })?;
+                }
             }
             Err(error) => {
                 error!("Failed to fetch IMAP capabilities: {:#?}", error);
@@ -20,6 +20,7 @@
 pub mod capabilities;
 pub mod client;
 pub mod executor;
+pub mod gmail;
 pub mod manager;
 pub mod oauth2;
 pub mod pool;
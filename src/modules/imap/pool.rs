@@ -16,6 +16,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::modules::account::migration::AccountModel;
+use crate::modules::cache::imap::idle::MAX_IDLE_FOLDERS;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::{BichonError, BichonResult};
 use crate::modules::imap::{manager::ImapConnectionManager, session::SessionStream};
@@ -44,16 +46,51 @@ impl bb8::ManageConnection for ImapConnectionManager {
     }
 }
 
+/// Connections an account may hold on the server, shared between the pool and
+/// the IDLE watchers, which each keep a connection of their own.
+const MAX_CONNECTIONS: u32 = 10;
+
 pub async fn build_imap_pool(account_id: u64) -> BichonResult<Pool<ImapConnectionManager>> {
+    let account = AccountModel::get(account_id).await?;
     let manager = ImapConnectionManager::new(account_id);
     let pool = Pool::builder()
         .connection_timeout(Duration::from_secs(30))
         //.idle_timeout(Duration::from_secs(120))
         .retry_connection(true)
-        .max_size(10)
+        .max_size(pool_size(&account))
         .test_on_check_out(true)
         .build(manager)
         .await?;
 
     Ok(pool)
 }
+
+/// The connections left to the pool after those of the IDLE watchers.
+fn pool_size(account: &AccountModel) -> u32 {
+    let idle_connections = account
+        .idle_folders
+        .as_ref()
+        .map_or(0, |folders| folders.len().min(MAX_IDLE_FOLDERS)) as u32;
+    MAX_CONNECTIONS - idle_connections
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pool_size, MAX_CONNECTIONS};
+    use crate::modules::account::migration::AccountModel;
+    use crate::modules::cache::imap::idle::MAX_IDLE_FOLDERS;
+
+    #[test]
+    fn test_pool_size() {
+        let account = |folders: Option<usize>| AccountModel {
+            idle_folders: folders.map(|n| (0..n).map(|i| format!("Folder {i}")).collect()),
+            ..Default::default()
+        };
+        assert_eq!(pool_size(&account(None)), MAX_CONNECTIONS);
+        assert_eq!(pool_size(&account(Some(1))), MAX_CONNECTIONS - 1);
+        // Folders over the limit are not watched, and keep no connection.
+        let smallest = pool_size(&account(Some(MAX_IDLE_FOLDERS + 5)));
+        assert_eq!(smallest, MAX_CONNECTIONS - MAX_IDLE_FOLDERS as u32);
+        assert!(smallest >= 1);
+    }
+}
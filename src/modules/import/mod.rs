@@ -1,18 +1,18 @@
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
-use tantivy::doc;
 
 use crate::{
     base64_decode_url_safe,
     modules::{
         account::migration::{AccountModel, AccountType},
+        blob::manager::EML_BLOB_MANAGER,
         cache::imap::mailbox::{Attribute, AttributeEnum, MailBox},
-        envelope::extractor::extract_envelope_from_eml,
-        error::{code::ErrorCode, BichonResult},
-        indexer::{
-            manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
-            schema::SchemaTools,
+        envelope::{
+            extractor::extract_envelope_from_eml, fingerprint::resolve_collision,
+            location::MessageLocation, thread::assign_thread,
         },
+        error::{code::ErrorCode, BichonResult},
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
         utils::create_hash,
     },
     raise_error,
@@ -85,6 +85,7 @@ impl ImportEmls {
                     unseen: None,
                     uid_next: None,
                     uid_validity: None,
+                    highest_modseq: None,
                 };
                 let mailbox_id = mailbox.id;
                 // Upsert the mailbox, creating it if it doesn't exist
@@ -93,7 +94,6 @@ impl ImportEmls {
             },
         };
 
-        let fields = SchemaTools::eml_fields();
         let account_id = account.id;
         let mut success_count = 0;
         let mut failed_details: Vec<FailedEmlDetail> = Vec::new(); // Store failure details
@@ -115,7 +115,7 @@ impl ImportEmls {
                 }
             };
 
-            let envelope = match extract_envelope_from_eml(&decoded, account_id, mailbox_id) {
+            let mut envelope = match extract_envelope_from_eml(&decoded, account_id, mailbox_id) {
                 Ok(env) => env,
                 Err(e) => {
                     let error_msg = format!(
@@ -131,22 +131,56 @@ impl ImportEmls {
                 }
             };
 
+            if let Err(e) = resolve_collision(&mut envelope).await {
+                let error_msg = format!(
+                    "Failed to check the Message-ID of EML at index {}: {:?}",
+                    index, e
+                );
+                tracing::error!("{}", error_msg);
+                failed_details.push(FailedEmlDetail {
+                    index,
+                    error_message: error_msg,
+                });
+                continue;
+            }
+
+            if let Err(e) = assign_thread(&mut envelope).await {
+                let error_msg = format!("Failed to thread EML at index {}: {:?}", index, e);
+                tracing::error!("{}", error_msg);
+                failed_details.push(FailedEmlDetail {
+                    index,
+                    error_message: error_msg,
+                });
+                continue;
+            }
+
+            if let Err(e) = EML_BLOB_MANAGER
+                .add(account_id, mailbox_id, envelope.id, &decoded)
+                .await
+            {
+                let error_msg = format!("Failed to store EML at index {}: {:?}", index, e);
+                tracing::error!("{}", error_msg);
+                failed_details.push(FailedEmlDetail {
+                    index,
+                    error_message: error_msg,
+                });
+                continue;
+            }
+
+            if let Err(e) = MessageLocation::save(&mut envelope).await {
+                let error_msg = format!("Failed to record EML at index {}: {:?}", index, e);
+                tracing::error!("{}", error_msg);
+                failed_details.push(FailedEmlDetail {
+                    index,
+                    error_message: error_msg,
+                });
+                continue;
+            }
+
             ENVELOPE_INDEX_MANAGER
                 .add_document(envelope.id, envelope.to_document(mailbox_id).unwrap())
                 .await;
 
-            EML_INDEX_MANAGER
-                .add_document(
-                    envelope.id,
-                    doc!(
-                        fields.f_id => envelope.id,
-                        fields.f_account_id => account_id,
-                        fields.f_mailbox_id => mailbox_id,
-                        fields.f_eml => decoded
-                    ),
-                )
-                .await;
-
             success_count += 1;
         }
 
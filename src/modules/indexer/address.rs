@@ -0,0 +1,263 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use tantivy::query::{BooleanQuery, FuzzyTermQuery, Occur, Query, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, Value};
+use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+use tantivy::{TantivyDocument, Term};
+
+use crate::modules::common::Addr;
+use crate::modules::indexer::schema::SchemaTools;
+use crate::modules::message::search::AddressMatch;
+
+/// Tokenizer of the `*_name_addr` fields, which hold `Name <address>` values.
+pub const ADDRESS_TOKENIZER: &str = "bichon_address";
+
+/// Formats an address with its display name the way it is stored in the
+/// `*_name_addr` fields.
+pub fn name_addr(name: Option<&String>, address: &str) -> String {
+    Addr {
+        name: name.cloned(),
+        address: Some(address.to_string()),
+    }
+    .to_string()
+}
+
+/// Splits a value of the `*_name_addr` fields into display name and address.
+pub fn parse_name_addr(value: &str) -> (Option<&str>, &str) {
+    match value.rfind('<') {
+        Some(start) if value.ends_with('>') => {
+            let name = value[..start].trim();
+            let address = value[start + 1..value.len() - 1].trim();
+            ((!name.is_empty()).then_some(name), address)
+        }
+        _ => (None, value.trim()),
+    }
+}
+
+/// Fills the `*_name_addr` fields of a document indexed before they existed
+/// from its bare addresses. Display names are only known after a reindex.
+pub fn add_missing_name_addr_fields(doc: &mut TantivyDocument) {
+    let f = SchemaTools::envelope_fields();
+    for (field, name_addr_field) in [
+        (f.f_from, f.f_from_name_addr),
+        (f.f_to, f.f_to_name_addr),
+        (f.f_cc, f.f_cc_name_addr),
+        (f.f_bcc, f.f_bcc_name_addr),
+    ] {
+        if doc.get_first(name_addr_field).is_some() {
+            continue;
+        }
+        let addresses: Vec<String> = doc
+            .get_all(field)
+            .filter_map(|v| v.as_str())
+            .map(|address| name_addr(None, address))
+            .collect();
+        for address in addresses {
+            doc.add_text(name_addr_field, address);
+        }
+    }
+}
+
+/// Builds the query for the `from`, `to`, `cc` and `bcc` filters on a
+/// `*_name_addr` field. Returns `None` if `value` has nothing to search for.
+///
+/// A value with an `@` is matched as a whole address, or as a domain when it
+/// starts with `@`. Other values are split into words, each of which has to
+/// match a word of the display name or of the local part.
+pub fn address_query(field: Field, value: &str, mode: AddressMatch) -> Option<Box<dyn Query>> {
+    let value = value.trim().to_lowercase();
+    let terms = match mode {
+        AddressMatch::Domain => {
+            let domain = value.rsplit('@').next().unwrap_or_default();
+            if domain.is_empty() {
+                return None;
+            }
+            vec![format!("@{}", domain)]
+        }
+        _ => query_terms(&value),
+    };
+    let mut clauses: Vec<(Occur, Box<dyn Query>)> = terms
+        .into_iter()
+        .map(|text| {
+            let term = Term::from_field_text(field, &text);
+            let query: Box<dyn Query> = match mode {
+                AddressMatch::Exact | AddressMatch::Domain => {
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+                }
+                AddressMatch::Prefix => Box::new(FuzzyTermQuery::new_prefix(term, 0, true)),
+                AddressMatch::Fuzzy => {
+                    Box::new(FuzzyTermQuery::new(term, fuzzy_distance(&text), true))
+                }
+            };
+            (Occur::Must, query)
+        })
+        .collect();
+    match clauses.len() {
+        0 => None,
+        1 => clauses.pop().map(|(_, query)| query),
+        _ => Some(Box::new(BooleanQuery::new(clauses))),
+    }
+}
+
+/// Typos allowed by a fuzzy match, fewer for short terms which would otherwise
+/// match almost anything.
+fn fuzzy_distance(term: &str) -> u8 {
+    match term.chars().count() {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+fn query_terms(value: &str) -> Vec<String> {
+    if value.contains('@') && !value.contains(char::is_whitespace) {
+        return vec![value.trim_matches(|c| c == '<' || c == '>').to_string()];
+    }
+    words(value).map(String::from).collect()
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+/// Tokens of one `Name <address>` value:
+/// - the words of the display name,
+/// - the address,
+/// - its local part and the words of the local part,
+/// - `@domain` for the domain and each parent domain, so `@example.com` also
+///   matches `mail.example.com`.
+///
+/// All tokens are lowercased. Positions are not indexed, so every token spans
+/// the whole value.
+fn address_tokens(text: &str) -> Vec<String> {
+    let text = text.to_lowercase();
+    let (name, address) = match parse_name_addr(&text) {
+        (None, address) if address.contains('@') && !address.contains(char::is_whitespace) => {
+            (None, address)
+        }
+        (None, name) => (Some(name), ""),
+        parsed => parsed,
+    };
+    let mut tokens: Vec<String> = words(name.unwrap_or_default()).map(String::from).collect();
+    if let Some((local, domain)) = address.rsplit_once('@') {
+        tokens.push(address.to_string());
+        if !local.is_empty() {
+            tokens.push(local.to_string());
+            tokens.extend(words(local).filter(|w| *w != local).map(String::from));
+        }
+        if domain.is_empty() {
+            tokens.sort();
+            tokens.dedup();
+            return tokens;
+        }
+        let labels: Vec<&str> = domain.split('.').collect();
+        for start in 0..labels.len().saturating_sub(1).max(1) {
+            tokens.push(format!("@{}", labels[start..].join(".")));
+        }
+    } else if !address.is_empty() {
+        tokens.push(address.to_string());
+    }
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AddressTokenizer;
+
+pub struct AddressTokenStream {
+    tokens: std::vec::IntoIter<Token>,
+    token: Token,
+}
+
+impl Tokenizer for AddressTokenizer {
+    type TokenStream<'a> = AddressTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let tokens: Vec<Token> = address_tokens(text)
+            .into_iter()
+            .enumerate()
+            .map(|(position, token)| Token {
+                offset_from: 0,
+                offset_to: text.len(),
+                position,
+                text: token,
+                position_length: 1,
+            })
+            .collect();
+        AddressTokenStream {
+            tokens: tokens.into_iter(),
+            token: Token::default(),
+        }
+    }
+}
+
+impl TokenStream for AddressTokenStream {
+    fn advance(&mut self) -> bool {
+        match self.tokens.next() {
+            Some(token) => {
+                self.token = token;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_address_tokens() {
+        assert_eq!(
+            address_tokens("John Smith <John.Smith@Mail.Vendor.com>"),
+            vec![
+                "@mail.vendor.com",
+                "@vendor.com",
+                "john",
+                "john.smith",
+                "john.smith@mail.vendor.com",
+                "smith",
+            ]
+        );
+        assert_eq!(
+            parse_name_addr("John Smith <js@x.com>"),
+            (Some("John Smith"), "js@x.com")
+        );
+        assert_eq!(parse_name_addr("<js@x.com>"), (None, "js@x.com"));
+        assert_eq!(
+            address_tokens("<alice@x.com>"),
+            vec!["@x.com", "alice", "alice@x.com"]
+        );
+        assert_eq!(
+            address_tokens("bob@localhost"),
+            vec!["@localhost", "bob", "bob@localhost"]
+        );
+    }
+}
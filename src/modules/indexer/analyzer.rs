@@ -0,0 +1,421 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::sync::LazyLock;
+
+use clap::ValueEnum;
+use tantivy::schema::Value;
+use tantivy::tokenizer::{
+    Language, LowerCaser, RawTokenizer, RemoveLongFilter, Stemmer, TextAnalyzer, Token,
+    TokenStream, Tokenizer,
+};
+use tantivy::{Index, TantivyDocument};
+use whatlang::{Detector, Lang};
+
+use crate::modules::indexer::address::{AddressTokenizer, ADDRESS_TOKENIZER};
+use crate::modules::indexer::schema::SchemaTools;
+use crate::modules::settings::cli::SETTINGS;
+
+/// Tokenizer of the unstemmed full-text fields (subject, body, attachment text).
+pub const TEXT_TOKENIZER: &str = "bichon_text";
+/// Tokenizer of exact-match fields that ignore case, the whole value is one token.
+pub const KEYWORD_TOKENIZER: &str = "bichon_keyword";
+
+/// Tokens longer than this are dropped, same as Tantivy's default analyzer.
+const MAX_TOKEN_LENGTH: usize = 40;
+/// Language detection only looks at the beginning of a message.
+const DETECTION_SAMPLE_SIZE: usize = 4096;
+const MIN_DETECTION_CONFIDENCE: f64 = 0.5;
+
+/// Only the stemmed languages are candidates, which makes detection on short
+/// messages more reliable. Text in other scripts is detected as nothing.
+static LANGUAGE_DETECTOR: LazyLock<Detector> = LazyLock::new(|| {
+    Detector::with_allowlist(
+        StemLanguage::value_variants()
+            .iter()
+            .map(|language| language.detected_language())
+            .collect(),
+    )
+});
+
+/// Languages that get a stemmed copy of the subject and body.
+///
+/// Every language has its own field in the schema, so enabling or disabling a
+/// language with `--bichon-stem-languages` does not change the index layout.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, ValueEnum)]
+pub enum StemLanguage {
+    #[clap(name = "da")]
+    Danish,
+    #[clap(name = "de")]
+    German,
+    #[clap(name = "el")]
+    Greek,
+    #[clap(name = "en")]
+    English,
+    #[clap(name = "es")]
+    Spanish,
+    #[clap(name = "fi")]
+    Finnish,
+    #[clap(name = "fr")]
+    French,
+    #[clap(name = "hu")]
+    Hungarian,
+    #[clap(name = "it")]
+    Italian,
+    #[clap(name = "nl")]
+    Dutch,
+    #[clap(name = "no")]
+    Norwegian,
+    #[clap(name = "pt")]
+    Portuguese,
+    #[clap(name = "ro")]
+    Romanian,
+    #[clap(name = "ru")]
+    Russian,
+    #[clap(name = "sv")]
+    Swedish,
+    #[clap(name = "tr")]
+    Turkish,
+}
+
+impl StemLanguage {
+    pub fn code(self) -> &'static str {
+        match self {
+            StemLanguage::Danish => "da",
+            StemLanguage::German => "de",
+            StemLanguage::Greek => "el",
+            StemLanguage::English => "en",
+            StemLanguage::Spanish => "es",
+            StemLanguage::Finnish => "fi",
+            StemLanguage::French => "fr",
+            StemLanguage::Hungarian => "hu",
+            StemLanguage::Italian => "it",
+            StemLanguage::Dutch => "nl",
+            StemLanguage::Norwegian => "no",
+            StemLanguage::Portuguese => "pt",
+            StemLanguage::Romanian => "ro",
+            StemLanguage::Russian => "ru",
+            StemLanguage::Swedish => "sv",
+            StemLanguage::Turkish => "tr",
+        }
+    }
+
+    /// Name of the stemmed field for this language in the envelope schema.
+    pub fn field_name(self) -> String {
+        format!("stem_{}", self.code())
+    }
+
+    pub fn tokenizer_name(self) -> String {
+        format!("bichon_stem_{}", self.code())
+    }
+
+    fn stemmer_language(self) -> Language {
+        match self {
+            StemLanguage::Danish => Language::Danish,
+            StemLanguage::German => Language::German,
+            StemLanguage::Greek => Language::Greek,
+            StemLanguage::English => Language::English,
+            StemLanguage::Spanish => Language::Spanish,
+            StemLanguage::Finnish => Language::Finnish,
+            StemLanguage::French => Language::French,
+            StemLanguage::Hungarian => Language::Hungarian,
+            StemLanguage::Italian => Language::Italian,
+            StemLanguage::Dutch => Language::Dutch,
+            StemLanguage::Norwegian => Language::Norwegian,
+            StemLanguage::Portuguese => Language::Portuguese,
+            StemLanguage::Romanian => Language::Romanian,
+            StemLanguage::Russian => Language::Russian,
+            StemLanguage::Swedish => Language::Swedish,
+            StemLanguage::Turkish => Language::Turkish,
+        }
+    }
+
+    fn detected_language(self) -> Lang {
+        match self {
+            StemLanguage::Danish => Lang::Dan,
+            StemLanguage::German => Lang::Deu,
+            StemLanguage::Greek => Lang::Ell,
+            StemLanguage::English => Lang::Eng,
+            StemLanguage::Spanish => Lang::Spa,
+            StemLanguage::Finnish => Lang::Fin,
+            StemLanguage::French => Lang::Fra,
+            StemLanguage::Hungarian => Lang::Hun,
+            StemLanguage::Italian => Lang::Ita,
+            StemLanguage::Dutch => Lang::Nld,
+            StemLanguage::Norwegian => Lang::Nob,
+            StemLanguage::Portuguese => Lang::Por,
+            StemLanguage::Romanian => Lang::Ron,
+            StemLanguage::Russian => Lang::Rus,
+            StemLanguage::Swedish => Lang::Swe,
+            StemLanguage::Turkish => Lang::Tur,
+        }
+    }
+
+    /// Languages enabled for stemming in the settings.
+    pub fn enabled() -> &'static [StemLanguage] {
+        &SETTINGS.bichon_stem_languages
+    }
+}
+
+/// Registers the analyzers referenced by the envelope schema on `index`.
+///
+/// Tokenizers are not persisted with the index, this must be called every time
+/// an envelope index is opened or created, before writing or parsing queries.
+pub fn register_analyzers(index: &Index) {
+    let tokenizers = index.tokenizers();
+    tokenizers.register(TEXT_TOKENIZER, text_analyzer());
+    tokenizers.register(
+        KEYWORD_TOKENIZER,
+        TextAnalyzer::builder(RawTokenizer::default())
+            .filter(LowerCaser)
+            .build(),
+    );
+    tokenizers.register(ADDRESS_TOKENIZER, AddressTokenizer);
+    for language in StemLanguage::value_variants() {
+        tokenizers.register(
+            &language.tokenizer_name(),
+            TextAnalyzer::builder(CjkBigramTokenizer)
+                .filter(RemoveLongFilter::limit(MAX_TOKEN_LENGTH))
+                .filter(LowerCaser)
+                .filter(Stemmer::new(language.stemmer_language()))
+                .build(),
+        );
+    }
+}
+
+/// The analyzer registered as `TEXT_TOKENIZER`.
+pub fn text_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(CjkBigramTokenizer)
+        .filter(RemoveLongFilter::limit(MAX_TOKEN_LENGTH))
+        .filter(LowerCaser)
+        .build()
+}
+
+/// Detects the language of `text`, if it is one of the stemmed languages.
+pub fn detect_language(text: &str) -> Option<StemLanguage> {
+    let mut end = text.len().min(DETECTION_SAMPLE_SIZE);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let info = LANGUAGE_DETECTOR.detect(&text[..end])?;
+    if info.confidence() < MIN_DETECTION_CONFIDENCE {
+        return None;
+    }
+    StemLanguage::value_variants()
+        .iter()
+        .find(|language| language.detected_language() == info.lang())
+        .copied()
+}
+
+/// Copies the subject and body of `doc` into the stemmed field of their
+/// detected language, if that language is enabled.
+///
+/// Stemmed fields are not stored, so this also has to run when a document is
+/// rebuilt from the doc store.
+pub fn add_stemmed_fields(doc: &mut TantivyDocument) {
+    let fields = SchemaTools::envelope_fields();
+    let value = |field| {
+        doc.get_first(field)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let subject = value(fields.f_subject);
+    let text = value(fields.f_text);
+    let Some(language) = detect_language(&format!("{}\n{}", subject, text)) else {
+        return;
+    };
+    if !StemLanguage::enabled().contains(&language) {
+        return;
+    }
+    let field = fields.stemmed_field(language);
+    doc.add_text(field, subject);
+    doc.add_text(field, text);
+}
+
+/// Splits text on non-alphanumeric characters like Tantivy's simple tokenizer,
+/// but emits overlapping bigrams for runs of Chinese, Japanese and Korean
+/// characters, which are not separated by whitespace.
+///
+/// A query runs through the same tokenizer, so a CJK query term becomes a
+/// phrase of bigrams and matches wherever the term occurs as a substring.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CjkBigramTokenizer;
+
+pub struct CjkBigramTokenStream {
+    tokens: std::vec::IntoIter<Token>,
+    token: Token,
+}
+
+impl Tokenizer for CjkBigramTokenizer {
+    type TokenStream<'a> = CjkBigramTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        CjkBigramTokenStream {
+            tokens: tokenize(text).into_iter(),
+            token: Token::default(),
+        }
+    }
+}
+
+impl TokenStream for CjkBigramTokenStream {
+    fn advance(&mut self) -> bool {
+        match self.tokens.next() {
+            Some(token) => {
+                self.token = token;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{1100}'..='\u{11FF}'     // Hangul Jamo
+        | '\u{3040}'..='\u{30FF}'   // Hiragana, Katakana
+        | '\u{3130}'..='\u{318F}'   // Hangul Compatibility Jamo
+        | '\u{31F0}'..='\u{31FF}'   // Katakana Phonetic Extensions
+        | '\u{3400}'..='\u{4DBF}'   // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}'   // Hangul Syllables
+        | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+        | '\u{FF66}'..='\u{FF9F}'   // Halfwidth Katakana
+        | '\u{20000}'..='\u{2FA1F}' // CJK Extensions B-F, Compatibility Supplement
+    )
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let push = |tokens: &mut Vec<Token>, from: usize, to: usize| {
+        let position = tokens.len();
+        tokens.push(Token {
+            offset_from: from,
+            offset_to: to,
+            position,
+            text: text[from..to].to_string(),
+            position_length: 1,
+        });
+    };
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if is_cjk(c) {
+            let mut run = vec![(start, c.len_utf8())];
+            while let Some(&(offset, next)) = chars.peek() {
+                if !is_cjk(next) {
+                    break;
+                }
+                run.push((offset, next.len_utf8()));
+                chars.next();
+            }
+            if run.len() == 1 {
+                push(&mut tokens, start, start + run[0].1);
+            } else {
+                for pair in run.windows(2) {
+                    push(&mut tokens, pair[0].0, pair[1].0 + pair[1].1);
+                }
+            }
+        } else if c.is_alphanumeric() {
+            let mut end = start + c.len_utf8();
+            while let Some(&(offset, next)) = chars.peek() {
+                if !next.is_alphanumeric() || is_cjk(next) {
+                    break;
+                }
+                end = offset + next.len_utf8();
+                chars.next();
+            }
+            push(&mut tokens, start, end);
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tantivy::collector::Count;
+    use tantivy::query::QueryParser;
+
+    fn token_texts(text: &str) -> Vec<String> {
+        tokenize(text).into_iter().map(|t| t.text).collect()
+    }
+
+    #[test]
+    fn test_cjk_runs_become_bigrams() {
+        assert_eq!(
+            token_texts("Re: 中华人民共和国 report-2024 日"),
+            vec![
+                "Re", "中华", "华人", "人民", "民共", "共和", "和国", "report", "2024", "日"
+            ]
+        );
+        assert_eq!(
+            token_texts("東京タワーへ"),
+            vec!["東京", "京タ", "タワ", "ワー", "ーへ"]
+        );
+    }
+
+    #[test]
+    fn test_detects_message_language() {
+        assert_eq!(
+            detect_language(
+                "The quarterly report is attached, please review the numbers before Friday."
+            ),
+            Some(StemLanguage::English)
+        );
+        assert_eq!(
+            detect_language("Bitte prüfen Sie den beigefügten Vertrag bis Ende der Woche."),
+            Some(StemLanguage::German)
+        );
+        assert_eq!(detect_language("中华人民共和国"), None);
+    }
+
+    #[test]
+    fn test_query_parser_uses_the_same_analyzers() {
+        let (schema, fields) = SchemaTools::create_envelope_schema();
+        let index = Index::create_in_ram(schema);
+        register_analyzers(&index);
+        let mut writer = index.writer::<TantivyDocument>(15_000_000).unwrap();
+        let english = fields.stemmed_field(StemLanguage::English);
+        let mut doc = TantivyDocument::new();
+        doc.add_text(fields.f_subject, "关于合同条款的问题");
+        doc.add_text(english, "Contracts were signed");
+        writer.add_document(doc).unwrap();
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let parser = QueryParser::for_index(&index, vec![fields.f_subject, english]);
+        let count = |query: &str| {
+            searcher
+                .search(&parser.parse_query(query).unwrap(), &Count)
+                .unwrap()
+        };
+        assert_eq!(count("合同"), 1);
+        assert_eq!(count("合同条款"), 1);
+        assert_eq!(count("条同"), 0);
+        assert_eq!(count("contract sign"), 1);
+    }
+}
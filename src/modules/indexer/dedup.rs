@@ -0,0 +1,294 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
+use crate::modules::envelope::extractor::{extract_envelope_from_eml, is_random_message_id};
+use crate::modules::envelope::location::{
+    deleted_on_server_at, flags_in, MailboxLocation, MessageLocation,
+};
+use crate::modules::envelope::thread::{assign_thread, link_thread};
+use crate::modules::error::{code::ErrorCode, BichonResult};
+use crate::modules::indexer::envelope::Envelope;
+use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
+use crate::modules::indexer::reindex::{ReindexState, REINDEX_JOB};
+use crate::modules::indexer::schema::SchemaTools;
+use crate::{raise_error, utc_now};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tantivy::collector::DocSetCollector;
+use tantivy::query::RegexQuery;
+use tantivy::schema::Value;
+use tantivy::TantivyDocument;
+use tracing::{error, info, warn};
+
+/// Matches the random Message-IDs given to messages without one, see
+/// `is_random_message_id`.
+const RANDOM_MESSAGE_ID_PATTERN: &str = r"<[0-9a-f]{16}\.[0-9]+\.[0-9]+@bichon>";
+
+pub static DEDUP_JOB: LazyLock<DedupJob> = LazyLock::new(DedupJob::default);
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct DedupStatus {
+    pub state: ReindexState,
+    /// Number of emails with a random Message-ID when the job started.
+    pub total: u64,
+    /// Number of those emails processed so far.
+    pub processed: u64,
+    /// Emails that were a copy of another archived email and were merged into it.
+    pub merged: u64,
+    /// Emails that could not be processed; they are left as they are.
+    pub failed: u64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// Moves emails archived without a Message-ID from the random Message-ID they
+/// were given to their synthetic one, merging the copies of the same email.
+///
+/// Each copy was archived as an envelope of its own. The mailboxes and tags of
+/// all copies end up on one envelope, the others are deleted. Running the job
+/// again only picks up what was not processed yet.
+#[derive(Default)]
+pub struct DedupJob {
+    status: Mutex<DedupStatus>,
+    cancelled: AtomicBool,
+}
+
+impl DedupJob {
+    pub fn status(&self) -> DedupStatus {
+        self.status
+            .lock()
+            .expect("dedup status lock poisoned")
+            .clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status().state == ReindexState::Running
+    }
+
+    pub fn start(&'static self) -> BichonResult<DedupStatus> {
+        if REINDEX_JOB.status().state == ReindexState::Running {
+            return Err(raise_error!(
+                "Cannot deduplicate emails while a reindex is running.".into(),
+                ErrorCode::AlreadyExists
+            ));
+        }
+        {
+            let mut status = self.status.lock().expect("dedup status lock poisoned");
+            if status.state == ReindexState::Running {
+                return Err(raise_error!(
+                    "Deduplication is already running.".into(),
+                    ErrorCode::AlreadyExists
+                ));
+            }
+            *status = DedupStatus {
+                state: ReindexState::Running,
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        self.cancelled.store(false, Ordering::SeqCst);
+        tokio::spawn(async move {
+            let result = self.run().await;
+            let mut status = self.status.lock().expect("dedup status lock poisoned");
+            status.finished_at = Some(utc_now!());
+            match result {
+                Ok(true) => {
+                    info!(
+                        "Deduplication completed: {} emails processed, {} merged, {} failed",
+                        status.processed, status.merged, status.failed
+                    );
+                    status.state = ReindexState::Completed;
+                }
+                Ok(false) => {
+                    info!("Deduplication cancelled after {} emails", status.processed);
+                    status.state = ReindexState::Cancelled;
+                }
+                Err(e) => {
+                    error!("Deduplication failed: {:#?}", e);
+                    status.state = ReindexState::Failed;
+                    status.error = Some(format!("{:#?}", e));
+                }
+            }
+        });
+        Ok(self.status())
+    }
+
+    pub fn cancel(&self) -> BichonResult<DedupStatus> {
+        if !self.is_running() {
+            return Err(raise_error!(
+                "No deduplication is running.".into(),
+                ErrorCode::ResourceNotFound
+            ));
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        Ok(self.status())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut DedupStatus)) {
+        f(&mut self.status.lock().expect("dedup status lock poisoned"));
+    }
+
+    /// Returns `false` if the job was cancelled.
+    async fn run(&self) -> BichonResult<bool> {
+        let candidates = find_random_message_ids()?;
+        self.update(|status| status.total = candidates.len() as u64);
+        info!(
+            "Moving {} emails to synthetic Message-IDs",
+            candidates.len()
+        );
+
+        for (account_id, envelope_id) in candidates {
+            if self.cancelled.load(Ordering::SeqCst) {
+                return Ok(false);
+            }
+            match merge_envelope(account_id, envelope_id).await {
+                Ok(merged) => self.update(|status| {
+                    status.processed += 1;
+                    if merged {
+                        status.merged += 1;
+                    }
+                }),
+                Err(e) => {
+                    warn!(
+                        "Failed to deduplicate email {} of account {}: {:#?}",
+                        envelope_id, account_id, e
+                    );
+                    self.update(|status| {
+                        status.processed += 1;
+                        status.failed += 1;
+                    });
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// `(account_id, envelope_id)` of the envelopes with a random Message-ID.
+fn find_random_message_ids() -> BichonResult<Vec<(u64, u64)>> {
+    let fields = SchemaTools::envelope_fields();
+    let searcher = ENVELOPE_INDEX_MANAGER.create_searcher()?;
+    let query = RegexQuery::from_pattern(RANDOM_MESSAGE_ID_PATTERN, fields.f_message_id)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let addresses = searcher
+        .search(&query, &DocSetCollector)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let mut candidates = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let doc: TantivyDocument = searcher
+            .doc(address)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let value = |field| doc.get_first(field).and_then(|v| v.as_u64());
+        if let (Some(account_id), Some(envelope_id)) =
+            (value(fields.f_account_id), value(fields.f_id))
+        {
+            candidates.push((account_id, envelope_id));
+        }
+    }
+    Ok(candidates)
+}
+
+/// Moves the envelope to its synthetic Message-ID. Returns whether an envelope
+/// with that Message-ID was already archived.
+async fn merge_envelope(account_id: u64, envelope_id: u64) -> BichonResult<bool> {
+    let Some(old) = ENVELOPE_INDEX_MANAGER
+        .get_envelope_by_id(account_id, envelope_id)
+        .await?
+    else {
+        // Deleted since the job started.
+        return Ok(false);
+    };
+    if !is_random_message_id(&old.message_id) {
+        return Ok(false);
+    }
+    let eml = EML_BLOB_MANAGER
+        .get(account_id, envelope_id)
+        .await?
+        .ok_or_else(|| {
+            raise_error!(
+                format!(
+                    "Email record not found: account_id={} id={}",
+                    account_id, envelope_id
+                ),
+                ErrorCode::ResourceNotFound
+            )
+        })?;
+    let mut envelope = extract_envelope_from_eml(&eml, account_id, old.mailbox_id)?;
+    if envelope.id == envelope_id {
+        return Ok(false);
+    }
+
+    let existing = ENVELOPE_INDEX_MANAGER
+        .get_envelope_by_id(account_id, envelope.id)
+        .await?;
+    // The envelope that was already there keeps its mailbox and UID.
+    let primary = existing.as_ref().unwrap_or(&old);
+    envelope.mailbox_id = primary.mailbox_id;
+    envelope.uid = primary.uid;
+    envelope.internal_date = primary.internal_date;
+    envelope.size = primary.size;
+    envelope.gmail_message_id = primary.gmail_message_id;
+    let tags: BTreeSet<String> = [&old, primary]
+        .into_iter()
+        .flat_map(|e| e.tags.clone().unwrap_or_default())
+        .collect();
+
+    if existing.is_none() {
+        EML_BLOB_MANAGER
+            .add(account_id, envelope.mailbox_id, envelope.id, &eml)
+            .await?;
+    }
+    match primary.gmail_message_id {
+        Some(_) => {
+            link_thread(&envelope).await?;
+            envelope.thread_id = primary.thread_id;
+        }
+        None => assign_thread(&mut envelope).await?,
+    }
+    let locations = MessageLocation::move_to(account_id, envelope_id, envelope.id).await?;
+    envelope.flags = flags_in(&locations, envelope.mailbox_id);
+    envelope.deleted_on_server_at = deleted_on_server_at(&locations);
+    envelope.locations = locations.iter().map(MailboxLocation::from).collect();
+    ENVELOPE_INDEX_MANAGER
+        .add_document(envelope.id, to_document(&envelope, &tags)?)
+        .await;
+
+    let deletes = HashMap::from([(account_id, vec![envelope_id])]);
+    EML_BLOB_MANAGER
+        .delete_email_multi_account(&deletes)
+        .await?;
+    ENVELOPE_INDEX_MANAGER
+        .delete_envelopes_multi_account(&deletes)
+        .await?;
+    Ok(existing.is_some())
+}
+
+fn to_document(envelope: &Envelope, tags: &BTreeSet<String>) -> BichonResult<TantivyDocument> {
+    let f_tags = SchemaTools::envelope_fields().f_tags;
+    let mut doc = envelope.to_document(envelope.mailbox_id)?;
+    for tag in tags {
+        doc.add_facet(f_tags, tag.as_str());
+    }
+    Ok(doc)
+}
@@ -16,12 +16,18 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
+
 use crate::modules::account::migration::AccountModel;
 use crate::modules::cache::imap::mailbox::MailBox;
+use crate::modules::envelope::location::{MailboxLocation, MessageLocation};
 use crate::modules::error::code::ErrorCode;
-use crate::modules::utils::create_hash;
+use crate::modules::indexer::address::{name_addr, parse_name_addr};
+use crate::modules::indexer::analyzer::add_stemmed_fields;
+use crate::modules::indexer::headers::{add_header_text_fields, headers_object, read_headers};
 use crate::modules::{error::BichonResult, indexer::schema::SchemaTools};
 use crate::raise_error;
+use itertools::Itertools;
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
 use tantivy::schema::Facet;
@@ -36,6 +42,9 @@ pub struct Envelope {
     pub mailbox_id: u64,
     pub mailbox_name: Option<String>,
     pub uid: u32,
+    /// Every mailbox the message is filed in. `mailbox_id` and `uid` are those of
+    /// the mailbox it was last archived from.
+    pub locations: Vec<MailboxLocation>,
     pub subject: String,
     pub text: String,
     pub from: String,
@@ -46,8 +55,51 @@ pub struct Envelope {
     pub internal_date: i64,
     pub size: u32,
     pub thread_id: u64,
+    /// X-GM-MSGID of the message, for accounts synced with the Gmail extensions.
+    /// Their `thread_id` is the X-GM-THRID.
+    pub gmail_message_id: Option<u64>,
+    /// When the message was found to be gone from the server, set once it is
+    /// gone from all of its mailboxes. The archived copy is kept.
+    pub deleted_on_server_at: Option<i64>,
     pub attachments: Vec<String>,
     pub tags: Option<Vec<String>>,
+    /// IMAP flags and keywords of the message, e.g. `\Seen`, `\Flagged` or
+    /// `$Label1`, as last fetched from the server.
+    pub flags: Vec<String>,
+    /// Text extracted from the attachments, only used for indexing.
+    #[oai(skip)]
+    #[serde(skip)]
+    pub attachment_text: String,
+    /// Message-IDs of the messages this one replies to, oldest first, only
+    /// used for threading.
+    #[oai(skip)]
+    #[serde(skip)]
+    pub references: Vec<String>,
+    /// Content fingerprint of the message, only used to tell apart different
+    /// messages with the same Message-ID. See `content_fingerprint`.
+    #[oai(skip)]
+    #[serde(skip)]
+    pub fingerprint: String,
+    /// Display names of the senders and recipients by address, to show them as
+    /// `John Smith <js@x.com>`. Addresses without a display name are left out.
+    pub display_names: HashMap<String, String>,
+    /// Values of the headers enabled with `--bichon-index-headers`, by lowercase
+    /// header name.
+    pub headers: HashMap<String, Vec<String>>,
+    /// Parts of the subject and body that matched the query, only set by searches
+    /// that ask for them.
+    pub highlights: Option<Highlights>,
+    /// Number of matching messages in the thread of this envelope, only set by
+    /// searches grouped by thread.
+    pub thread_matches: Option<u64>,
+}
+
+/// HTML fragments with the matched terms wrapped in `<mark>` tags.
+/// Everything else in the fragments is HTML-escaped.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct Highlights {
+    pub subject: Option<String>,
+    pub text: Option<String>,
 }
 
 fn extract_u64_field(
@@ -115,6 +167,24 @@ fn extract_vec_string_field(
     Ok(value)
 }
 
+fn extract_display_names(document: &TantivyDocument) -> HashMap<String, String> {
+    let fields = SchemaTools::envelope_fields();
+    let mut names = HashMap::new();
+    for field in [
+        fields.f_from_name_addr,
+        fields.f_to_name_addr,
+        fields.f_cc_name_addr,
+        fields.f_bcc_name_addr,
+    ] {
+        for value in document.get_all(field).filter_map(|v| v.as_str()) {
+            if let (Some(name), address) = parse_name_addr(value) {
+                names.entry(address.to_string()).or_insert(name.to_string());
+            }
+        }
+    }
+    names
+}
+
 impl Envelope {
     pub fn to_document(&self, mailbox_id: u64) -> BichonResult<TantivyDocument> {
         let fields = SchemaTools::envelope_fields();
@@ -123,36 +193,71 @@ impl Envelope {
         doc.add_text(fields.f_message_id, &self.message_id);
         doc.add_u64(fields.f_account_id, self.account_id);
         doc.add_u64(fields.f_mailbox_id, mailbox_id);
+        for location in self.locations.iter().unique_by(|l| l.mailbox_id) {
+            if location.mailbox_id != mailbox_id {
+                doc.add_u64(fields.f_mailbox_id, location.mailbox_id);
+            }
+        }
         doc.add_u64(fields.f_uid, self.uid as u64);
         doc.add_text(fields.f_subject, &self.subject);
         doc.add_text(fields.f_text, &self.text);
+        add_stemmed_fields(&mut doc);
         doc.add_text(fields.f_from, &self.from);
+        doc.add_text(fields.f_from_name_addr, self.name_addr(&self.from));
         for to in &self.to {
             doc.add_text(fields.f_to, to);
+            doc.add_text(fields.f_to_name_addr, self.name_addr(to));
         }
         for cc in &self.cc {
             doc.add_text(fields.f_cc, cc);
+            doc.add_text(fields.f_cc_name_addr, self.name_addr(cc));
         }
         for bcc in &self.bcc {
             doc.add_text(fields.f_bcc, bcc);
+            doc.add_text(fields.f_bcc_name_addr, self.name_addr(bcc));
         }
         doc.add_i64(fields.f_date, self.date);
         doc.add_i64(fields.f_internal_date, self.internal_date);
         doc.add_u64(fields.f_size, self.size as u64);
         doc.add_u64(fields.f_thread_id, self.thread_id);
+        if let Some(gmail_message_id) = self.gmail_message_id {
+            doc.add_u64(fields.f_gmail_message_id, gmail_message_id);
+        }
+        if let Some(deleted_on_server_at) = self.deleted_on_server_at {
+            doc.add_i64(fields.f_deleted_on_server_at, deleted_on_server_at);
+        }
         for att in &self.attachments {
             doc.add_text(fields.f_attachments, att);
         }
         doc.add_bool(fields.f_has_attachment, self.attachments.len() > 0);
+        for tag in self.tags.iter().flatten() {
+            doc.add_facet(fields.f_tags, tag.as_str());
+        }
+        for flag in &self.flags {
+            doc.add_text(fields.f_flags, flag);
+        }
+        if !self.attachment_text.is_empty() {
+            doc.add_text(fields.f_attachment_text, &self.attachment_text);
+        }
+        if !self.headers.is_empty() {
+            doc.add_object(fields.f_headers, headers_object(&self.headers));
+            add_header_text_fields(&mut doc);
+        }
         Ok(doc)
     }
 
+    fn name_addr(&self, address: &str) -> String {
+        name_addr(self.display_names.get(address), address)
+    }
+
     pub async fn from_tantivy_doc(doc: &TantivyDocument) -> BichonResult<Self> {
         let fields = SchemaTools::envelope_fields();
         let account_id = extract_u64_field(doc, fields.f_account_id)?;
         let message_id = extract_string_field(doc, fields.f_message_id)?;
         let mailbox_id = extract_u64_field(doc, fields.f_mailbox_id)?;
-        let id = create_hash(account_id, &message_id);
+        // Not derived from the Message-ID, messages whose Message-ID collides
+        // with another one get an id of their own.
+        let id = extract_u64_field(doc, fields.f_id)?;
         let full_text = extract_string_field(doc, fields.f_text)?;
 
         // Take up to the first 120 characters as a preview;
@@ -179,6 +284,26 @@ impl Envelope {
             .iter()
             .find(|m| m.id == mailbox_id)
             .map(|m| m.name.clone());
+        let uid = extract_u64_field(doc, fields.f_uid)? as u32;
+        let mut locations: Vec<MailboxLocation> = MessageLocation::list(id)
+            .await?
+            .iter()
+            .map(|location| MailboxLocation {
+                mailbox_name: mailboxes
+                    .iter()
+                    .find(|m| m.id == location.mailbox_id)
+                    .map(|m| m.name.clone()),
+                ..location.into()
+            })
+            .collect();
+        if locations.is_empty() {
+            locations.push(MailboxLocation {
+                mailbox_id,
+                mailbox_name: mailbox_name.clone(),
+                uid,
+                vanished_at: None,
+            });
+        }
 
         let envelope = Envelope {
             id,
@@ -186,8 +311,9 @@ impl Envelope {
             account_email,
             mailbox_id,
             mailbox_name,
-            message_id: extract_string_field(doc, fields.f_message_id)?,
-            uid: extract_u64_field(doc, fields.f_uid)? as u32,
+            message_id,
+            uid,
+            locations,
             subject: extract_string_field(doc, fields.f_subject)?,
             text: preview,
             from: extract_string_field(doc, fields.f_from)?,
@@ -198,8 +324,22 @@ impl Envelope {
             internal_date: extract_i64_field(doc, fields.f_internal_date)?,
             size: extract_u64_field(doc, fields.f_size)? as u32,
             thread_id: extract_u64_field(doc, fields.f_thread_id)?,
+            gmail_message_id: doc
+                .get_first(fields.f_gmail_message_id)
+                .and_then(|v| v.as_u64()),
+            deleted_on_server_at: doc
+                .get_first(fields.f_deleted_on_server_at)
+                .and_then(|v| v.as_i64()),
             attachments: extract_vec_string_field(doc, fields.f_attachments)?,
             tags: Some(tags),
+            flags: extract_vec_string_field(doc, fields.f_flags)?,
+            attachment_text: String::new(),
+            references: Vec::new(),
+            fingerprint: String::new(),
+            display_names: extract_display_names(doc),
+            headers: read_headers(doc),
+            highlights: None,
+            thread_matches: None,
         };
         Ok(envelope)
     }
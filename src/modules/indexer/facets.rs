@@ -0,0 +1,410 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Datelike};
+use tantivy::{
+    collector::{Collector, SegmentCollector},
+    columnar::{Column, StrColumn},
+    fastfield::FacetReader,
+    schema::Facet,
+    DocId, Score, SegmentOrdinal, SegmentReader,
+};
+
+use crate::modules::{
+    indexer::fields::{
+        F_ACCOUNT_ID, F_BCC, F_CC, F_DATE, F_FROM, F_HAS_ATTACHMENT, F_MAILBOX_ID, F_SIZE, F_TAGS,
+        F_TO,
+    },
+    message::facets::FacetKind,
+};
+
+/// Size buckets as inclusive byte ranges, the same bounds `min_size`/`max_size` take.
+pub const SIZE_RANGES: [(u64, Option<u64>); 5] = [
+    (0, Some(10 * 1024 - 1)),
+    (10 * 1024, Some(100 * 1024 - 1)),
+    (100 * 1024, Some(1024 * 1024 - 1)),
+    (1024 * 1024, Some(10 * 1024 * 1024 - 1)),
+    (10 * 1024 * 1024, None),
+];
+
+/// Counts of the matching envelopes per facet value. Only the requested facets are filled.
+#[derive(Debug, Default)]
+pub struct FacetCounts {
+    pub senders: HashMap<String, u64>,
+    /// Every address is counted once per message, even if it is in several recipient headers.
+    pub recipients: HashMap<String, u64>,
+    /// Mailbox id to its account id and count.
+    pub mailboxes: HashMap<u64, (u64, u64)>,
+    pub accounts: HashMap<u64, u64>,
+    pub tags: HashMap<String, u64>,
+    /// Keyed by `year * 12 + month0` of the `Date` header, in UTC.
+    pub months: HashMap<i32, u64>,
+    /// Without and with attachments.
+    pub attachments: [u64; 2],
+    /// One count per entry of `SIZE_RANGES`.
+    pub sizes: [u64; SIZE_RANGES.len()],
+}
+
+impl FacetCounts {
+    fn merge(&mut self, other: FacetCounts) {
+        fn merge_map<K: std::hash::Hash + Eq>(into: &mut HashMap<K, u64>, from: HashMap<K, u64>) {
+            for (key, count) in from {
+                *into.entry(key).or_default() += count;
+            }
+        }
+        merge_map(&mut self.senders, other.senders);
+        merge_map(&mut self.recipients, other.recipients);
+        merge_map(&mut self.accounts, other.accounts);
+        merge_map(&mut self.tags, other.tags);
+        merge_map(&mut self.months, other.months);
+        for (mailbox_id, (account_id, count)) in other.mailboxes {
+            self.mailboxes
+                .entry(mailbox_id)
+                .or_insert((account_id, 0))
+                .1 += count;
+        }
+        for (total, count) in self.attachments.iter_mut().zip(other.attachments) {
+            *total += count;
+        }
+        for (total, count) in self.sizes.iter_mut().zip(other.sizes) {
+            *total += count;
+        }
+    }
+}
+
+/// Collects `FacetCounts` from the fast fields of the matching envelopes.
+pub struct FacetCountCollector {
+    kinds: HashSet<FacetKind>,
+}
+
+impl FacetCountCollector {
+    pub fn new(kinds: &[FacetKind]) -> Self {
+        Self {
+            kinds: kinds.iter().copied().collect(),
+        }
+    }
+
+    fn wants(&self, kind: FacetKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+}
+
+impl Collector for FacetCountCollector {
+    type Fruit = FacetCounts;
+    type Child = FacetCountSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let fast_fields = segment.fast_fields();
+        let senders = if self.wants(FacetKind::Sender) || self.wants(FacetKind::SenderDomain) {
+            fast_fields.str(F_FROM)?
+        } else {
+            None
+        };
+        let recipients = if self.wants(FacetKind::Recipient) {
+            let mut columns = Vec::new();
+            for field in [F_TO, F_CC, F_BCC] {
+                columns.extend(fast_fields.str(field)?);
+            }
+            columns
+        } else {
+            Vec::new()
+        };
+        let mailboxes = if self.wants(FacetKind::Mailbox) {
+            Some((
+                fast_fields.u64(F_MAILBOX_ID)?,
+                fast_fields.u64(F_ACCOUNT_ID)?,
+            ))
+        } else {
+            None
+        };
+        let accounts = if self.wants(FacetKind::Account) {
+            Some(fast_fields.u64(F_ACCOUNT_ID)?)
+        } else {
+            None
+        };
+        let tags = if self.wants(FacetKind::Tag) {
+            Some(segment.facet_reader(F_TAGS)?)
+        } else {
+            None
+        };
+        let dates = if self.wants(FacetKind::Month) {
+            Some(fast_fields.i64(F_DATE)?)
+        } else {
+            None
+        };
+        let attachments = if self.wants(FacetKind::HasAttachment) {
+            Some(fast_fields.bool(F_HAS_ATTACHMENT)?)
+        } else {
+            None
+        };
+        let sizes = if self.wants(FacetKind::Size) {
+            Some(fast_fields.u64(F_SIZE)?)
+        } else {
+            None
+        };
+        Ok(FacetCountSegmentCollector {
+            sender_ords: HashMap::new(),
+            recipient_ords: vec![HashMap::new(); recipients.len()],
+            tag_ords: HashMap::new(),
+            counts: FacetCounts::default(),
+            senders,
+            recipients,
+            mailboxes,
+            accounts,
+            tags,
+            dates,
+            attachments,
+            sizes,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(&self, segment_fruits: Vec<FacetCounts>) -> tantivy::Result<FacetCounts> {
+        let mut counts = FacetCounts::default();
+        for fruit in segment_fruits {
+            counts.merge(fruit);
+        }
+        Ok(counts)
+    }
+}
+
+pub struct FacetCountSegmentCollector {
+    senders: Option<StrColumn>,
+    recipients: Vec<StrColumn>,
+    mailboxes: Option<(Column<u64>, Column<u64>)>,
+    accounts: Option<Column<u64>>,
+    tags: Option<FacetReader>,
+    dates: Option<Column<i64>>,
+    attachments: Option<Column<bool>>,
+    sizes: Option<Column<u64>>,
+    // Text values are counted by term ordinal and resolved once in `harvest`.
+    sender_ords: HashMap<u64, u64>,
+    recipient_ords: Vec<HashMap<u64, u64>>,
+    tag_ords: HashMap<u64, u64>,
+    counts: FacetCounts,
+}
+
+impl FacetCountSegmentCollector {
+    fn collect_recipients(&mut self, doc: DocId) {
+        let mut per_column: Vec<(usize, Vec<u64>)> = Vec::new();
+        for (index, column) in self.recipients.iter().enumerate() {
+            let mut ords: Vec<u64> = column.term_ords(doc).collect();
+            if !ords.is_empty() {
+                ords.sort_unstable();
+                ords.dedup();
+                per_column.push((index, ords));
+            }
+        }
+        match per_column.as_slice() {
+            [] => {}
+            [(index, ords)] => {
+                for ord in ords {
+                    *self.recipient_ords[*index].entry(*ord).or_default() += 1;
+                }
+            }
+            // Ordinals of different columns are unrelated, compare the addresses.
+            _ => {
+                let mut addresses = HashSet::new();
+                for (index, ords) in per_column {
+                    for ord in ords {
+                        let mut address = String::new();
+                        if let Ok(true) = self.recipients[index].ord_to_str(ord, &mut address) {
+                            addresses.insert(address);
+                        }
+                    }
+                }
+                for address in addresses {
+                    *self.counts.recipients.entry(address).or_default() += 1;
+                }
+            }
+        }
+    }
+}
+
+impl SegmentCollector for FacetCountSegmentCollector {
+    type Fruit = FacetCounts;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        if let Some(senders) = &self.senders {
+            for ord in senders.term_ords(doc) {
+                *self.sender_ords.entry(ord).or_default() += 1;
+            }
+        }
+        if !self.recipients.is_empty() {
+            self.collect_recipients(doc);
+        }
+        if let Some((mailboxes, accounts)) = &self.mailboxes {
+            // A message filed in several mailboxes counts for each of them.
+            let account_id = accounts.first(doc).unwrap_or_default();
+            for mailbox_id in mailboxes.values_for_doc(doc) {
+                self.counts
+                    .mailboxes
+                    .entry(mailbox_id)
+                    .or_insert((account_id, 0))
+                    .1 += 1;
+            }
+        }
+        if let Some(account_id) = self.accounts.as_ref().and_then(|c| c.first(doc)) {
+            *self.counts.accounts.entry(account_id).or_default() += 1;
+        }
+        if let Some(tags) = &self.tags {
+            for ord in tags.facet_ords(doc) {
+                *self.tag_ords.entry(ord).or_default() += 1;
+            }
+        }
+        if let Some(date) = self.dates.as_ref().and_then(|c| c.first(doc)) {
+            if let Some(date) = DateTime::from_timestamp_millis(date) {
+                let month = date.year() * 12 + date.month0() as i32;
+                *self.counts.months.entry(month).or_default() += 1;
+            }
+        }
+        if let Some(has_attachment) = self.attachments.as_ref().and_then(|c| c.first(doc)) {
+            self.counts.attachments[has_attachment as usize] += 1;
+        }
+        if let Some(size) = self.sizes.as_ref().and_then(|c| c.first(doc)) {
+            if let Some(index) = SIZE_RANGES
+                .iter()
+                .position(|(_, max)| max.is_none_or(|max| size <= max))
+            {
+                self.counts.sizes[index] += 1;
+            }
+        }
+    }
+
+    fn harvest(mut self) -> FacetCounts {
+        let mut value = String::new();
+        if let Some(senders) = &self.senders {
+            for (ord, count) in self.sender_ords {
+                if let Ok(true) = senders.ord_to_str(ord, &mut value) {
+                    *self.counts.senders.entry(value.clone()).or_default() += count;
+                }
+            }
+        }
+        for (column, ords) in self.recipients.iter().zip(self.recipient_ords) {
+            for (ord, count) in ords {
+                if let Ok(true) = column.ord_to_str(ord, &mut value) {
+                    *self.counts.recipients.entry(value.clone()).or_default() += count;
+                }
+            }
+        }
+        if let Some(tags) = &self.tags {
+            let mut facet = Facet::root();
+            for (ord, count) in self.tag_ords {
+                if tags.facet_from_ord(ord, &mut facet).is_ok() {
+                    *self.counts.tags.entry(facet.to_string()).or_default() += count;
+                }
+            }
+        }
+        self.counts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tantivy::{collector::Count, query::AllQuery, Index, IndexWriter, TantivyDocument};
+
+    use super::*;
+    use crate::modules::indexer::{analyzer::register_analyzers, schema::SchemaTools};
+
+    #[test]
+    fn test_counts_facets_of_matching_documents() {
+        let index = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_analyzers(&index);
+        let f = SchemaTools::envelope_fields();
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+        let messages = [
+            (
+                "a@example.com",
+                vec!["x@test.org"],
+                vec!["x@test.org"],
+                1,
+                1_706_745_600_000,
+                2048,
+            ),
+            (
+                "b@example.com",
+                vec!["x@test.org", "y@test.org"],
+                vec![],
+                1,
+                1_709_251_200_000,
+                20 * 1024 * 1024,
+            ),
+            (
+                "a@example.com",
+                vec![],
+                vec!["y@test.org"],
+                2,
+                1_709_337_600_000,
+                50 * 1024,
+            ),
+        ];
+        for (from, to, cc, mailbox_id, date, size) in messages {
+            let mut doc = TantivyDocument::new();
+            doc.add_text(f.f_from, from);
+            for address in to {
+                doc.add_text(f.f_to, address);
+            }
+            for address in cc {
+                doc.add_text(f.f_cc, address);
+            }
+            doc.add_u64(f.f_account_id, 7);
+            doc.add_u64(f.f_mailbox_id, mailbox_id);
+            doc.add_i64(f.f_date, date);
+            doc.add_u64(f.f_size, size);
+            doc.add_bool(f.f_has_attachment, mailbox_id == 2);
+            doc.add_facet(f.f_tags, Facet::from("/case/42"));
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+
+        let kinds = [
+            FacetKind::Sender,
+            FacetKind::Recipient,
+            FacetKind::Mailbox,
+            FacetKind::Tag,
+            FacetKind::Month,
+            FacetKind::HasAttachment,
+            FacetKind::Size,
+        ];
+        let searcher = index.reader().unwrap().searcher();
+        let (total, counts) = searcher
+            .search(&AllQuery, &(Count, FacetCountCollector::new(&kinds)))
+            .unwrap();
+
+        assert_eq!(total, 3);
+        assert_eq!(counts.senders["a@example.com"], 2);
+        // Listed in both To and Cc of the first message, counted once.
+        assert_eq!(counts.recipients["x@test.org"], 2);
+        assert_eq!(counts.recipients["y@test.org"], 2);
+        assert_eq!(counts.mailboxes[&1], (7, 2));
+        assert_eq!(counts.tags["/case/42"], 3);
+        assert_eq!(counts.months[&(2024 * 12 + 2)], 2);
+        assert_eq!(counts.attachments, [2, 1]);
+        assert_eq!(counts.sizes, [1, 1, 0, 0, 1]);
+        assert!(counts.accounts.is_empty());
+    }
+}
@@ -17,6 +17,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+use crate::modules::indexer::analyzer::StemLanguage;
 use tantivy::schema::Field;
 
 pub const F_MESSAGE_ID: &str = "message_id";
@@ -29,13 +30,23 @@ pub const F_FROM: &str = "from";
 pub const F_TO: &str = "to";
 pub const F_CC: &str = "cc";
 pub const F_BCC: &str = "bcc";
+pub const F_FROM_NAME_ADDR: &str = "from_name_addr";
+pub const F_TO_NAME_ADDR: &str = "to_name_addr";
+pub const F_CC_NAME_ADDR: &str = "cc_name_addr";
+pub const F_BCC_NAME_ADDR: &str = "bcc_name_addr";
 pub const F_DATE: &str = "date";
 pub const F_INTERNAL_DATE: &str = "internal_date";
 pub const F_SIZE: &str = "size";
 pub const F_THREAD_ID: &str = "thread_id";
+pub const F_GMAIL_MESSAGE_ID: &str = "gmail_message_id";
+pub const F_DELETED_ON_SERVER_AT: &str = "deleted_on_server_at";
 pub const F_ATTACHMENTS: &str = "attachments";
 pub const F_HAS_ATTACHMENT: &str = "has_attachment";
+pub const F_ATTACHMENT_TEXT: &str = "attachment_text";
 pub const F_TAGS: &str = "tags";
+pub const F_FLAGS: &str = "flags";
+pub const F_HEADERS: &str = "headers";
+pub const F_HEADERS_TEXT: &str = "headers_text";
 
 pub const F_ID: &str = "id";
 pub struct EnvelopeFields {
@@ -50,20 +61,45 @@ pub struct EnvelopeFields {
     pub f_to: Field,
     pub f_cc: Field,
     pub f_bcc: Field,
+    /// `Name <address>` of the senders and recipients, for address, domain and
+    /// display name searches.
+    pub f_from_name_addr: Field,
+    pub f_to_name_addr: Field,
+    pub f_cc_name_addr: Field,
+    pub f_bcc_name_addr: Field,
     pub f_date: Field,
     pub f_internal_date: Field,
     pub f_size: Field,
     pub f_thread_id: Field,
+    /// X-GM-MSGID of messages archived from Gmail.
+    pub f_gmail_message_id: Field,
+    /// When the message was found to be gone from the server, only set once it
+    /// is gone from all of its mailboxes.
+    pub f_deleted_on_server_at: Field,
     pub f_attachments: Field,
     pub f_has_attachment: Field,
+    pub f_attachment_text: Field,
     pub f_tags: Field,
+    /// IMAP flags and keywords, e.g. `\Seen` or `$Junk`, matched ignoring case.
+    pub f_flags: Field,
+    /// Values of the headers enabled with `--bichon-index-headers`, a JSON object
+    /// keyed by lowercase header name.
+    pub f_headers: Field,
+    /// Words of the headers enabled for text search, keyed like `f_headers`.
+    pub f_headers_text: Field,
+    /// Stemmed copies of subject and body, one field per language.
+    pub f_stemmed: Vec<(StemLanguage, Field)>,
 }
 
-pub const F_EML: &str = "eml";
-
-pub struct EmlFields {
-    pub f_id: Field,
-    pub f_account_id: Field,
-    pub f_mailbox_id: Field,
-    pub f_eml: Field,
+impl EnvelopeFields {
+    pub fn stemmed_field(&self, language: StemLanguage) -> Field {
+        self.f_stemmed
+            .iter()
+            .find(|(l, _)| *l == language)
+            .map(|(_, field)| *field)
+            .expect("every stem language has a field in the envelope schema")
+    }
 }
+
+/// Raw EML field of the legacy EML index, kept for `migrate_legacy_eml_index`.
+pub const F_EML: &str = "eml";
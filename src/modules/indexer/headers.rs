@@ -0,0 +1,242 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use mail_parser::{HeaderValue, Message};
+use tantivy::query::{PhraseQuery, Query, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption, OwnedValue, Value};
+use tantivy::tokenizer::TokenStream;
+use tantivy::{TantivyDocument, Term};
+
+use crate::modules::common::AddrVec;
+use crate::modules::indexer::analyzer::text_analyzer;
+use crate::modules::indexer::schema::SchemaTools;
+use crate::modules::settings::cli::SETTINGS;
+
+/// A header picked with `--bichon-index-headers` to be extracted and indexed.
+///
+/// Written as `List-Id` for exact, case-insensitive matches on the whole value, or
+/// `X-Mailer:text` to also search the words of the value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexedHeader {
+    /// Lowercase header name, the key in `Envelope::headers`.
+    pub name: String,
+    pub text: bool,
+}
+
+impl FromStr for IndexedHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, mode) = match s.trim().rsplit_once(':') {
+            Some((name, mode)) => (name.trim(), Some(mode.trim())),
+            None => (s.trim(), None),
+        };
+        let valid_name = !name.is_empty()
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && b != b':' && b != b'.');
+        if !valid_name {
+            return Err(format!("'{}' is not a valid header name", name));
+        }
+        let text = match mode {
+            None | Some("exact") => false,
+            Some("text") => true,
+            Some(other) => {
+                return Err(format!(
+                    "unknown match mode '{}' for header '{}', expected 'exact' or 'text'",
+                    other, name
+                ))
+            }
+        };
+        Ok(IndexedHeader {
+            name: name.to_ascii_lowercase(),
+            text,
+        })
+    }
+}
+
+impl fmt::Display for IndexedHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.text {
+            write!(f, "{}:text", self.name)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
+
+impl IndexedHeader {
+    /// Headers enabled in the settings.
+    pub fn enabled() -> &'static [IndexedHeader] {
+        &SETTINGS.bichon_index_headers
+    }
+
+    pub fn find(name: &str) -> Option<&'static IndexedHeader> {
+        Self::enabled()
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// Values of the enabled headers of `message` by lowercase header name, in the
+/// order they appear. Address headers like `Reply-To` or `List-Id` give one value
+/// per address.
+pub fn extract_headers(message: &Message<'_>) -> HashMap<String, Vec<String>> {
+    let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+    for header in message.headers() {
+        let Some(indexed) = IndexedHeader::find(header.name.as_str()) else {
+            continue;
+        };
+        let values: Vec<String> = match &header.value {
+            HeaderValue::Address(address) => AddrVec::from(address)
+                .0
+                .into_iter()
+                .filter_map(|addr| addr.address.or(addr.name))
+                .collect(),
+            HeaderValue::Text(text) => vec![text.to_string()],
+            HeaderValue::TextList(list) => list.iter().map(|text| text.to_string()).collect(),
+            HeaderValue::DateTime(date) => vec![date.to_rfc3339()],
+            HeaderValue::ContentType(_) | HeaderValue::Received(_) => message
+                .raw_message()
+                .get(header.offset_start as usize..header.offset_end as usize)
+                .map(|raw| {
+                    String::from_utf8_lossy(raw)
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .into_iter()
+                .collect(),
+            HeaderValue::Empty => Vec::new(),
+        };
+        headers.entry(indexed.name.clone()).or_default().extend(
+            values
+                .into_iter()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty()),
+        );
+    }
+    headers.retain(|_, values| !values.is_empty());
+    headers
+}
+
+pub fn headers_object(headers: &HashMap<String, Vec<String>>) -> BTreeMap<String, OwnedValue> {
+    headers
+        .iter()
+        .map(|(name, values)| {
+            let values = values.iter().map(|v| OwnedValue::Str(v.clone())).collect();
+            (name.clone(), OwnedValue::Array(values))
+        })
+        .collect()
+}
+
+/// Reads the stored header values of a document.
+pub fn read_headers(doc: &TantivyDocument) -> HashMap<String, Vec<String>> {
+    let f = SchemaTools::envelope_fields();
+    let mut headers = HashMap::new();
+    let Some(object) = doc.get_first(f.f_headers).and_then(|v| v.as_object()) else {
+        return headers;
+    };
+    for (name, value) in object {
+        let values: Vec<String> = match value.as_array() {
+            Some(array) => array.filter_map(|v| v.as_str().map(String::from)).collect(),
+            None => value.as_str().map(String::from).into_iter().collect(),
+        };
+        headers.insert(name.to_string(), values);
+    }
+    headers
+}
+
+/// Copies the values of the headers enabled for text search into the full-text
+/// header field.
+///
+/// That field is not stored, so this also has to run when a document is rebuilt
+/// from the doc store.
+pub fn add_header_text_fields(doc: &mut TantivyDocument) {
+    let f = SchemaTools::envelope_fields();
+    let mut headers = read_headers(doc);
+    headers.retain(|name, _| IndexedHeader::find(name).is_some_and(|header| header.text));
+    if !headers.is_empty() {
+        doc.add_object(f.f_headers_text, headers_object(&headers));
+    }
+}
+
+/// Builds the query for a header filter: the whole value ignoring case, or the
+/// words of the value as a phrase for headers enabled for text search. Returns
+/// `None` if `value` has nothing to search for.
+pub fn header_query(header: &IndexedHeader, value: &str) -> Option<Box<dyn Query>> {
+    let f = SchemaTools::envelope_fields();
+    let term = |field: Field, text: &str| {
+        let mut term = Term::from_field_json_path(field, &header.name, false);
+        term.append_type_and_str(text);
+        term
+    };
+    let value = value.trim();
+    if !header.text {
+        if value.is_empty() {
+            return None;
+        }
+        return Some(Box::new(TermQuery::new(
+            term(f.f_headers, &value.to_lowercase()),
+            IndexRecordOption::Basic,
+        )));
+    }
+    let mut analyzer = text_analyzer();
+    let mut stream = analyzer.token_stream(value);
+    let mut terms = Vec::new();
+    while stream.advance() {
+        terms.push(term(f.f_headers_text, &stream.token().text));
+    }
+    match terms.len() {
+        0 => None,
+        1 => Some(Box::new(TermQuery::new(
+            terms.remove(0),
+            IndexRecordOption::Basic,
+        ))),
+        _ => Some(Box::new(PhraseQuery::new(terms))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parses_indexed_headers() {
+        assert_eq!(
+            "List-Id".parse::<IndexedHeader>(),
+            Ok(IndexedHeader {
+                name: "list-id".into(),
+                text: false
+            })
+        );
+        assert_eq!(
+            " X-Mailer:text".parse::<IndexedHeader>(),
+            Ok(IndexedHeader {
+                name: "x-mailer".into(),
+                text: true
+            })
+        );
+        assert!("X-Mailer:fuzzy".parse::<IndexedHeader>().is_err());
+        assert!(":text".parse::<IndexedHeader>().is_err());
+    }
+}
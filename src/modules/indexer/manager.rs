@@ -19,8 +19,8 @@
 use std::{
     collections::{HashMap, HashSet},
     ops::Bound,
-    path::PathBuf,
-    sync::{Arc, LazyLock},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex as StdMutex, RwLock},
     time::Duration,
 };
 
@@ -29,24 +29,34 @@ use crate::{
     modules::{
         account::migration::AccountModel,
         common::signal::SIGNAL_MANAGER,
+        context::Initialize,
         dashboard::{DashboardStats, Group, LargestEmail, TimeBucket},
+        envelope::location::{seed_message_locations, MessageLocation},
         error::{code::ErrorCode, BichonResult},
+        imap::gmail::is_label_facet,
         indexer::{
-            envelope::Envelope,
+            address::address_query,
+            analyzer::{add_stemmed_fields, register_analyzers},
+            envelope::{Envelope, Highlights},
+            facets::{FacetCountCollector, FacetCounts},
             fields::{
-                F_ACCOUNT_ID, F_DATE, F_FROM, F_HAS_ATTACHMENT, F_MAILBOX_ID, F_SIZE, F_TAGS,
-                F_THREAD_ID, F_UID,
+                F_ACCOUNT_ID, F_DATE, F_FROM, F_HAS_ATTACHMENT, F_ID, F_INTERNAL_DATE, F_SIZE,
+                F_TAGS, F_THREAD_ID,
             },
+            headers::{add_header_text_fields, header_query, IndexedHeader},
+            query::{deleted_on_server_query, flag_query, parse_search_query},
             schema::SchemaTools,
+            threads::{thread_key, ThreadCountCollector},
+            upgrade::{upgrade_envelope_index, PREVIOUS_DIR},
         },
-        message::search::SearchFilter,
+        message::facets::FacetKind,
+        message::search::{PageCursor, ResultOptions, SearchFilter, SortField, SortOptions},
         rest::response::DataPage,
         settings::dir::DATA_DIR_MANAGER,
     },
     raise_error, utc_now,
 };
 use chrono::Utc;
-use mail_parser::{MessageParser, MimeHeaders};
 use serde_json::json;
 use tantivy::{
     aggregation::{
@@ -57,63 +67,109 @@ use tantivy::{
         AggregationCollector, Key,
     },
     collector::{Count, FacetCollector, TopDocs},
-    query::{AllQuery, BooleanQuery, EmptyQuery, Occur, Query, QueryParser, RangeQuery, TermQuery},
-    schema::{Facet, IndexRecordOption, Value},
-    store::{Compressor, ZstdCompressor},
-    DocAddress, Index, IndexBuilder, IndexReader, IndexSettings, IndexWriter, Order,
+    query::{
+        AllQuery, BooleanQuery, EmptyQuery, MoreLikeThisQuery, Occur, Query, QueryParser,
+        RangeQuery, TermQuery,
+    },
+    schema::{Facet, Field, IndexRecordOption, OwnedValue, Value},
+    snippet::{Snippet, SnippetGenerator},
+    DocAddress, DocId, Index, IndexReader, IndexWriter, Order, Score, SegmentReader,
     TantivyDocument, Term,
 };
 use tantivy::{indexer::UserOperation, Searcher};
 use tokio::{
-    fs::File,
-    io::AsyncWriteExt,
-    sync::{mpsc, Mutex},
+    sync::{mpsc, MappedMutexGuard, Mutex, MutexGuard},
     task,
 };
 
 pub static ENVELOPE_INDEX_MANAGER: LazyLock<EnvelopeIndexManager> =
     LazyLock::new(EnvelopeIndexManager::new);
-pub static EML_INDEX_MANAGER: LazyLock<EmlIndexManager> = LazyLock::new(EmlIndexManager::new);
 
 pub const ENVELOPE_BATCH_SIZE: usize = 1000;
-pub const EML_BATCH_SIZE: usize = 200;
+/// Words of the message that are in fewer documents than this are ignored when
+/// looking for similar messages.
+const SIMILAR_MIN_DOC_FREQUENCY: u64 = 2;
+const SIMILAR_MAX_QUERY_TERMS: usize = 25;
+const SIMILAR_MIN_WORD_LENGTH: usize = 3;
+/// How deep `SearchFilter` groups can be nested.
+const MAX_FILTER_DEPTH: usize = 8;
 
 const MAX_BUFFER_DURATION: Duration = Duration::from_secs(30);
 
 pub enum WriteMessage {
     Document((u64, TantivyDocument)),
+    /// Envelope ids with the thread they moved to.
+    ThreadIds(Vec<(u64, u64)>),
+    /// Envelope ids with their new flags.
+    Flags(Vec<(u64, Vec<String>)>),
+    /// Envelope ids with the tags of their Gmail labels.
+    Labels(Vec<(u64, Vec<String>)>),
+    /// Envelope ids with when they were found to be deleted on the server.
+    DeletedOnServer(Vec<(u64, i64)>),
     Shutdown,
 }
 
 pub struct EnvelopeIndexManager {
-    index_writer: Arc<Mutex<IndexWriter>>,
+    /// Only empty while a reindex swaps the index directory.
+    index_writer: Arc<Mutex<Option<IndexWriter>>>,
     sender: mpsc::Sender<WriteMessage>,
+    handles: RwLock<IndexHandles>,
+    /// Envelopes written while a reindex is running, `None` when no reindex is running.
+    reindex_dirty: StdMutex<Option<HashSet<u64>>>,
+}
+
+/// Reader and query parsers bound to the open index, replaced when a reindex
+/// swaps in a new one.
+#[derive(Clone)]
+struct IndexHandles {
     reader: IndexReader,
     query_parser: QueryParser,
+    attachment_query_parser: QueryParser,
+}
+
+impl IndexHandles {
+    fn new(index: &Index) -> tantivy::Result<Self> {
+        let reader = index.reader()?;
+        let mut query_parser =
+            QueryParser::for_index(index, SchemaTools::envelope_default_fields());
+        query_parser.set_conjunction_by_default();
+        let mut attachment_query_parser = QueryParser::for_index(
+            index,
+            vec![SchemaTools::envelope_fields().f_attachment_text],
+        );
+        attachment_query_parser.set_conjunction_by_default();
+        Ok(Self {
+            reader,
+            query_parser,
+            attachment_query_parser,
+        })
+    }
+}
+
+impl Initialize for EnvelopeIndexManager {
+    async fn initialize() -> BichonResult<()> {
+        upgrade_envelope_index().await?;
+        seed_message_locations().await
+    }
 }
 
 impl EnvelopeIndexManager {
     pub fn new() -> Self {
         let index = Self::open_or_create_index(&DATA_DIR_MANAGER.envelope_dir);
-        let index_writer = Arc::new(Mutex::new(
-            index
-                .writer_with_num_threads(8, 536_870_912)
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
-                        DATA_DIR_MANAGER.envelope_dir, e
-                    )
-                }),
-        ));
-        let reader = index.reader().unwrap_or_else(|e| {
+        let index_writer = Arc::new(Mutex::new(Some(
+            Self::create_writer(&index).unwrap_or_else(|e| {
+                panic!(
+                    "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
+                    DATA_DIR_MANAGER.envelope_dir, e
+                )
+            }),
+        )));
+        let handles = IndexHandles::new(&index).unwrap_or_else(|e| {
             panic!(
                 "Failed to create IndexReader for {:?}: {}",
                 DATA_DIR_MANAGER.envelope_dir, e
             )
         });
-        let mut query_parser =
-            QueryParser::for_index(&index, SchemaTools::envelope_default_fields());
-        query_parser.set_conjunction_by_default();
 
         let (sender, mut receiver) = mpsc::channel::<WriteMessage>(1000);
         task::spawn(async move {
@@ -131,6 +187,46 @@ impl EnvelopeIndexManager {
                                     ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
                                 }
                             }
+                            Some(WriteMessage::ThreadIds(updates)) => {
+                                ENVELOPE_INDEX_MANAGER
+                                    .rewrite_documents(&mut buffer, updates, |doc, thread_id| {
+                                        with_thread_id(doc, *thread_id)
+                                    })
+                                    .await;
+                                if buffer.len() >= ENVELOPE_BATCH_SIZE {
+                                    ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
+                                }
+                            }
+                            Some(WriteMessage::Flags(updates)) => {
+                                ENVELOPE_INDEX_MANAGER
+                                    .rewrite_documents(&mut buffer, updates, |doc, flags| {
+                                        with_flags(doc, flags)
+                                    })
+                                    .await;
+                                if buffer.len() >= ENVELOPE_BATCH_SIZE {
+                                    ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
+                                }
+                            }
+                            Some(WriteMessage::Labels(updates)) => {
+                                ENVELOPE_INDEX_MANAGER
+                                    .rewrite_documents(&mut buffer, updates, |doc, tags| {
+                                        with_label_tags(doc, tags)
+                                    })
+                                    .await;
+                                if buffer.len() >= ENVELOPE_BATCH_SIZE {
+                                    ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
+                                }
+                            }
+                            Some(WriteMessage::DeletedOnServer(updates)) => {
+                                ENVELOPE_INDEX_MANAGER
+                                    .rewrite_documents(&mut buffer, updates, |doc, at| {
+                                        with_deleted_on_server_at(doc, *at)
+                                    })
+                                    .await;
+                                if buffer.len() >= ENVELOPE_BATCH_SIZE {
+                                    ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
+                                }
+                            }
                             Some(WriteMessage::Shutdown) => {
                                 ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
                                 break;
@@ -152,8 +248,8 @@ impl EnvelopeIndexManager {
         Self {
             index_writer,
             sender,
-            reader,
-            query_parser,
+            handles: RwLock::new(handles),
+            reindex_dirty: StdMutex::new(None),
         }
     }
 
@@ -161,14 +257,95 @@ impl EnvelopeIndexManager {
         let _ = self.sender.send(WriteMessage::Document((eid, doc))).await;
     }
 
+    /// Moves indexed envelopes to another thread, see `assign_thread`.
+    pub async fn update_thread_ids(&self, updates: Vec<(u64, u64)>) {
+        let _ = self.sender.send(WriteMessage::ThreadIds(updates)).await;
+    }
+
+    /// Sets the flags of indexed envelopes, see `MessageLocation::update_flags`.
+    pub async fn update_flags(&self, updates: Vec<(u64, Vec<String>)>) {
+        let _ = self.sender.send(WriteMessage::Flags(updates)).await;
+    }
+
+    /// Sets the tags of the Gmail labels of indexed envelopes, see `label_tag`.
+    /// Other tags are kept.
+    pub async fn update_labels(&self, updates: Vec<(u64, Vec<String>)>) {
+        let _ = self.sender.send(WriteMessage::Labels(updates)).await;
+    }
+
+    /// Marks indexed envelopes as deleted on the server, see
+    /// `MessageLocation::mark_vanished`.
+    pub async fn mark_deleted_on_server(&self, updates: Vec<(u64, i64)>) {
+        let _ = self
+            .sender
+            .send(WriteMessage::DeletedOnServer(updates))
+            .await;
+    }
+
+    /// Documents still in the buffer are changed in place, the others are read
+    /// back from the index and written with the next commit.
+    async fn rewrite_documents<T>(
+        &self,
+        buffer: &mut HashMap<u64, TantivyDocument>,
+        updates: Vec<(u64, T)>,
+        rewrite: impl Fn(&TantivyDocument, &T) -> TantivyDocument,
+    ) {
+        let searcher = match self.create_searcher() {
+            Ok(searcher) => searcher,
+            Err(e) => {
+                tracing::error!("Failed to update envelopes: {:#?}", e);
+                return;
+            }
+        };
+        let f_id = SchemaTools::envelope_fields().f_id;
+        for (eid, update) in updates {
+            if let Some(doc) = buffer.get_mut(&eid) {
+                *doc = rewrite(doc, &update);
+                continue;
+            }
+            let query = TermQuery::new(Term::from_field_u64(f_id, eid), IndexRecordOption::Basic);
+            let doc_address = match searcher.search(&query, &TopDocs::with_limit(1)) {
+                Ok(docs) => match docs.first() {
+                    Some((_, doc_address)) => *doc_address,
+                    None => continue,
+                },
+                Err(e) => {
+                    tracing::error!("Failed to find envelope {}: {:#?}", eid, e);
+                    continue;
+                }
+            };
+            match searcher.doc_async::<TantivyDocument>(doc_address).await {
+                Ok(doc) => {
+                    let mut new_doc = rewrite(&doc, &update);
+                    add_stemmed_fields(&mut new_doc);
+                    add_header_text_fields(&mut new_doc);
+                    buffer.insert(eid, new_doc);
+                }
+                Err(e) => tracing::error!("Failed to load envelope {}: {:#?}", eid, e),
+            }
+        }
+    }
+
     async fn drain_and_commit(&self, buffer: &mut HashMap<u64, TantivyDocument>) {
         if buffer.is_empty() {
             return;
         }
-        let mut writer = self.index_writer.lock().await;
+        // Another mailbox may have recorded a location of the same message after
+        // the document was built.
+        let mailboxes = MessageLocation::mailboxes_of(buffer.keys().copied().collect())
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("Failed to load message locations: {:#?}", e);
+                HashMap::new()
+            });
+        let mut writer = self.writer().await;
+        self.mark_dirty(buffer.keys().copied());
         let mut operations = Vec::new();
 
-        for (eid, doc) in buffer.drain() {
+        for (eid, mut doc) in buffer.drain() {
+            if let Some(mailbox_ids) = mailboxes.get(&eid) {
+                doc = with_mailbox_ids(&doc, mailbox_ids);
+            }
             let delete_term = Term::from_field_u64(SchemaTools::envelope_fields().f_id, eid);
             operations.push(UserOperation::Delete(delete_term));
             operations.push(UserOperation::Add(doc));
@@ -181,8 +358,20 @@ impl EnvelopeIndexManager {
         fatal_commit(&mut writer);
     }
 
+    fn create_writer(index: &Index) -> tantivy::Result<IndexWriter> {
+        index.writer_with_num_threads(8, 536_870_912)
+    }
+
+    async fn writer(&self) -> MappedMutexGuard<'_, IndexWriter> {
+        MutexGuard::map(self.index_writer.lock().await, |writer| {
+            writer
+                .as_mut()
+                .expect("the envelope index writer is only missing while the index is swapped")
+        })
+    }
+
     fn open_or_create_index(index_dir: &PathBuf) -> Index {
-        if !index_dir.exists() {
+        let index = if !index_dir.exists() {
             std::fs::create_dir_all(&index_dir).unwrap_or_else(|e| {
                 panic!("Failed to create index directory {:?}: {}", index_dir, e)
             });
@@ -190,7 +379,9 @@ impl EnvelopeIndexManager {
                 .unwrap_or_else(|e| panic!("Failed to create index in {:?}: {}", index_dir, e))
         } else {
             open(&index_dir)
-        }
+        };
+        register_analyzers(&index);
+        index
     }
 
     pub fn total_emails(&self, accounts: &Option<HashSet<u64>>) -> BichonResult<u64> {
@@ -272,8 +463,37 @@ impl EnvelopeIndexManager {
             }
         }
 
+        subqueries.extend(self.filter_clauses(&filter, &parser, 0)?);
+        Ok(combine_clauses(subqueries))
+    }
+
+    /// The clauses `filter` adds to a query, including those of its nested groups.
+    fn filter_clauses(
+        &self,
+        filter: &SearchFilter,
+        parser: &QueryParser,
+        depth: usize,
+    ) -> BichonResult<Vec<(Occur, Box<dyn Query>)>> {
+        if depth > MAX_FILTER_DEPTH {
+            return Err(raise_error!(
+                format!(
+                    "Filter groups can be nested at most {} levels deep.",
+                    MAX_FILTER_DEPTH
+                ),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        let f = SchemaTools::envelope_fields();
+        let mut subqueries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+
         if let Some(ref text) = filter.text {
-            let query = parser
+            subqueries.push((Occur::Must, parse_search_query(text, parser)?));
+        }
+
+        if let Some(ref text) = filter.attachment_text {
+            let query = self
+                .handles()
+                .attachment_query_parser
                 .parse_query(text)
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
             subqueries.push((Occur::Must, Box::new(query)));
@@ -299,21 +519,71 @@ impl EnvelopeIndexManager {
             }
         }
 
+        for flag in filter.flags.iter().flatten() {
+            subqueries.push((Occur::Must, flag_query(flag)));
+        }
+        for (flag, set) in [("\\Seen", filter.seen), ("\\Flagged", filter.flagged)] {
+            if let Some(set) = set {
+                let occur = if set { Occur::Must } else { Occur::MustNot };
+                subqueries.push((occur, flag_query(flag)));
+            }
+        }
+        if let Some(deleted) = filter.deleted_on_server {
+            let occur = if deleted { Occur::Must } else { Occur::MustNot };
+            subqueries.push((occur, deleted_on_server_query()));
+        }
+        if filter.deleted_on_server_since.is_some() || filter.deleted_on_server_before.is_some() {
+            let bound = |at: Option<i64>| match at {
+                Some(at) => Bound::Included(Term::from_field_i64(f.f_deleted_on_server_at, at)),
+                None => Bound::Unbounded,
+            };
+            let q = RangeQuery::new(
+                bound(filter.deleted_on_server_since),
+                bound(filter.deleted_on_server_before),
+            );
+            subqueries.push((Occur::Must, Box::new(q)));
+        }
+
+        let address_match = filter.address_match.unwrap_or_default();
         for (field, opt_value) in [
-            (f.f_from, &filter.from),
-            (f.f_to, &filter.to),
-            (f.f_cc, &filter.cc),
-            (f.f_bcc, &filter.bcc),
+            (f.f_from_name_addr, &filter.from),
+            (f.f_to_name_addr, &filter.to),
+            (f.f_cc_name_addr, &filter.cc),
+            (f.f_bcc_name_addr, &filter.bcc),
         ] {
             if let Some(ref v) = opt_value {
-                let term = Term::from_field_text(field, v);
-                subqueries.push((
-                    Occur::Must,
-                    Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
-                ));
+                let query = address_query(field, v, address_match).ok_or_else(|| {
+                    raise_error!(
+                        format!("Cannot search for the address '{}'.", v),
+                        ErrorCode::InvalidParameter
+                    )
+                })?;
+                subqueries.push((Occur::Must, query));
             }
         }
 
+        for header in filter.headers.iter().flatten() {
+            let indexed = IndexedHeader::find(&header.name).ok_or_else(|| {
+                raise_error!(
+                    format!(
+                        "The header '{}' is not indexed, it can be added with --bichon-index-headers.",
+                        header.name
+                    ),
+                    ErrorCode::InvalidParameter
+                )
+            })?;
+            let query = header_query(indexed, &header.value).ok_or_else(|| {
+                raise_error!(
+                    format!(
+                        "Cannot search the header '{}' for '{}'.",
+                        header.name, header.value
+                    ),
+                    ErrorCode::InvalidParameter
+                )
+            })?;
+            subqueries.push((Occur::Must, query));
+        }
+
         if let Some(has) = filter.has_attachment {
             if has {
                 subqueries.push((
@@ -392,11 +662,28 @@ impl EnvelopeIndexManager {
             ));
         }
 
-        if subqueries.is_empty() {
-            return Ok(Box::new(AllQuery));
+        let group = |filter: &SearchFilter| -> BichonResult<Box<dyn Query>> {
+            Ok(combine_clauses(self.filter_clauses(
+                filter,
+                parser,
+                depth + 1,
+            )?))
+        };
+        for nested in filter.all_of.iter().flatten() {
+            subqueries.push((Occur::Must, group(nested)?));
+        }
+        if let Some(any_of) = filter.any_of.as_ref().filter(|any_of| !any_of.is_empty()) {
+            let mut alternatives = Vec::with_capacity(any_of.len());
+            for nested in any_of {
+                alternatives.push((Occur::Should, group(nested)?));
+            }
+            subqueries.push((Occur::Must, Box::new(BooleanQuery::new(alternatives))));
+        }
+        for nested in filter.none_of.iter().flatten() {
+            subqueries.push((Occur::MustNot, group(nested)?));
         }
 
-        Ok(Box::new(BooleanQuery::new(subqueries)))
+        Ok(subqueries)
     }
 
     fn thread_query(&self, account_id: u64, thread_id: u64) -> Box<dyn Query> {
@@ -433,7 +720,7 @@ impl EnvelopeIndexManager {
 
     pub async fn delete_account_envelopes(&self, account_id: u64) -> BichonResult<()> {
         let query = self.account_query(account_id);
-        let mut writer = self.index_writer.lock().await;
+        let mut writer = self.writer().await;
         writer
             .delete_query(query)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
@@ -443,6 +730,8 @@ impl EnvelopeIndexManager {
         Ok(())
     }
 
+    /// Removes the envelopes from the mailboxes. Envelopes that are also in
+    /// other mailboxes are kept there.
     pub async fn delete_mailbox_envelopes(
         &self,
         account_id: u64,
@@ -452,16 +741,49 @@ impl EnvelopeIndexManager {
             tracing::warn!("delete_mailbox_envelopes: mailbox_ids is empty, nothing to delete");
             return Ok(());
         }
+        let remaining = MessageLocation::remove_mailboxes(account_id, mailbox_ids.clone()).await?;
+
+        let searcher = self.create_searcher()?;
+        let f = SchemaTools::envelope_fields();
+        let mut kept = Vec::new();
+        for (eid, locations) in remaining {
+            let Some(primary) = locations.first() else {
+                continue;
+            };
+            if let Some(doc_address) = find_envelope(&searcher, account_id, eid)? {
+                let doc: TantivyDocument = searcher
+                    .doc_async(doc_address)
+                    .await
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let mailbox_ids: Vec<u64> = locations.iter().map(|l| l.mailbox_id).collect();
+                let mut new_doc = with_u64_values(
+                    &with_mailbox_ids(&doc, &mailbox_ids),
+                    f.f_uid,
+                    &[primary.uid as u64],
+                );
+                add_stemmed_fields(&mut new_doc);
+                add_header_text_fields(&mut new_doc);
+                kept.push((eid, new_doc));
+            }
+        }
+
         let mut queries: Vec<Box<dyn Query>> = Vec::with_capacity(mailbox_ids.len());
         for mailbox_id in mailbox_ids {
             queries.push(self.mailbox_query(account_id, mailbox_id));
         }
-        let mut writer = self.index_writer.lock().await;
+        let mut writer = self.writer().await;
         for query in queries {
             writer
                 .delete_query(query)
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
         }
+        // Added after the deletes, so they are not affected by them.
+        self.mark_dirty(kept.iter().map(|(eid, _)| *eid));
+        for (_, doc) in kept {
+            writer
+                .add_document(doc)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
         writer
             .commit()
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
@@ -496,7 +818,7 @@ impl EnvelopeIndexManager {
         &self,
         accounts: Option<HashSet<u64>>,
     ) -> BichonResult<Vec<TagCount>> {
-        let searcher = self.reader.searcher();
+        let searcher = self.create_searcher()?;
 
         let query: Box<dyn Query> = match accounts {
             Some(ref ids) if !ids.is_empty() => {
@@ -529,7 +851,7 @@ impl EnvelopeIndexManager {
             return Ok(());
         }
 
-        let mut writer = self.index_writer.lock().await;
+        let mut writer = self.writer().await;
 
         for (account_id, envelope_ids) in deletes {
             let unique_ids: HashSet<u64> = envelope_ids.iter().copied().collect();
@@ -560,8 +882,8 @@ impl EnvelopeIndexManager {
             return Ok(());
         }
 
+        let mut writer = self.writer().await;
         let searcher = self.create_searcher()?;
-        let mut writer = self.index_writer.lock().await;
 
         let f_tags = SchemaTools::envelope_fields().f_tags;
         let f_id = SchemaTools::envelope_fields().f_id;
@@ -594,10 +916,13 @@ impl EnvelopeIndexManager {
                     for tag in &tags {
                         new_doc.add_facet(f_tags, tag);
                     }
+                    add_stemmed_fields(&mut new_doc);
+                    add_header_text_fields(&mut new_doc);
 
                     let delete_term = Term::from_field_u64(f_id, *eid);
                     operations.push(UserOperation::Delete(delete_term));
                     operations.push(UserOperation::Add(new_doc));
+                    self.mark_dirty([*eid]);
                 }
             }
         }
@@ -614,18 +939,26 @@ impl EnvelopeIndexManager {
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn search(
         &self,
         accounts: Option<HashSet<u64>>,
         filter: SearchFilter,
         page: u64,
         page_size: u64,
-        desc: bool,
+        sort: SortOptions,
+        after: Option<PageCursor>,
+        options: ResultOptions,
     ) -> BichonResult<DataPage<Envelope>> {
         assert!(page > 0, "Page number must be greater than 0");
         assert!(page_size > 0, "Page size must be greater than 0");
-        let query = self.filter_query(accounts, filter, self.query_parser.clone())?;
+        let current_page = after.is_none().then_some(page);
+        let query = self.filter_query(accounts, filter, self.handles().query_parser)?;
         let searcher = self.create_searcher()?;
+        if options.group_by_thread {
+            return search_threads(&searcher, query.as_ref(), page, page_size, &sort, options)
+                .await;
+        }
         let total = searcher
             .search(&query, &Count)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
@@ -633,100 +966,228 @@ impl EnvelopeIndexManager {
 
         if total == 0 {
             return Ok(DataPage {
-                current_page: Some(page),
+                current_page,
                 page_size: Some(page_size),
                 total_items: 0,
                 items: vec![],
                 total_pages: Some(0),
+                next_cursor: None,
             });
         }
-        let offset = (page - 1) * page_size;
+        let offset = if after.is_some() {
+            0
+        } else {
+            (page - 1) * page_size
+        };
         let total_pages = total.div_ceil(page_size);
         if offset > total {
             return Ok(DataPage {
-                current_page: Some(page),
+                current_page,
                 page_size: Some(page_size),
                 total_items: total,
                 items: vec![],
                 total_pages: Some(total_pages),
+                next_cursor: None,
             });
         }
 
-        let order = if desc { Order::Desc } else { Order::Asc };
-        let mailbox_docs: Vec<(i64, DocAddress)> = searcher
-            .search(
-                &query,
-                &TopDocs::with_limit(page_size as usize)
-                    .and_offset(offset as usize)
-                    .order_by_fast_field(F_DATE, order),
+        let mailbox_docs = sorted_docs(
+            &searcher,
+            search_after(query.as_ref(), after, &sort).as_ref(),
+            &sort,
+            page_size as usize,
+            offset as usize,
+        )?;
+        let highlighter = if options.highlight {
+            Some(Highlighter::new(&searcher, query.as_ref())?)
+        } else {
+            None
+        };
+        let mut result = Vec::new();
+
+        for doc_address in mailbox_docs {
+            result
+                .push(search_result(&searcher, doc_address, highlighter.as_ref(), options).await?);
+        }
+        let next_cursor = next_cursor(&result, page_size, &sort);
+        Ok(DataPage {
+            current_page,
+            page_size: Some(page_size),
+            total_items: total,
+            items: result,
+            total_pages: Some(total_pages),
+            next_cursor,
+        })
+    }
+
+    /// Finds the messages most similar to one message by the words of their subject,
+    /// body and attachment names, best match first. The message itself is left out.
+    pub async fn similar(
+        &self,
+        accounts: Option<HashSet<u64>>,
+        account_id: u64,
+        message_id: u64,
+        page: u64,
+        page_size: u64,
+    ) -> BichonResult<DataPage<Envelope>> {
+        assert!(page > 0, "Page number must be greater than 0");
+        assert!(page_size > 0, "Page size must be greater than 0");
+        let f = SchemaTools::envelope_fields();
+        let searcher = self.create_searcher()?;
+        let doc_address = find_envelope(&searcher, account_id, message_id)?.ok_or_else(|| {
+            raise_error!(
+                format!(
+                    "Envelope not found: account_id={} message_id={}",
+                    account_id, message_id
+                ),
+                ErrorCode::ResourceNotFound
             )
+        })?;
+        let doc: TantivyDocument = searcher
+            .doc_async(doc_address)
+            .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let mut result = Vec::new();
+        let doc_fields = [f.f_subject, f.f_text, f.f_attachments]
+            .into_iter()
+            .map(|field| {
+                let values = doc
+                    .get_all(field)
+                    .filter_map(|v| v.as_str())
+                    .map(|v| OwnedValue::Str(v.to_string()))
+                    .collect();
+                (field, values)
+            })
+            .collect();
+        let like_this = MoreLikeThisQuery::builder()
+            .with_min_doc_frequency(SIMILAR_MIN_DOC_FREQUENCY)
+            .with_min_term_frequency(1)
+            .with_max_query_terms(SIMILAR_MAX_QUERY_TERMS)
+            .with_min_word_length(SIMILAR_MIN_WORD_LENGTH)
+            .with_document_fields(doc_fields);
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, Box::new(like_this) as Box<dyn Query>),
+            (
+                Occur::Must,
+                self.filter_query(
+                    accounts,
+                    SearchFilter::default(),
+                    self.handles().query_parser,
+                )?,
+            ),
+            (
+                Occur::MustNot,
+                Box::new(TermQuery::new(
+                    Term::from_field_u64(f.f_id, message_id),
+                    IndexRecordOption::Basic,
+                )),
+            ),
+        ]);
 
-        for (_, doc_address) in mailbox_docs {
-            let doc: TantivyDocument = searcher
-                .doc_async(doc_address)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            let envelope = Envelope::from_tantivy_doc(&doc).await?;
-            result.push(envelope);
+        let total = searcher
+            .search(&query, &Count)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            as u64;
+        let total_pages = total.div_ceil(page_size);
+        let offset = (page - 1) * page_size;
+        let mut items = Vec::new();
+        if offset < total {
+            let sort = SortOptions {
+                field: Some(SortField::Relevance),
+                ..Default::default()
+            };
+            let docs = sorted_docs(
+                &searcher,
+                &query,
+                &sort,
+                page_size as usize,
+                offset as usize,
+            )?;
+            for doc_address in docs {
+                let doc: TantivyDocument = searcher
+                    .doc_async(doc_address)
+                    .await
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                items.push(Envelope::from_tantivy_doc(&doc).await?);
+            }
         }
         Ok(DataPage {
             current_page: Some(page),
             page_size: Some(page_size),
             total_items: total,
-            items: result,
+            items,
             total_pages: Some(total_pages),
+            next_cursor: None,
         })
     }
 
+    /// Counts the envelopes matching `filter` per value of each of the `kinds`.
+    pub async fn facets(
+        &self,
+        accounts: Option<HashSet<u64>>,
+        filter: SearchFilter,
+        kinds: &[FacetKind],
+    ) -> BichonResult<(u64, FacetCounts)> {
+        let query = self.filter_query(accounts, filter, self.handles().query_parser)?;
+        let searcher = self.create_searcher()?;
+        let (total, counts) = searcher
+            .search(&query, &(Count, FacetCountCollector::new(kinds)))
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok((total as u64, counts))
+    }
+
     pub async fn list_mailbox_envelopes(
         &self,
         account_id: u64,
         mailbox_id: u64,
         page: u64,
         page_size: u64,
-        desc: bool,
+        sort: SortOptions,
+        after: Option<PageCursor>,
     ) -> BichonResult<DataPage<Envelope>> {
         assert!(page > 0, "Page number must be greater than 0");
         assert!(page_size > 0, "Page size must be greater than 0");
+        let current_page = after.is_none().then_some(page);
         let searcher = self.create_searcher()?;
         let total = self
             .num_messages_in_mailbox(&searcher, account_id, mailbox_id)
             .await?;
         if total == 0 {
             return Ok(DataPage {
-                current_page: Some(page),
+                current_page,
                 page_size: Some(page_size),
                 total_items: 0,
                 items: vec![],
                 total_pages: Some(0),
+                next_cursor: None,
             });
         }
-        let offset = (page - 1) * page_size;
+        let offset = if after.is_some() {
+            0
+        } else {
+            (page - 1) * page_size
+        };
         let total_pages = total.div_ceil(page_size);
         if offset > total {
             return Ok(DataPage {
-                current_page: Some(page),
+                current_page,
                 page_size: Some(page_size),
                 total_items: total,
                 items: vec![],
                 total_pages: Some(total_pages),
+                next_cursor: None,
             });
         }
         let query = self.mailbox_query(account_id, mailbox_id);
-        let order = if desc { Order::Desc } else { Order::Asc };
-        let mailbox_docs: Vec<(i64, DocAddress)> = searcher
-            .search(
-                query.as_ref(),
-                &TopDocs::with_limit(page_size as usize)
-                    .and_offset(offset as usize)
-                    .order_by_fast_field(F_DATE, order),
-            )
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let mailbox_docs = sorted_docs(
+            &searcher,
+            search_after(query.as_ref(), after, &sort).as_ref(),
+            &sort,
+            page_size as usize,
+            offset as usize,
+        )?;
         let mut result = Vec::new();
 
-        for (_, doc_address) in mailbox_docs {
+        for doc_address in mailbox_docs {
             let doc: TantivyDocument = searcher
                 .doc_async(doc_address)
                 .await
@@ -734,12 +1195,14 @@ impl EnvelopeIndexManager {
             let envelope = Envelope::from_tantivy_doc(&doc).await?;
             result.push(envelope);
         }
+        let next_cursor = next_cursor(&result, page_size, &sort);
         Ok(DataPage {
-            current_page: Some(page),
+            current_page,
             page_size: Some(page_size),
             total_items: total,
             items: result,
             total_pages: Some(total_pages),
+            next_cursor,
         })
     }
 
@@ -749,7 +1212,7 @@ impl EnvelopeIndexManager {
         thread_id: u64,
         page: u64,
         page_size: u64,
-        desc: bool,
+        sort: SortOptions,
     ) -> BichonResult<DataPage<Envelope>> {
         assert!(page > 0, "Page number must be greater than 0");
         assert!(page_size > 0, "Page size must be greater than 0");
@@ -764,6 +1227,7 @@ impl EnvelopeIndexManager {
                 total_items: 0,
                 items: vec![],
                 total_pages: Some(0),
+                next_cursor: None,
             });
         }
         let offset = (page - 1) * page_size;
@@ -775,23 +1239,22 @@ impl EnvelopeIndexManager {
                 total_items: total,
                 items: vec![],
                 total_pages: Some(total_pages),
+                next_cursor: None,
             });
         }
 
         let query = self.thread_query(account_id, thread_id);
 
-        let order = if desc { Order::Desc } else { Order::Asc };
-        let thread_docs: Vec<(i64, DocAddress)> = searcher
-            .search(
-                query.as_ref(),
-                &TopDocs::with_limit(page_size as usize)
-                    .and_offset(offset as usize)
-                    .order_by_fast_field(F_DATE, order),
-            )
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let thread_docs = sorted_docs(
+            &searcher,
+            query.as_ref(),
+            &sort,
+            page_size as usize,
+            offset as usize,
+        )?;
         let mut result = Vec::new();
 
-        for (_, doc_address) in thread_docs {
+        for doc_address in thread_docs {
             let doc: TantivyDocument = searcher
                 .doc_async(doc_address)
                 .await
@@ -805,41 +1268,54 @@ impl EnvelopeIndexManager {
             total_items: total,
             items: result,
             total_pages: Some(total_pages),
+            next_cursor: None,
         })
     }
 
+    /// The envelopes of a thread, oldest first, with their full body text, and
+    /// the number of envelopes in the thread. At most `limit` are returned.
+    pub async fn thread_messages(
+        &self,
+        account_id: u64,
+        thread_id: u64,
+        limit: usize,
+    ) -> BichonResult<(u64, Vec<(Envelope, String)>)> {
+        let searcher = self.create_searcher()?;
+        let total = self
+            .num_messages_in_thread(&searcher, account_id, thread_id)
+            .await?;
+        let query = self.thread_query(account_id, thread_id);
+        let sort = SortOptions {
+            field: Some(SortField::Date),
+            desc: Some(false),
+            date_decay_days: None,
+        };
+        let f_text = SchemaTools::envelope_fields().f_text;
+        let mut result = Vec::new();
+        for doc_address in sorted_docs(&searcher, query.as_ref(), &sort, limit, 0)? {
+            let doc: TantivyDocument = searcher
+                .doc_async(doc_address)
+                .await
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let text = doc
+                .get_first(f_text)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+            result.push((Envelope::from_tantivy_doc(&doc).await?, text));
+        }
+        Ok((total, result))
+    }
+
     pub async fn get_envelope_by_id(
         &self,
         account_id: u64,
         message_id: u64,
     ) -> BichonResult<Option<Envelope>> {
         let searcher = self.create_searcher()?;
-        let f = SchemaTools::envelope_fields();
-
-        let query = BooleanQuery::new(vec![
-            (
-                Occur::Must,
-                Box::new(TermQuery::new(
-                    Term::from_field_u64(f.f_account_id, account_id),
-                    IndexRecordOption::Basic,
-                )),
-            ),
-            (
-                Occur::Must,
-                Box::new(TermQuery::new(
-                    Term::from_field_u64(f.f_id, message_id),
-                    IndexRecordOption::Basic,
-                )),
-            ),
-        ]);
-
-        let docs: Vec<(f32, DocAddress)> = searcher
-            .search(&query, &TopDocs::with_limit(1))
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-
-        if let Some((_, doc_address)) = docs.first() {
+        if let Some(doc_address) = find_envelope(&searcher, account_id, message_id)? {
             let doc: TantivyDocument = searcher
-                .doc_async(*doc_address)
+                .doc_async(doc_address)
                 .await
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
             let envelope = Envelope::from_tantivy_doc(&doc).await?;
@@ -854,10 +1330,7 @@ impl EnvelopeIndexManager {
         &self,
         accounts: &Option<HashSet<u64>>,
     ) -> BichonResult<Vec<LargestEmail>> {
-        self.reader
-            .reload()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let searcher = self.reader.searcher();
+        let searcher = self.create_searcher()?;
 
         let query: Box<dyn Query> = match accounts {
             Some(ref ids) if !ids.is_empty() => {
@@ -895,41 +1368,6 @@ impl EnvelopeIndexManager {
         Ok(result)
     }
 
-    pub async fn get_max_uid(&self, account_id: u64, mailbox_id: u64) -> BichonResult<Option<u64>> {
-        self.reader
-            .reload()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let searcher = self.reader.searcher();
-
-        let query = self.mailbox_query(account_id, mailbox_id);
-        let agg_req: Aggregations = serde_json::from_value(json!({
-            "max_uid": {
-                "max": {
-                    "field": F_UID
-                }
-            }
-        }))
-        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-
-        let collector = AggregationCollector::from_aggs(agg_req, Default::default());
-
-        let agg_res = searcher
-            .search(query.as_ref(), &collector)
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(Self::extract_max_uid(&agg_res))
-    }
-
-    fn extract_max_uid(agg_res: &AggregationResults) -> Option<u64> {
-        agg_res.0.get("max_uid").and_then(|result| match result {
-            AggregationResult::MetricResult(MetricResult::Max(max)) => {
-                max.value.and_then(|value| {
-                    (value >= 0.0 && value <= u64::MAX as f64).then(|| value.trunc() as u64)
-                })
-            }
-            _ => None,
-        })
-    }
-
     pub async fn num_messages_in_mailbox(
         &self,
         searcher: &Searcher,
@@ -937,46 +1375,10 @@ impl EnvelopeIndexManager {
         mailbox_id: u64,
     ) -> BichonResult<u64> {
         let query = self.mailbox_query(account_id, mailbox_id);
-
-        let agg_req: Aggregations = serde_json::from_value(json!({
-            "mailbox_count": {
-                "value_count": {
-                    "field": F_MAILBOX_ID
-                }
-            }
-        }))
-        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-
-        let collector = AggregationCollector::from_aggs(agg_req, Default::default());
-
-        let agg_res = searcher
-            .search(query.as_ref(), &collector)
+        let count = searcher
+            .search(query.as_ref(), &Count)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Self::extract_mailbox_count(&agg_res)
-    }
-
-    fn extract_mailbox_count(agg_res: &AggregationResults) -> BichonResult<u64> {
-        let Some(result) = agg_res.0.get("mailbox_count") else {
-            return Err(raise_error!(
-                "Missing aggregation result: mailbox_count".into(),
-                ErrorCode::InternalError
-            ));
-        };
-
-        match result {
-            AggregationResult::MetricResult(MetricResult::Count(count)) => {
-                Ok(count.value.map(|v| v as u64).ok_or_else(|| {
-                    raise_error!(
-                        "Failed to get count value from aggregation result: value is None".into(),
-                        ErrorCode::InternalError
-                    )
-                })?)
-            }
-            other => Err(raise_error!(
-                format!("Unexpected aggregation result type: {other:?}"),
-                ErrorCode::InternalError
-            )),
-        }
+        Ok(count as u64)
     }
 
     pub async fn num_messages_in_thread(
@@ -1027,11 +1429,152 @@ impl EnvelopeIndexManager {
         }
     }
 
-    fn create_searcher(&self) -> BichonResult<Searcher> {
-        self.reader
+    fn mark_dirty(&self, ids: impl IntoIterator<Item = u64>) {
+        if let Some(dirty) = self
+            .reindex_dirty
+            .lock()
+            .expect("reindex dirty set lock poisoned")
+            .as_mut()
+        {
+            dirty.extend(ids);
+        }
+    }
+
+    /// Starts or stops recording the envelopes written to the live index, so a
+    /// reindex can catch up with changes made while it was running.
+    pub(crate) fn track_reindex(&self, enabled: bool) {
+        *self
+            .reindex_dirty
+            .lock()
+            .expect("reindex dirty set lock poisoned") = enabled.then(HashSet::new);
+    }
+
+    /// Brings a freshly built index up to date with the live index and swaps it
+    /// into place.
+    ///
+    /// `rebuilt` maps every envelope written to the new index to whether it was
+    /// present in the live index at that time. While this runs, writes to the
+    /// live index are blocked: envelopes written since they were rebuilt are
+    /// copied over from the live index, and rebuilt envelopes that have since
+    /// been deleted are removed again.
+    pub(crate) async fn swap_in_reindexed(
+        &self,
+        reindexed: Index,
+        mut writer: IndexWriter,
+        rebuilt: &HashMap<u64, bool>,
+        reindex_dir: &Path,
+    ) -> BichonResult<()> {
+        let mut live_writer = self.index_writer.lock().await;
+        let dirty = self
+            .reindex_dirty
+            .lock()
+            .expect("reindex dirty set lock poisoned")
+            .take()
+            .unwrap_or_default();
+        let f_id = SchemaTools::envelope_fields().f_id;
+
+        let live = self.create_searcher()?;
+        let mut live_ids = HashSet::new();
+        for segment_reader in live.segment_readers() {
+            let ids = segment_reader
+                .fast_fields()
+                .u64(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let store_reader = segment_reader
+                .get_store_reader(1)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            for doc_id in segment_reader.doc_ids_alive() {
+                let Some(id) = ids.first(doc_id) else {
+                    continue;
+                };
+                live_ids.insert(id);
+                if rebuilt.contains_key(&id) && !dirty.contains(&id) {
+                    continue;
+                }
+                let doc: TantivyDocument = store_reader
+                    .get(doc_id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let mut copy = TantivyDocument::new();
+                for (field, value) in doc.field_values() {
+                    copy.add_field_value(field, value);
+                }
+                add_stemmed_fields(&mut copy);
+                add_header_text_fields(&mut copy);
+                writer.delete_term(Term::from_field_u64(f_id, id));
+                writer
+                    .add_document(copy)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+        }
+        for (id, was_live) in rebuilt {
+            if *was_live && !live_ids.contains(id) {
+                writer.delete_term(Term::from_field_u64(f_id, *id));
+            }
+        }
+        writer
+            .commit()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        writer
+            .wait_merging_threads()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        drop(reindexed);
+
+        // The old writer must be gone before its directory is moved, it cleans
+        // up files relative to the index path when dropped.
+        drop(live_writer.take());
+        let mut handles = self
+            .handles
+            .write()
+            .expect("envelope index handles lock poisoned");
+        let envelope_dir = &DATA_DIR_MANAGER.envelope_dir;
+        let previous_dir = DATA_DIR_MANAGER.root_dir.join(PREVIOUS_DIR);
+        let swapped = std::fs::rename(envelope_dir, &previous_dir).and_then(|_| {
+            std::fs::rename(reindex_dir, envelope_dir).inspect_err(|_| {
+                let _ = std::fs::rename(&previous_dir, envelope_dir);
+            })
+        });
+
+        // Whatever is in place now has to be opened again, even if the swap failed.
+        let index = Self::open_or_create_index(envelope_dir);
+        *live_writer = Some(Self::create_writer(&index).unwrap_or_else(|e| {
+            panic!("Failed to create IndexWriter for {:?}: {}", envelope_dir, e)
+        }));
+        *handles = IndexHandles::new(&index).unwrap_or_else(|e| {
+            panic!("Failed to create IndexReader for {:?}: {}", envelope_dir, e)
+        });
+        drop(handles);
+        drop(live_writer);
+
+        swapped.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        if let Err(e) = std::fs::remove_dir_all(&previous_dir) {
+            tracing::warn!(
+                "Failed to remove previous envelope index {:?}: {:#?}",
+                previous_dir,
+                e
+            );
+        }
+        Ok(())
+    }
+
+    fn handles(&self) -> IndexHandles {
+        self.handles
+            .read()
+            .expect("envelope index handles lock poisoned")
+            .clone()
+    }
+
+    pub(crate) fn create_searcher(&self) -> BichonResult<Searcher> {
+        // Reload under the read lock, so a reader never picks up the files of an
+        // index that is being swapped in.
+        let handles = self
+            .handles
+            .read()
+            .expect("envelope index handles lock poisoned");
+        handles
+            .reader
             .reload()
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(self.reader.searcher())
+        Ok(handles.reader.searcher())
     }
 
     pub async fn get_dashboard_stats(
@@ -1190,325 +1733,85 @@ impl EnvelopeIndexManager {
     }
 }
 
-pub struct EmlIndexManager {
-    index_writer: Arc<Mutex<IndexWriter>>,
-    sender: mpsc::Sender<WriteMessage>,
-    reader: IndexReader,
+fn with_thread_id(doc: &TantivyDocument, thread_id: u64) -> TantivyDocument {
+    with_u64_values(
+        doc,
+        SchemaTools::envelope_fields().f_thread_id,
+        &[thread_id],
+    )
 }
 
-impl EmlIndexManager {
-    pub fn new() -> Self {
-        let index = Self::open_or_create_index(&DATA_DIR_MANAGER.eml_dir);
-        let index_writer = Arc::new(Mutex::new(
-            index
-                .writer_with_num_threads(8, 536_870_912)
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
-                        DATA_DIR_MANAGER.eml_dir, e
-                    )
-                }),
-        ));
-        let reader = index.reader().unwrap_or_else(|e| {
-            panic!(
-                "Failed to create IndexReader for {:?}: {}",
-                DATA_DIR_MANAGER.eml_dir, e
-            )
-        });
-        let (sender, mut receiver) = mpsc::channel::<WriteMessage>(100);
-        task::spawn(async move {
-            let mut buffer: HashMap<u64, TantivyDocument> = HashMap::with_capacity(EML_BATCH_SIZE);
-            let mut interval = tokio::time::interval(MAX_BUFFER_DURATION);
-            let mut shutdown = SIGNAL_MANAGER.subscribe();
-            loop {
-                tokio::select! {
-                    maybe_msg = receiver.recv() => {
-                        match maybe_msg {
-                            Some(WriteMessage::Document((eid, doc))) => {
-                                buffer.insert(eid, doc);
-                                if buffer.len() >= EML_BATCH_SIZE {
-                                    EML_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
-                                }
-                            }
-                            Some(WriteMessage::Shutdown) => {
-                                EML_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
-                                break;
-                            }
-                            None => break,
-                        }
-                    }
-                    _ = interval.tick() => {
-                        if !buffer.is_empty() {
-                            EML_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
-                        }
-                    }
-                    _ = shutdown.recv() => {
-                        let _ = EML_INDEX_MANAGER.sender.send(WriteMessage::Shutdown).await;
-                    }
-                }
-            }
-        });
-        Self {
-            index_writer,
-            sender,
-            reader,
-        }
-    }
-
-    pub async fn add_document(&self, eid: u64, doc: TantivyDocument) {
-        let _ = self.sender.send(WriteMessage::Document((eid, doc))).await;
-    }
-
-    fn open_or_create_index(index_dir: &PathBuf) -> Index {
-        if !index_dir.exists() {
-            std::fs::create_dir_all(&index_dir).unwrap_or_else(|e| {
-                panic!("Failed to create index directory {:?}: {}", index_dir, e)
-            });
-            IndexBuilder::new()
-                .schema(SchemaTools::eml_schema())
-                .settings(IndexSettings {
-                    docstore_compression: Compressor::Zstd(ZstdCompressor {
-                        compression_level: Some(6),
-                    }),
-                    docstore_compress_dedicated_thread: true,
-                    docstore_blocksize: 2_097_152,
-                })
-                .create_in_dir(&index_dir)
-                .unwrap_or_else(|e| panic!("Failed to create index in {:?}: {}", index_dir, e))
-        } else {
-            open(&index_dir)
-        }
-    }
-
-    fn envelope_query(&self, account_id: u64, eid: u64) -> Box<dyn Query> {
-        let account_id_query = TermQuery::new(
-            Term::from_field_u64(SchemaTools::eml_fields().f_account_id, account_id),
-            IndexRecordOption::Basic,
-        );
-        let envelope_id_query = TermQuery::new(
-            Term::from_field_u64(SchemaTools::eml_fields().f_id, eid),
-            IndexRecordOption::Basic,
-        );
-        let boolean_query = BooleanQuery::new(vec![
-            (Occur::Must, Box::new(account_id_query)),
-            (Occur::Must, Box::new(envelope_id_query)),
-        ]);
-        Box::new(boolean_query)
-    }
-
-    pub async fn get(&self, account_id: u64, eid: u64) -> BichonResult<Option<Vec<u8>>> {
-        let searcher = self.reader.searcher();
-        let query = self.envelope_query(account_id, eid);
-        let docs = searcher
-            .search(query.as_ref(), &TopDocs::with_limit(1))
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-
-        if docs.is_empty() {
-            return Ok(None);
+fn with_flags(doc: &TantivyDocument, flags: &[String]) -> TantivyDocument {
+    let f_flags = SchemaTools::envelope_fields().f_flags;
+    let mut new_doc = TantivyDocument::new();
+    for (field, value) in doc.field_values() {
+        if field != f_flags {
+            new_doc.add_field_value(field, value);
         }
-
-        let (_, doc_address) = docs.first().unwrap();
-        let doc: TantivyDocument = searcher
-            .doc_async(*doc_address)
-            .await
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let fields = SchemaTools::eml_fields();
-        let value = doc.get_first(fields.f_eml).ok_or_else(|| {
-            raise_error!(
-                format!("miss '{}' field in tantivy document", stringify!(field)),
-                ErrorCode::InternalError
-            )
-        })?;
-        let bytes = value.as_bytes().ok_or_else(|| {
-            raise_error!(
-                format!("'{}' field is not a bytes", stringify!(field)),
-                ErrorCode::InternalError
-            )
-        })?;
-
-        Ok(Some(bytes.to_vec()))
     }
-
-    pub async fn get_reader(&self, account_id: u64, eid: u64) -> BichonResult<File> {
-        let data = self.get(account_id, eid).await?.ok_or_else(|| {
-            raise_error!(
-                format!("Email not found: account_id={}, eid={}", account_id, eid),
-                ErrorCode::ResourceNotFound
-            )
-        })?;
-        let mut path = DATA_DIR_MANAGER.temp_dir.clone();
-
-        path.push(format!("{eid}.eml"));
-        {
-            let mut file = File::create(&path)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            file.write_all(&data)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        }
-        let file = File::open(&path)
-            .await
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(file)
+    for flag in flags {
+        new_doc.add_text(f_flags, flag);
     }
+    new_doc
+}
 
-    pub async fn get_attachment(
-        &self,
-        account_id: u64,
-        eid: u64,
-        file_name: &str,
-    ) -> BichonResult<File> {
-        let data = self.get(account_id, eid).await?.ok_or_else(|| {
-            raise_error!(
-                format!("Email not found: account_id={}, eid={}", account_id, eid),
-                ErrorCode::ResourceNotFound
-            )
-        })?;
-        let message = MessageParser::default().parse(&data).ok_or_else(|| {
-            raise_error!(
-                format!(
-                    "Failed to parse email: account_id={}, eid={}",
-                    account_id, eid
-                ),
-                ErrorCode::InternalError
-            )
-        })?;
-        let target_attachment = message
-            .attachments()
-            .find(|p| p.attachment_name().is_some_and(|name| name == file_name));
-        let content = match target_attachment {
-            Some(att) => att.contents(),
-            None => {
-                return Err(raise_error!(
-                    "Attachment not found".into(),
-                    ErrorCode::ResourceNotFound
-                ))
-            }
-        };
-        let mut path = DATA_DIR_MANAGER.temp_dir.clone();
-        path.push(format!("{eid}.{file_name}.eml"));
-        {
-            let mut file = File::create(&path)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            file.write_all(content)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+fn with_label_tags(doc: &TantivyDocument, tags: &[String]) -> TantivyDocument {
+    let f_tags = SchemaTools::envelope_fields().f_tags;
+    let mut new_doc = TantivyDocument::new();
+    for (field, value) in doc.field_values() {
+        if field != f_tags || !value.as_facet().is_some_and(is_label_facet) {
+            new_doc.add_field_value(field, value);
         }
-        let file = File::open(&path)
-            .await
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(file)
     }
-
-    fn account_query(&self, account_id: u64) -> Box<TermQuery> {
-        let account_term = Term::from_field_u64(SchemaTools::eml_fields().f_account_id, account_id);
-        Box::new(TermQuery::new(account_term, IndexRecordOption::Basic))
-    }
-
-    pub async fn delete_account_envelopes(&self, account_id: u64) -> BichonResult<()> {
-        let query = self.account_query(account_id);
-        let mut writer = self.index_writer.lock().await;
-        writer
-            .delete_query(query)
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        writer
-            .commit()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(())
-    }
-
-    fn mailbox_query(&self, account_id: u64, mailbox_id: u64) -> Box<dyn Query> {
-        let account_query = TermQuery::new(
-            Term::from_field_u64(SchemaTools::eml_fields().f_account_id, account_id),
-            IndexRecordOption::Basic,
-        );
-        let mailbox_query = TermQuery::new(
-            Term::from_field_u64(SchemaTools::eml_fields().f_mailbox_id, mailbox_id),
-            IndexRecordOption::Basic,
-        );
-        let boolean_query = BooleanQuery::new(vec![
-            (Occur::Must, Box::new(account_query)),
-            (Occur::Must, Box::new(mailbox_query)),
-        ]);
-        Box::new(boolean_query)
+    for tag in tags {
+        new_doc.add_facet(f_tags, tag.as_str());
     }
+    new_doc
+}
 
-    pub async fn delete_mailbox_envelopes(
-        &self,
-        account_id: u64,
-        mailbox_ids: Vec<u64>,
-    ) -> BichonResult<()> {
-        if mailbox_ids.is_empty() {
-            tracing::warn!("delete_mailbox_envelopes: mailbox_ids is empty, nothing to delete");
-            return Ok(());
+fn with_deleted_on_server_at(doc: &TantivyDocument, at: i64) -> TantivyDocument {
+    let f_deleted_on_server_at = SchemaTools::envelope_fields().f_deleted_on_server_at;
+    let mut new_doc = TantivyDocument::new();
+    for (field, value) in doc.field_values() {
+        if field != f_deleted_on_server_at {
+            new_doc.add_field_value(field, value);
         }
-        let mut queries: Vec<Box<dyn Query>> = Vec::with_capacity(mailbox_ids.len());
-        for mailbox_id in mailbox_ids {
-            queries.push(self.mailbox_query(account_id, mailbox_id));
-        }
-        let mut writer = self.index_writer.lock().await;
-        for query in queries {
-            writer
-                .delete_query(query)
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        }
-        writer
-            .commit()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(())
     }
+    new_doc.add_i64(f_deleted_on_server_at, at);
+    new_doc
+}
 
-    pub async fn delete_email_multi_account(
-        &self,
-        deletes: &HashMap<u64, Vec<u64>>, // HashMap<account_id, envelope_ids>
-    ) -> BichonResult<()> {
-        if deletes.is_empty() {
-            tracing::warn!("delete_envelopes_multi_account: deletes is empty, nothing to delete");
-            return Ok(());
-        }
-
-        let mut writer = self.index_writer.lock().await;
+/// Sets the mailboxes of an envelope. The first mailbox stays first if the
+/// envelope is still in it, since `uid` belongs to it.
+fn with_mailbox_ids(doc: &TantivyDocument, mailbox_ids: &[u64]) -> TantivyDocument {
+    let f_mailbox_id = SchemaTools::envelope_fields().f_mailbox_id;
+    let primary = doc
+        .get_first(f_mailbox_id)
+        .and_then(|v| v.as_u64())
+        .filter(|id| mailbox_ids.contains(id));
+    let values: Vec<u64> = primary
+        .into_iter()
+        .chain(
+            mailbox_ids
+                .iter()
+                .copied()
+                .filter(|id| Some(*id) != primary),
+        )
+        .collect();
+    with_u64_values(doc, f_mailbox_id, &values)
+}
 
-        for (account_id, envelope_ids) in deletes {
-            let unique_ids: HashSet<u64> = envelope_ids.iter().copied().collect();
-            if unique_ids.is_empty() {
-                continue;
-            }
-            for eid in unique_ids {
-                let query = self.envelope_query(*account_id, eid);
-                writer
-                    .delete_query(query)
-                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            }
+fn with_u64_values(doc: &TantivyDocument, field: Field, values: &[u64]) -> TantivyDocument {
+    let mut new_doc = TantivyDocument::new();
+    for (f, value) in doc.field_values() {
+        if f != field {
+            new_doc.add_field_value(f, value);
         }
-        writer
-            .commit()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-
-        Ok(())
     }
-
-    async fn drain_and_commit(&self, buffer: &mut HashMap<u64, TantivyDocument>) {
-        if buffer.is_empty() {
-            return;
-        }
-        let mut writer = self.index_writer.lock().await;
-        let mut operations = Vec::new();
-
-        for (eid, doc) in buffer.drain() {
-            let delete_term = Term::from_field_u64(SchemaTools::eml_fields().f_id, eid);
-            operations.push(UserOperation::Delete(delete_term));
-            operations.push(UserOperation::Add(doc));
-        }
-        if let Err(e) = writer.run(operations) {
-            eprintln!("[FATAL] Tantivy run failed: {e:?}");
-            std::process::exit(1);
-        }
-
-        fatal_commit(&mut writer);
+    for value in values {
+        new_doc.add_u64(field, *value);
     }
+    new_doc
 }
 
 fn fatal_commit(writer: &mut IndexWriter) {
@@ -1554,7 +1857,454 @@ fn fatal_commit(writer: &mut IndexWriter) {
     }
 }
 
+/// Joins filter clauses into one query. No clauses match everything, and neither do
+/// exclusions alone, which would match nothing in tantivy.
+fn combine_clauses(mut clauses: Vec<(Occur, Box<dyn Query>)>) -> Box<dyn Query> {
+    if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+        clauses.push((Occur::Must, Box::new(AllQuery)));
+    }
+    if clauses.len() == 1 {
+        return clauses.pop().expect("one clause").1;
+    }
+    Box::new(BooleanQuery::new(clauses))
+}
+
+/// The address of the envelope `message_id` of an account.
+fn find_envelope(
+    searcher: &Searcher,
+    account_id: u64,
+    message_id: u64,
+) -> BichonResult<Option<DocAddress>> {
+    let f = SchemaTools::envelope_fields();
+    let query = BooleanQuery::new(vec![
+        (
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(f.f_account_id, account_id),
+                IndexRecordOption::Basic,
+            )),
+        ),
+        (
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(f.f_id, message_id),
+                IndexRecordOption::Basic,
+            )),
+        ),
+    ]);
+    let docs: Vec<(f32, DocAddress)> = searcher
+        .search(&query, &TopDocs::with_limit(1))
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(docs.first().map(|(_, doc_address)| *doc_address))
+}
+
+/// Runs `query` and returns one page of matches in the requested order.
+fn sorted_docs(
+    searcher: &Searcher,
+    query: &dyn Query,
+    sort: &SortOptions,
+    limit: usize,
+    offset: usize,
+) -> BichonResult<Vec<DocAddress>> {
+    fn addresses<T>(docs: Vec<(T, DocAddress)>) -> Vec<DocAddress> {
+        docs.into_iter().map(|(_, address)| address).collect()
+    }
+
+    let top_docs = TopDocs::with_limit(limit).and_offset(offset);
+    let order = if sort.desc() { Order::Desc } else { Order::Asc };
+    let docs = match (sort.field(), sort.date_decay_days) {
+        (SortField::Relevance, None) => searcher.search(query, &top_docs).map(addresses),
+        (SortField::Relevance, Some(half_life_days)) => {
+            let now = utc_now!();
+            let half_life_ms = half_life_days as f64 * 86_400_000.0;
+            let collector = top_docs.tweak_score(move |segment_reader: &SegmentReader| {
+                let dates = segment_reader.fast_fields().i64(F_DATE).ok();
+                move |doc: DocId, score: Score| {
+                    let age_ms = dates
+                        .as_ref()
+                        .and_then(|dates| dates.first(doc))
+                        .map(|date| (now - date).max(0) as f64)
+                        .unwrap_or(f64::INFINITY);
+                    score * (1.0 + 0.5f64.powf(age_ms / half_life_ms)) as Score
+                }
+            });
+            searcher.search(query, &collector).map(addresses)
+        }
+        (SortField::Date, _) => {
+            // Ties are broken by id so that `search_after` continues exactly
+            // where the page ended.
+            let desc = sort.desc();
+            let collector = top_docs.custom_score(move |segment_reader: &SegmentReader| {
+                let dates = segment_reader.fast_fields().i64(F_DATE).ok();
+                let ids = segment_reader.fast_fields().u64(F_ID).ok();
+                move |doc: DocId| {
+                    let date = dates.as_ref().and_then(|dates| dates.first(doc));
+                    let id = ids.as_ref().and_then(|ids| ids.first(doc));
+                    // Map the date onto u64 keeping its order, so both keys can be inverted.
+                    let key = (date.unwrap_or(i64::MIN) as u64 ^ (1 << 63), id.unwrap_or(0));
+                    if desc {
+                        key
+                    } else {
+                        (!key.0, !key.1)
+                    }
+                }
+            });
+            searcher.search(query, &collector).map(addresses)
+        }
+        (SortField::InternalDate, _) => searcher
+            .search(
+                query,
+                &top_docs.order_by_fast_field::<i64>(F_INTERNAL_DATE, order),
+            )
+            .map(addresses),
+        (SortField::Size, _) => searcher
+            .search(query, &top_docs.order_by_fast_field::<u64>(F_SIZE, order))
+            .map(addresses),
+        (SortField::Sender, _) => searcher
+            .search(query, &top_docs.order_by_string_fast_field(F_FROM, order))
+            .map(addresses),
+    };
+    docs.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+}
+
+/// Restricts `query` to the envelopes that come after `after` in `Date` order.
+fn search_after(
+    query: &dyn Query,
+    after: Option<PageCursor>,
+    sort: &SortOptions,
+) -> Box<dyn Query> {
+    let Some(after) = after else {
+        return query.box_clone();
+    };
+    let f = SchemaTools::envelope_fields();
+    let date = |bound: fn(Term) -> Bound<Term>| bound(Term::from_field_i64(f.f_date, after.date));
+    let id = |bound: fn(Term) -> Bound<Term>| bound(Term::from_field_u64(f.f_id, after.id));
+    let (dates_after, ids_after): (Box<dyn Query>, Box<dyn Query>) = if sort.desc() {
+        (
+            Box::new(RangeQuery::new(Bound::Unbounded, date(Bound::Excluded))),
+            Box::new(RangeQuery::new(Bound::Unbounded, id(Bound::Excluded))),
+        )
+    } else {
+        (
+            Box::new(RangeQuery::new(date(Bound::Excluded), Bound::Unbounded)),
+            Box::new(RangeQuery::new(id(Bound::Excluded), Bound::Unbounded)),
+        )
+    };
+    let same_date = BooleanQuery::new(vec![
+        (
+            Occur::Must,
+            Box::new(RangeQuery::new(
+                date(Bound::Included),
+                date(Bound::Included),
+            )) as Box<dyn Query>,
+        ),
+        (Occur::Must, ids_after),
+    ]);
+    Box::new(BooleanQuery::new(vec![
+        (Occur::Must, query.box_clone()),
+        (
+            Occur::Must,
+            Box::new(BooleanQuery::new(vec![
+                (Occur::Should, dates_after),
+                (Occur::Should, Box::new(same_date) as Box<dyn Query>),
+            ])),
+        ),
+    ]))
+}
+
+/// Like `EnvelopeIndexManager::search`, but returns only the best match of each
+/// thread, in the requested order, and pages over the threads.
+async fn search_threads(
+    searcher: &Searcher,
+    query: &dyn Query,
+    page: u64,
+    page_size: u64,
+    sort: &SortOptions,
+    options: ResultOptions,
+) -> BichonResult<DataPage<Envelope>> {
+    let counts = searcher
+        .search(query, &ThreadCountCollector)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let total = counts.len() as u64;
+    let offset = (page - 1) * page_size;
+    let wanted = (offset + page_size).min(total) as usize;
+    let matches = counts.values().sum::<u64>() as usize;
+
+    // Walk the matches in order and keep the first one of every thread.
+    let mut seen = HashSet::new();
+    let mut picked = Vec::new();
+    let batch = (wanted * 2).max(ENVELOPE_BATCH_SIZE);
+    let mut batch_offset = 0;
+    while seen.len() < wanted && batch_offset < matches {
+        for doc_address in sorted_docs(searcher, query, sort, batch, batch_offset)? {
+            let key = thread_key(searcher, doc_address)?;
+            if seen.insert(key) && seen.len() as u64 > offset {
+                picked.push((doc_address, key));
+            }
+            if seen.len() >= wanted {
+                break;
+            }
+        }
+        batch_offset += batch;
+    }
+
+    let highlighter = if options.highlight && !picked.is_empty() {
+        Some(Highlighter::new(searcher, query)?)
+    } else {
+        None
+    };
+    let mut items = Vec::with_capacity(picked.len());
+    for (doc_address, key) in picked {
+        let mut envelope =
+            search_result(searcher, doc_address, highlighter.as_ref(), options).await?;
+        envelope.thread_matches = counts.get(&key).copied();
+        items.push(envelope);
+    }
+    Ok(DataPage {
+        current_page: Some(page),
+        page_size: Some(page_size),
+        total_items: total,
+        items,
+        total_pages: Some(total.div_ceil(page_size)),
+        next_cursor: None,
+    })
+}
+
+async fn search_result(
+    searcher: &Searcher,
+    doc_address: DocAddress,
+    highlighter: Option<&Highlighter>,
+    options: ResultOptions,
+) -> BichonResult<Envelope> {
+    let doc: TantivyDocument = searcher
+        .doc_async(doc_address)
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let mut envelope = Envelope::from_tantivy_doc(&doc).await?;
+    if let Some(highlighter) = highlighter {
+        envelope.highlights = Some(highlighter.highlights(&doc));
+    }
+    if options.exclude_text {
+        envelope.text.clear();
+    }
+    Ok(envelope)
+}
+
+/// The cursor of the page after `items`, if it was a full page in `Date` order.
+fn next_cursor(items: &[Envelope], page_size: u64, sort: &SortOptions) -> Option<String> {
+    if sort.field() != SortField::Date || (items.len() as u64) < page_size {
+        return None;
+    }
+    items.last().map(|last| {
+        PageCursor {
+            date: last.date,
+            id: last.id,
+        }
+        .encode()
+    })
+}
+
 fn open(index_dir: &PathBuf) -> Index {
     Index::open_in_dir(index_dir)
         .unwrap_or_else(|e| panic!("Failed to open index in {:?}: {}", index_dir, e))
 }
+
+/// Builds the highlighted subject and body snippets of search results.
+struct Highlighter {
+    subject: SnippetGenerator,
+    text: SnippetGenerator,
+}
+
+impl Highlighter {
+    /// Subjects are short, so they are returned whole rather than cut to a fragment.
+    const MAX_SUBJECT_CHARS: usize = 1000;
+    const MAX_TEXT_CHARS: usize = 200;
+
+    fn new(searcher: &Searcher, query: &dyn Query) -> BichonResult<Self> {
+        let fields = SchemaTools::envelope_fields();
+        let generator = |field, max_num_chars| {
+            SnippetGenerator::create(searcher, query, field)
+                .map(|mut generator| {
+                    generator.set_max_num_chars(max_num_chars);
+                    generator
+                })
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+        };
+        Ok(Self {
+            subject: generator(fields.f_subject, Self::MAX_SUBJECT_CHARS)?,
+            text: generator(fields.f_text, Self::MAX_TEXT_CHARS)?,
+        })
+    }
+
+    fn highlights(&self, doc: &TantivyDocument) -> Highlights {
+        let html = |mut snippet: Snippet| {
+            if snippet.highlighted().is_empty() {
+                return None;
+            }
+            snippet.set_snippet_prefix_postfix("<mark>", "</mark>");
+            Some(snippet.to_html())
+        };
+        Highlights {
+            subject: html(self.subject.snippet_from_doc(doc)),
+            text: html(self.text.snippet_from_doc(doc)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::settings::cli::use_test_root_dir;
+
+    fn date_order(desc: bool) -> SortOptions {
+        SortOptions {
+            field: Some(SortField::Date),
+            desc: Some(desc),
+            date_decay_days: None,
+        }
+    }
+
+    /// Follows the cursors from the first page to the last, as a client would.
+    fn page_through(searcher: &Searcher, sort: &SortOptions, page_size: u64) -> Vec<u64> {
+        let f = SchemaTools::envelope_fields();
+        let mut after = None;
+        let mut ids = Vec::new();
+        loop {
+            let query = search_after(&AllQuery, after, sort);
+            let items: Vec<Envelope> =
+                sorted_docs(searcher, query.as_ref(), sort, page_size as usize, 0)
+                    .unwrap()
+                    .into_iter()
+                    .map(|address| {
+                        let doc: TantivyDocument = searcher.doc(address).unwrap();
+                        Envelope {
+                            id: doc.get_first(f.f_id).and_then(|v| v.as_u64()).unwrap(),
+                            date: doc.get_first(f.f_date).and_then(|v| v.as_i64()).unwrap(),
+                            ..Default::default()
+                        }
+                    })
+                    .collect();
+            ids.extend(items.iter().map(|e| e.id));
+            match next_cursor(&items, page_size, sort) {
+                Some(cursor) => after = Some(PageCursor::decode(&cursor, sort).unwrap()),
+                None => return ids,
+            }
+        }
+    }
+
+    /// A manager over `index` that searches only, without a writer task.
+    fn search_manager(index: &Index) -> EnvelopeIndexManager {
+        EnvelopeIndexManager {
+            index_writer: Arc::new(Mutex::new(None)),
+            sender: mpsc::channel(1).0,
+            handles: RwLock::new(IndexHandles::new(index).unwrap()),
+            reindex_dirty: StdMutex::new(None),
+        }
+    }
+
+    #[test]
+    fn test_cursor_pages_through_equal_dates() {
+        let index = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_analyzers(&index);
+        let f = SchemaTools::envelope_fields();
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+        // Two segments, so that ties are also broken across segments.
+        for batch in [[(4, 50), (2, 100), (5, 50)], [(1, 100), (6, 10), (3, 100)]] {
+            for (id, date) in batch {
+                let mut doc = TantivyDocument::new();
+                doc.add_u64(f.f_id, id);
+                doc.add_i64(f.f_date, date);
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        let searcher = index.reader().unwrap().searcher();
+
+        for page_size in [1, 2, 4, 6] {
+            assert_eq!(
+                page_through(&searcher, &date_order(true), page_size),
+                vec![3, 2, 1, 5, 4, 6]
+            );
+            assert_eq!(
+                page_through(&searcher, &date_order(false), page_size),
+                vec![6, 4, 5, 1, 2, 3]
+            );
+        }
+    }
+
+    #[test]
+    fn test_nested_filter_groups() {
+        use_test_root_dir();
+        let index = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_analyzers(&index);
+        let f = SchemaTools::envelope_fields();
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+        for (id, mailbox_id, size) in [(1, 1, 100), (2, 2, 100), (3, 3, 5_000), (4, 1, 5_000)] {
+            let mut doc = TantivyDocument::new();
+            doc.add_u64(f.f_id, id);
+            doc.add_u64(f.f_mailbox_id, mailbox_id);
+            doc.add_u64(f.f_size, size);
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        let manager = search_manager(&index);
+        let searcher = index.reader().unwrap().searcher();
+        let matches = |filter: SearchFilter| -> BichonResult<Vec<u64>> {
+            let query = manager.filter_query(None, filter, manager.handles().query_parser)?;
+            let mut ids: Vec<u64> = searcher
+                .search(&query, &TopDocs::with_limit(10))
+                .unwrap()
+                .into_iter()
+                .map(|(_, address)| {
+                    let doc: TantivyDocument = searcher.doc(address).unwrap();
+                    doc.get_first(f.f_id).and_then(|v| v.as_u64()).unwrap()
+                })
+                .collect();
+            ids.sort();
+            Ok(ids)
+        };
+        let mailbox = |mailbox_id| SearchFilter {
+            mailbox_id: Some(mailbox_id),
+            ..Default::default()
+        };
+
+        // Exclusions alone match everything else, also inside a group.
+        let not_in_1 = SearchFilter {
+            none_of: Some(vec![mailbox(1)]),
+            ..Default::default()
+        };
+        assert_eq!(matches(not_in_1.clone()).unwrap(), vec![2, 3]);
+        let in_1 = SearchFilter {
+            none_of: Some(vec![not_in_1]),
+            ..Default::default()
+        };
+        assert_eq!(matches(in_1).unwrap(), vec![1, 4]);
+
+        let large_in_1_or_in_2 = SearchFilter {
+            any_of: Some(vec![
+                mailbox(2),
+                SearchFilter {
+                    all_of: Some(vec![
+                        mailbox(1),
+                        SearchFilter {
+                            min_size: Some(1_000),
+                            ..Default::default()
+                        },
+                    ]),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(matches(large_in_1_or_in_2).unwrap(), vec![2, 4]);
+
+        let nested = |levels| {
+            (0..levels).fold(mailbox(3), |filter, _| SearchFilter {
+                all_of: Some(vec![filter]),
+                ..Default::default()
+            })
+        };
+        assert_eq!(matches(nested(MAX_FILTER_DEPTH)).unwrap(), vec![3]);
+        assert!(matches(nested(MAX_FILTER_DEPTH + 1)).is_err());
+    }
+}
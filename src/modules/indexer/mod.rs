@@ -17,9 +17,18 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+pub mod address;
+pub mod analyzer;
+pub mod dedup;
 pub mod envelope;
+pub mod facets;
 pub mod fields;
+pub mod headers;
 pub mod manager;
+pub mod query;
+pub mod reindex;
 pub mod schema;
+pub mod threads;
+pub mod upgrade;
 #[cfg(test)]
 mod tests;
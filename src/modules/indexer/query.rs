@@ -0,0 +1,856 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The query language of the search box.
+//!
+//! Free words and `"quoted phrases"` are matched against the default text fields.
+//! Field operators narrow the search the same way the `SearchFilter` fields do:
+//!
+//! - `from:`, `to:`, `cc:`, `bcc:` an email address, `@domain` or display name words,
+//!   `from:ali*` matches a prefix and `from:alise~` tolerates typos
+//! - `subject:`, `body:`, `attachment:` (attachment name) words or phrases
+//! - `has:attachment`
+//! - `tag:legal` or `tag:/legal/case-42`
+//! - `is:read`, `is:unread`, `is:flagged`, `is:answered`, `is:draft`, `is:removed` (deleted
+//!   on the server)
+//! - `flag:` an IMAP flag or keyword, e.g. `flag:$Label1` or `flag:seen` for `\Seen`
+//! - `before:2023-01-01`, `after:2023-01-01` (also `since:`), days start at midnight UTC
+//! - `larger:10M`, `smaller:500K`
+//! - `account:`, `mailbox:` an id
+//!
+//! Terms are combined with AND unless joined by `OR`. `NOT` or a leading `-` excludes
+//! a term, and parentheses group terms. A word ending with `*` matches as a prefix.
+
+use std::ops::Bound;
+
+use chrono::NaiveDate;
+use tantivy::{
+    query::{
+        AllQuery, BooleanQuery, ExistsQuery, Occur, PhrasePrefixQuery, Query, QueryParser,
+        RangeQuery, TermQuery,
+    },
+    schema::{Facet, Field, IndexRecordOption},
+    Term,
+};
+
+use crate::{
+    modules::{
+        error::{code::ErrorCode, BichonResult},
+        indexer::{
+            address::address_query,
+            fields::{F_ATTACHMENTS, F_DELETED_ON_SERVER_AT, F_SUBJECT, F_TEXT},
+            schema::SchemaTools,
+        },
+        message::search::AddressMatch,
+    },
+    raise_error,
+};
+
+/// Longest query accepted, in characters.
+const MAX_QUERY_LENGTH: usize = 4_000;
+/// How deep parentheses and `NOT` can be nested. The parser and the query
+/// builder recurse once per level.
+const MAX_QUERY_DEPTH: usize = 32;
+
+/// Parses `input` into a query, reporting syntax errors with the column they occur at.
+pub fn parse_search_query(input: &str, parser: &QueryParser) -> BichonResult<Box<dyn Query>> {
+    let to_error = |e: QueryError| {
+        raise_error!(
+            format!("Invalid query at column {}: {}", e.position + 1, e.message),
+            ErrorCode::InvalidParameter
+        )
+    };
+    let tokens = lex(input).map_err(to_error)?;
+    match ExprParser::new(tokens).parse().map_err(to_error)? {
+        Some(expr) => build(&expr, parser).map_err(to_error),
+        None => Ok(Box::new(AllQuery)),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct QueryError {
+    /// Offset of the offending character, in characters.
+    position: usize,
+    message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    From,
+    To,
+    Cc,
+    Bcc,
+    Subject,
+    Body,
+    Attachment,
+    Has,
+    Tag,
+    Is,
+    Flag,
+    Before,
+    After,
+    Larger,
+    Smaller,
+    Account,
+    Mailbox,
+}
+
+impl Operator {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "from" => Self::From,
+            "to" => Self::To,
+            "cc" => Self::Cc,
+            "bcc" => Self::Bcc,
+            "subject" => Self::Subject,
+            "body" => Self::Body,
+            "attachment" => Self::Attachment,
+            "has" => Self::Has,
+            "tag" => Self::Tag,
+            "is" => Self::Is,
+            "flag" => Self::Flag,
+            "before" => Self::Before,
+            "after" | "since" => Self::After,
+            "larger" => Self::Larger,
+            "smaller" => Self::Smaller,
+            "account" => Self::Account,
+            "mailbox" => Self::Mailbox,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct TermExpr {
+    operator: Option<Operator>,
+    value: String,
+    quoted: bool,
+    /// Where the value starts, for error messages.
+    position: usize,
+}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term(TermExpr),
+}
+
+#[derive(Debug, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    position: usize,
+}
+
+fn lex(input: &str) -> Result<Vec<Token>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    if chars.len() > MAX_QUERY_LENGTH {
+        return Err(QueryError::new(
+            MAX_QUERY_LENGTH,
+            format!("query is longer than {} characters", MAX_QUERY_LENGTH),
+        ));
+    }
+    let is_word_char = |c: char| !c.is_whitespace() && !matches!(c, '(' | ')' | '"');
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let kind = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => {
+                i += 1;
+                TokenKind::LParen
+            }
+            ')' => {
+                i += 1;
+                TokenKind::RParen
+            }
+            '-' if chars
+                .get(i + 1)
+                .is_some_and(|c| is_word_char(*c) || *c == '"') =>
+            {
+                i += 1;
+                TokenKind::Not
+            }
+            '"' => {
+                let (value, end) = read_quoted(&chars, i)?;
+                i = end;
+                TokenKind::Term(TermExpr {
+                    operator: None,
+                    value,
+                    quoted: true,
+                    position: start + 1,
+                })
+            }
+            _ => {
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.as_str() {
+                    "AND" => TokenKind::And,
+                    "OR" => TokenKind::Or,
+                    "NOT" => TokenKind::Not,
+                    _ => match word.split_once(':') {
+                        Some((name, value)) if Operator::from_name(name).is_some() => {
+                            let position = start + name.chars().count() + 1;
+                            let (value, quoted) = if !value.is_empty() {
+                                (value.to_string(), false)
+                            } else if chars.get(i) == Some(&'"') {
+                                let (value, end) = read_quoted(&chars, i)?;
+                                i = end;
+                                (value, true)
+                            } else {
+                                return Err(QueryError::new(
+                                    start,
+                                    format!("missing value after '{}:'", name),
+                                ));
+                            };
+                            TokenKind::Term(TermExpr {
+                                operator: Operator::from_name(name),
+                                value,
+                                quoted,
+                                position,
+                            })
+                        }
+                        _ => TokenKind::Term(TermExpr {
+                            operator: None,
+                            value: word,
+                            quoted: false,
+                            position: start,
+                        }),
+                    },
+                }
+            }
+        };
+        tokens.push(Token {
+            kind,
+            position: start,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Reads the phrase starting with the quote at `start`, returns it and the position after
+/// its closing quote. A backslash escapes the next character.
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize), QueryError> {
+    let mut value = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                value.push(chars[i + 1]);
+                i += 2;
+            }
+            '"' => return Ok((value, i + 1)),
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(QueryError::new(start, "unterminated quote"))
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Term(TermExpr),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+struct ExprParser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    /// Parentheses and `NOT`s around the current position.
+    depth: usize,
+}
+
+impl ExprParser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens: tokens.into_iter().peekable(),
+            depth: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<Option<Expr>, QueryError> {
+        let expr = self.parse_or()?;
+        match self.tokens.next() {
+            Some(token) => Err(QueryError::new(token.position, "unexpected ')'")),
+            None => Ok(expr),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Option<Expr>, QueryError> {
+        let first = self.parse_and()?;
+        let mut alternatives = Vec::new();
+        while let Some(token) = self.tokens.next_if(|t| t.kind == TokenKind::Or) {
+            if alternatives.is_empty() {
+                match &first {
+                    Some(_) => {}
+                    None => {
+                        return Err(QueryError::new(
+                            token.position,
+                            "expected a search term before 'OR'",
+                        ))
+                    }
+                }
+            }
+            match self.parse_and()? {
+                Some(expr) => alternatives.push(expr),
+                None => {
+                    return Err(QueryError::new(
+                        token.position,
+                        "expected a search term after 'OR'",
+                    ))
+                }
+            }
+        }
+        Ok(match (first, alternatives.is_empty()) {
+            (first, true) => first,
+            (Some(first), false) => {
+                alternatives.insert(0, first);
+                Some(Expr::Or(alternatives))
+            }
+            (None, false) => unreachable!("checked when the first 'OR' was read"),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Option<Expr>, QueryError> {
+        let mut terms = Vec::new();
+        loop {
+            match self.tokens.peek().map(|t| &t.kind) {
+                None | Some(TokenKind::RParen) | Some(TokenKind::Or) => break,
+                Some(TokenKind::And) => {
+                    let token = self.tokens.next().expect("peeked");
+                    if terms.is_empty() {
+                        return Err(QueryError::new(
+                            token.position,
+                            "expected a search term before 'AND'",
+                        ));
+                    }
+                    if !self.starts_term() {
+                        return Err(QueryError::new(
+                            token.position,
+                            "expected a search term after 'AND'",
+                        ));
+                    }
+                }
+                Some(_) => terms.push(self.parse_unary()?),
+            }
+        }
+        Ok(match terms.len() {
+            0 => None,
+            1 => terms.pop(),
+            _ => Some(Expr::And(terms)),
+        })
+    }
+
+    fn starts_term(&mut self) -> bool {
+        matches!(
+            self.tokens.peek().map(|t| &t.kind),
+            Some(TokenKind::Term(_) | TokenKind::Not | TokenKind::LParen)
+        )
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        let token = self.tokens.next().expect("called with a token ahead");
+        match token.kind {
+            TokenKind::Term(term) => Ok(Expr::Term(term)),
+            TokenKind::Not => {
+                if !self.starts_term() {
+                    return Err(QueryError::new(
+                        token.position,
+                        "expected a search term after 'NOT'",
+                    ));
+                }
+                self.enter(token.position)?;
+                let inner = self.parse_unary()?;
+                self.depth -= 1;
+                Ok(Expr::Not(Box::new(inner)))
+            }
+            TokenKind::LParen => {
+                self.enter(token.position)?;
+                let inner = self.parse_or()?;
+                if self
+                    .tokens
+                    .next_if(|t| t.kind == TokenKind::RParen)
+                    .is_none()
+                {
+                    return Err(QueryError::new(token.position, "unclosed '('"));
+                }
+                self.depth -= 1;
+                inner.ok_or_else(|| QueryError::new(token.position, "empty parentheses"))
+            }
+            TokenKind::RParen | TokenKind::And | TokenKind::Or => {
+                unreachable!("handled by parse_and")
+            }
+        }
+    }
+
+    /// Goes one level deeper for the `(` or `NOT` at `position`.
+    fn enter(&mut self, position: usize) -> Result<(), QueryError> {
+        if self.depth >= MAX_QUERY_DEPTH {
+            return Err(QueryError::new(position, "query nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+fn build(expr: &Expr, parser: &QueryParser) -> Result<Box<dyn Query>, QueryError> {
+    Ok(match expr {
+        Expr::Term(term) => build_term(term, parser)?,
+        Expr::Not(inner) => Box::new(BooleanQuery::new(vec![
+            (Occur::Must, Box::new(AllQuery) as Box<dyn Query>),
+            (Occur::MustNot, build(inner, parser)?),
+        ])),
+        Expr::And(terms) => {
+            let mut clauses = Vec::with_capacity(terms.len());
+            for term in terms {
+                clauses.push(match term {
+                    Expr::Not(inner) => (Occur::MustNot, build(inner, parser)?),
+                    term => (Occur::Must, build(term, parser)?),
+                });
+            }
+            // Exclusions alone match nothing in tantivy.
+            if clauses.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+                clauses.push((Occur::Must, Box::new(AllQuery)));
+            }
+            Box::new(BooleanQuery::new(clauses))
+        }
+        Expr::Or(alternatives) => {
+            let mut clauses = Vec::with_capacity(alternatives.len());
+            for alternative in alternatives {
+                clauses.push((Occur::Should, build(alternative, parser)?));
+            }
+            Box::new(BooleanQuery::new(clauses))
+        }
+    })
+}
+
+fn build_term(term: &TermExpr, parser: &QueryParser) -> Result<Box<dyn Query>, QueryError> {
+    let f = SchemaTools::envelope_fields();
+    let invalid = |message: String| QueryError::new(term.position, message);
+    let value = term.value.as_str();
+    let term_query =
+        |term: Term| -> Box<dyn Query> { Box::new(TermQuery::new(term, IndexRecordOption::Basic)) };
+    let Some(operator) = term.operator else {
+        return text_query(term, None, parser);
+    };
+    Ok(match operator {
+        Operator::Subject => text_query(term, Some(F_SUBJECT), parser)?,
+        Operator::Body => text_query(term, Some(F_TEXT), parser)?,
+        Operator::Attachment => text_query(term, Some(F_ATTACHMENTS), parser)?,
+        Operator::From | Operator::To | Operator::Cc | Operator::Bcc => {
+            let field = match operator {
+                Operator::From => f.f_from_name_addr,
+                Operator::To => f.f_to_name_addr,
+                Operator::Cc => f.f_cc_name_addr,
+                _ => f.f_bcc_name_addr,
+            };
+            let (value, mode) = if let Some(prefix) = value.strip_suffix('*') {
+                (prefix, AddressMatch::Prefix)
+            } else if let Some(fuzzy) = value.strip_suffix('~') {
+                (fuzzy, AddressMatch::Fuzzy)
+            } else {
+                (value, AddressMatch::Exact)
+            };
+            address_query(field, value, mode)
+                .ok_or_else(|| invalid(format!("cannot search for '{}'", term.value)))?
+        }
+        Operator::Has => {
+            if !matches!(
+                value.to_ascii_lowercase().as_str(),
+                "attachment" | "attachments"
+            ) {
+                return Err(invalid(format!(
+                    "unknown value '{}' for 'has:', expected 'attachment'",
+                    value
+                )));
+            }
+            term_query(Term::from_field_bool(f.f_has_attachment, true))
+        }
+        Operator::Tag => {
+            let path = if value.starts_with('/') {
+                value.to_string()
+            } else {
+                format!("/{}", value)
+            };
+            let facet =
+                Facet::from_text(&path).map_err(|_| invalid(format!("invalid tag '{}'", value)))?;
+            term_query(Term::from_facet(f.f_tags, &facet))
+        }
+        Operator::Is => {
+            let (query, set) = match value.to_ascii_lowercase().as_str() {
+                "read" | "seen" => (flag_query("\\Seen"), true),
+                "unread" | "unseen" => (flag_query("\\Seen"), false),
+                "flagged" | "starred" => (flag_query("\\Flagged"), true),
+                "answered" | "replied" => (flag_query("\\Answered"), true),
+                "draft" => (flag_query("\\Draft"), true),
+                "removed" => (deleted_on_server_query(), true),
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown value '{}' for 'is:', expected read, unread, flagged, answered, draft or removed",
+                        value
+                    )))
+                }
+            };
+            if set {
+                query
+            } else {
+                Box::new(BooleanQuery::new(vec![
+                    (Occur::Must, Box::new(AllQuery) as Box<dyn Query>),
+                    (Occur::MustNot, query),
+                ]))
+            }
+        }
+        Operator::Flag => flag_query(&system_flag(value)),
+        Operator::Before => {
+            let date = parse_date(value).ok_or_else(|| invalid(date_error(value)))?;
+            date_range(f.f_date, Bound::Unbounded, Bound::Excluded(date))
+        }
+        Operator::After => {
+            let date = parse_date(value).ok_or_else(|| invalid(date_error(value)))?;
+            date_range(f.f_date, Bound::Included(date), Bound::Unbounded)
+        }
+        Operator::Larger => {
+            let size = parse_size(value).ok_or_else(|| invalid(size_error(value)))?;
+            size_range(f.f_size, Bound::Excluded(size), Bound::Unbounded)
+        }
+        Operator::Smaller => {
+            let size = parse_size(value).ok_or_else(|| invalid(size_error(value)))?;
+            size_range(f.f_size, Bound::Unbounded, Bound::Excluded(size))
+        }
+        Operator::Account | Operator::Mailbox => {
+            let id: u64 = value
+                .parse()
+                .map_err(|_| invalid(format!("'{}' is not a numeric id", value)))?;
+            let field = if operator == Operator::Account {
+                f.f_account_id
+            } else {
+                f.f_mailbox_id
+            };
+            term_query(Term::from_field_u64(field, id))
+        }
+    })
+}
+
+/// Runs words and phrases through the query parser, so they are tokenized like the
+/// indexed text. `field` restricts the match to one field instead of the default ones.
+fn text_query(
+    term: &TermExpr,
+    field: Option<&str>,
+    parser: &QueryParser,
+) -> Result<Box<dyn Query>, QueryError> {
+    let cannot_search =
+        || QueryError::new(term.position, format!("cannot search for '{}'", term.value));
+    let quote = |value: &str| {
+        let phrase = format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""));
+        match field {
+            Some(field) => format!("{}:{}", field, phrase),
+            None => phrase,
+        }
+    };
+    let prefix = match term.value.strip_suffix('*') {
+        Some(prefix) if !term.quoted && !prefix.is_empty() => prefix,
+        _ => {
+            return parser
+                .parse_query(&quote(&term.value))
+                .map_err(|_| cannot_search())
+        }
+    };
+
+    // The parser only takes prefixes of phrases with two or more words, so the
+    // prefix query is built from the terms it tokenized for each field.
+    let parsed = parser
+        .parse_query(&quote(prefix))
+        .map_err(|_| cannot_search())?;
+    let mut terms_by_field: Vec<(Field, Vec<Term>)> = Vec::new();
+    parsed.query_terms(&mut |term, _| match terms_by_field
+        .iter_mut()
+        .find(|(field, _)| *field == term.field())
+    {
+        Some((_, terms)) => terms.push(term.clone()),
+        None => terms_by_field.push((term.field(), vec![term.clone()])),
+    });
+    if terms_by_field.is_empty() {
+        return Err(cannot_search());
+    }
+    Ok(Box::new(BooleanQuery::new(
+        terms_by_field
+            .into_iter()
+            .map(|(_, terms)| {
+                (
+                    Occur::Should,
+                    Box::new(PhrasePrefixQuery::new(terms)) as Box<dyn Query>,
+                )
+            })
+            .collect(),
+    )))
+}
+
+fn date_range(field: Field, lower: Bound<i64>, upper: Bound<i64>) -> Box<dyn Query> {
+    let term = |date| Term::from_field_i64(field, date);
+    Box::new(RangeQuery::new(lower.map(term), upper.map(term)))
+}
+
+fn size_range(field: Field, lower: Bound<u64>, upper: Bound<u64>) -> Box<dyn Query> {
+    let term = |size| Term::from_field_u64(field, size);
+    Box::new(RangeQuery::new(lower.map(term), upper.map(term)))
+}
+
+/// Milliseconds at the start of the day, UTC.
+fn parse_date(value: &str) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp_millis())
+}
+
+/// Messages with the IMAP flag or keyword, ignoring case.
+pub fn flag_query(flag: &str) -> Box<dyn Query> {
+    let term = Term::from_field_text(SchemaTools::envelope_fields().f_flags, &flag.to_lowercase());
+    Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+}
+
+/// Messages found to be deleted on the server, see `Envelope::deleted_on_server_at`.
+pub fn deleted_on_server_query() -> Box<dyn Query> {
+    Box::new(ExistsQuery::new(F_DELETED_ON_SERVER_AT.to_string(), false))
+}
+
+/// Lets `seen` stand for the system flag `\Seen`, other values are keywords.
+fn system_flag(value: &str) -> String {
+    match value.to_ascii_lowercase().as_str() {
+        "seen" | "answered" | "flagged" | "deleted" | "draft" => format!("\\{}", value),
+        _ => value.to_string(),
+    }
+}
+
+fn date_error(value: &str) -> String {
+    format!("invalid date '{}', expected YYYY-MM-DD", value)
+}
+
+/// Bytes, optionally with a K, M or G suffix.
+fn parse_size(value: &str) -> Option<u64> {
+    let upper = value.to_ascii_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, unit) = match number.chars().last()? {
+        'K' => (&number[..number.len() - 1], 1024),
+        'M' => (&number[..number.len() - 1], 1024 * 1024),
+        'G' => (&number[..number.len() - 1], 1024 * 1024 * 1024),
+        _ => (number, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+fn size_error(value: &str) -> String {
+    format!(
+        "invalid size '{}', expected a number of bytes like 500K or 10M",
+        value
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use tantivy::{collector::Count, Index, IndexWriter, TantivyDocument};
+
+    use super::*;
+    use crate::modules::indexer::analyzer::register_analyzers;
+
+    fn parse(input: &str) -> Result<Option<Expr>, QueryError> {
+        ExprParser::new(lex(input)?).parse()
+    }
+
+    fn word(value: &str, position: usize) -> Expr {
+        Expr::Term(TermExpr {
+            operator: None,
+            value: value.into(),
+            quoted: false,
+            position,
+        })
+    }
+
+    #[test]
+    fn test_parses_boolean_structure() {
+        assert_eq!(
+            parse("a OR b -c (d OR e)").unwrap(),
+            Some(Expr::Or(vec![
+                word("a", 0),
+                Expr::And(vec![
+                    word("b", 5),
+                    Expr::Not(Box::new(word("c", 8))),
+                    Expr::Or(vec![word("d", 11), word("e", 16)]),
+                ]),
+            ]))
+        );
+        assert_eq!(
+            parse(r#"from:"a@x.com" re:meeting"#).unwrap(),
+            Some(Expr::And(vec![
+                Expr::Term(TermExpr {
+                    operator: Some(Operator::From),
+                    value: "a@x.com".into(),
+                    quoted: true,
+                    position: 5,
+                }),
+                word("re:meeting", 15),
+            ]))
+        );
+        assert_eq!(parse("  ").unwrap(), None);
+    }
+
+    #[test]
+    fn test_reports_error_positions() {
+        let error = |input| parse(input).unwrap_err();
+        assert_eq!(error("(a OR b").position, 0);
+        assert_eq!(error("a b)").position, 3);
+        assert_eq!(error(r#"subject:"open"#).position, 8);
+        assert_eq!(error("a OR").position, 2);
+        assert_eq!(error("OR a").position, 0);
+        assert_eq!(error("a NOT").position, 2);
+        assert_eq!(error("a from: b").message, "missing value after 'from:'");
+        assert_eq!(error("()").message, "empty parentheses");
+    }
+
+    #[test]
+    fn test_limits_nesting() {
+        let nested = |levels| format!("{}a{}", "(".repeat(levels), ")".repeat(levels));
+        assert_eq!(
+            parse(&nested(MAX_QUERY_DEPTH)).unwrap(),
+            Some(word("a", MAX_QUERY_DEPTH))
+        );
+        let error = parse(&nested(MAX_QUERY_DEPTH + 1)).unwrap_err();
+        assert_eq!(
+            error,
+            QueryError::new(MAX_QUERY_DEPTH, "query nested too deeply")
+        );
+
+        let error = parse(&format!("{}a", "NOT ".repeat(MAX_QUERY_DEPTH + 1))).unwrap_err();
+        assert_eq!(error.position, 4 * MAX_QUERY_DEPTH);
+        assert_eq!(error.message, "query nested too deeply");
+        assert!(parse(&format!("{}a", "-".repeat(MAX_QUERY_DEPTH + 1))).is_err());
+
+        // Far too deep to parse recursively, refused by its length.
+        let error = parse(&nested(200_000)).unwrap_err();
+        assert_eq!(error.position, MAX_QUERY_LENGTH);
+        let error = parse(&nested(MAX_QUERY_LENGTH / 2 - 1)).unwrap_err();
+        assert_eq!(error.message, "query nested too deeply");
+    }
+
+    #[test]
+    fn test_operators_match_documents() {
+        let index = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_analyzers(&index);
+        let f = SchemaTools::envelope_fields();
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+        for (from, subject, date, size, has_attachment, tag, flags) in [
+            (
+                "alice@x.com",
+                "Quarterly invoice",
+                1_640_995_200_000,
+                5_000,
+                true,
+                "/legal",
+                vec!["\\Seen", "$Label1"],
+            ),
+            (
+                "bob@y.com",
+                "Lunch plans",
+                1_680_000_000_000,
+                2_000_000,
+                false,
+                "/personal",
+                vec!["\\Seen", "\\Flagged"],
+            ),
+            (
+                "alice@x.com",
+                "Invoices for March",
+                1_690_000_000_000,
+                900,
+                false,
+                "/legal",
+                vec![],
+            ),
+        ] {
+            let mut doc = TantivyDocument::new();
+            doc.add_text(f.f_from, from);
+            doc.add_text(f.f_from_name_addr, from);
+            doc.add_text(f.f_subject, subject);
+            doc.add_i64(f.f_date, date);
+            doc.add_u64(f.f_size, size);
+            doc.add_bool(f.f_has_attachment, has_attachment);
+            doc.add_facet(f.f_tags, Facet::from(tag));
+            if flags.is_empty() {
+                doc.add_i64(f.f_deleted_on_server_at, 1_700_000_000_000);
+            }
+            for flag in flags {
+                doc.add_text(f.f_flags, flag);
+            }
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let mut parser = QueryParser::for_index(&index, vec![f.f_subject, f.f_text]);
+        parser.set_conjunction_by_default();
+        let count = |input: &str| {
+            let query = parse_search_query(input, &parser).unwrap();
+            searcher.search(query.as_ref(), &Count).unwrap()
+        };
+
+        assert_eq!(
+            count("from:alice@x.com has:attachment before:2023-01-01 tag:legal"),
+            1
+        );
+        assert_eq!(count("from:alice@x.com"), 2);
+        assert_eq!(count("from:Alice@X.com"), 2);
+        assert_eq!(count("from:@y.com OR from:ali*"), 3);
+        assert_eq!(count("from:alise~"), 2);
+        assert_eq!(count("invoice*"), 2);
+        assert_eq!(count("subject:invoice OR lunch"), 2);
+        assert_eq!(count("NOT tag:legal"), 1);
+        assert_eq!(count("-from:bob@y.com -has:attachment"), 1);
+        assert_eq!(count("larger:1M OR smaller:1K"), 2);
+        assert_eq!(count("after:2023-06-01 (march OR lunch)"), 1);
+        assert_eq!(count(r#""quarterly invoice""#), 1);
+        assert_eq!(count("is:unread"), 1);
+        assert_eq!(count("is:read from:alice@x.com"), 1);
+        assert_eq!(count("is:flagged OR flag:$label1"), 2);
+        assert_eq!(count("flag:seen -is:flagged"), 1);
+        assert_eq!(count("is:removed"), 1);
+        assert_eq!(count("from:alice@x.com -is:removed"), 1);
+
+        let error = parse_search_query("before:yesterday", &parser).unwrap_err();
+        assert!(format!("{:?}", error).contains("column 8"));
+    }
+}
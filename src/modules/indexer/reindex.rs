@@ -0,0 +1,308 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+use crate::modules::blob::entity::EmlRecord;
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
+use crate::modules::envelope::extractor::extract_envelope_from_eml;
+use crate::modules::envelope::location::{
+    deleted_on_server_at, flags_in, MailboxLocation, MessageLocation,
+};
+use crate::modules::envelope::thread::{assign_thread, link_thread};
+use crate::modules::error::{code::ErrorCode, BichonResult};
+use crate::modules::indexer::analyzer::register_analyzers;
+use crate::modules::indexer::dedup::DEDUP_JOB;
+use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
+use crate::modules::indexer::schema::SchemaTools;
+use crate::modules::settings::dir::DATA_DIR_MANAGER;
+use crate::{raise_error, utc_now};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use tantivy::collector::TopDocs;
+use tantivy::query::TermQuery;
+use tantivy::schema::{IndexRecordOption, Value};
+use tantivy::{Index, IndexWriter, Searcher, TantivyDocument, Term};
+use tracing::{error, info, warn};
+
+/// Where a reindex builds the new envelope index.
+pub(crate) const REINDEX_DIR: &str = "envelope.reindex";
+const REINDEX_BATCH_SIZE: usize = 500;
+
+pub static REINDEX_JOB: LazyLock<ReindexJob> = LazyLock::new(ReindexJob::default);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum ReindexState {
+    /// No reindex has run since the server started.
+    #[default]
+    Idle,
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ReindexStatus {
+    pub state: ReindexState,
+    /// Number of stored emails when the reindex started.
+    pub total: u64,
+    /// Number of stored emails processed so far.
+    pub processed: u64,
+    /// Emails that could not be parsed again; their existing envelope is kept.
+    pub failed: u64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// Rebuilds the envelope index from the EML blob store.
+///
+/// The new index is written beside the live one, which keeps serving searches
+/// and receiving new mail. Tags, UIDs and internal dates are taken over from
+/// the live index, since they are not part of the EML. Once every stored email
+/// has been processed, the changes made to the live index in the meantime are
+/// applied and the new index is swapped in.
+#[derive(Default)]
+pub struct ReindexJob {
+    status: Mutex<ReindexStatus>,
+    cancelled: AtomicBool,
+}
+
+impl ReindexJob {
+    pub fn status(&self) -> ReindexStatus {
+        self.status
+            .lock()
+            .expect("reindex status lock poisoned")
+            .clone()
+    }
+
+    pub fn start(&'static self) -> BichonResult<ReindexStatus> {
+        if DEDUP_JOB.is_running() {
+            return Err(raise_error!(
+                "Cannot reindex while emails are being deduplicated.".into(),
+                ErrorCode::AlreadyExists
+            ));
+        }
+        {
+            let mut status = self.status.lock().expect("reindex status lock poisoned");
+            if status.state == ReindexState::Running {
+                return Err(raise_error!(
+                    "A reindex is already running.".into(),
+                    ErrorCode::AlreadyExists
+                ));
+            }
+            *status = ReindexStatus {
+                state: ReindexState::Running,
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        self.cancelled.store(false, Ordering::SeqCst);
+        tokio::spawn(async move {
+            let result = self.run().await;
+            ENVELOPE_INDEX_MANAGER.track_reindex(false);
+            let mut status = self.status.lock().expect("reindex status lock poisoned");
+            status.finished_at = Some(utc_now!());
+            match result {
+                Ok(true) => {
+                    info!(
+                        "Reindex completed: {} emails processed, {} failed",
+                        status.processed, status.failed
+                    );
+                    status.state = ReindexState::Completed;
+                }
+                Ok(false) => {
+                    info!("Reindex cancelled after {} emails", status.processed);
+                    status.state = ReindexState::Cancelled;
+                }
+                Err(e) => {
+                    error!("Reindex failed: {:#?}", e);
+                    status.state = ReindexState::Failed;
+                    status.error = Some(format!("{:#?}", e));
+                }
+            }
+        });
+        Ok(self.status())
+    }
+
+    pub fn cancel(&self) -> BichonResult<ReindexStatus> {
+        if self.status().state != ReindexState::Running {
+            return Err(raise_error!(
+                "No reindex is running.".into(),
+                ErrorCode::ResourceNotFound
+            ));
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        Ok(self.status())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ReindexStatus)) {
+        f(&mut self.status.lock().expect("reindex status lock poisoned"));
+    }
+
+    /// Returns `false` if the reindex was cancelled.
+    async fn run(&self) -> BichonResult<bool> {
+        let reindex_dir = DATA_DIR_MANAGER.root_dir.join(REINDEX_DIR);
+        if reindex_dir.exists() {
+            std::fs::remove_dir_all(&reindex_dir)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+        std::fs::create_dir_all(&reindex_dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let index = Index::create_in_dir(&reindex_dir, SchemaTools::envelope_schema())
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        register_analyzers(&index);
+        let writer: IndexWriter = index
+            .writer_with_num_threads(4, 268_435_456)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+        ENVELOPE_INDEX_MANAGER.track_reindex(true);
+        let total = EML_BLOB_MANAGER.count().await?;
+        self.update(|status| status.total = total);
+        info!("Reindexing {} emails into {:?}", total, reindex_dir);
+
+        let mut rebuilt: HashMap<u64, bool> = HashMap::new();
+        let mut after = None;
+        loop {
+            if self.cancelled.load(Ordering::SeqCst) {
+                drop(writer);
+                drop(index);
+                if let Err(e) = std::fs::remove_dir_all(&reindex_dir) {
+                    warn!("Failed to remove {:?}: {:#?}", reindex_dir, e);
+                }
+                return Ok(false);
+            }
+            let records = EML_BLOB_MANAGER
+                .list_after(after, REINDEX_BATCH_SIZE)
+                .await?;
+            let Some(last) = records.last() else {
+                break;
+            };
+            after = Some(last.id);
+
+            // Envelopes written from here on are picked up again at swap time.
+            let live = ENVELOPE_INDEX_MANAGER.create_searcher()?;
+            let mut failed = 0;
+            for record in &records {
+                let live_doc = find_live_document(&live, record.id).await?;
+                match rebuild_document(record, live_doc.as_ref()).await {
+                    Ok(doc) => {
+                        writer.add_document(doc).map_err(|e| {
+                            raise_error!(format!("{:#?}", e), ErrorCode::InternalError)
+                        })?;
+                        rebuilt.insert(record.id, live_doc.is_some());
+                    }
+                    Err(e) => {
+                        // Not marked as rebuilt, so the live envelope is carried over.
+                        warn!("Failed to reindex email {}: {:#?}", record.id, e);
+                        failed += 1;
+                    }
+                }
+            }
+            let processed = records.len() as u64;
+            self.update(|status| {
+                status.processed += processed;
+                status.failed += failed;
+            });
+        }
+
+        ENVELOPE_INDEX_MANAGER
+            .swap_in_reindexed(index, writer, &rebuilt, &reindex_dir)
+            .await?;
+        Ok(true)
+    }
+}
+
+async fn find_live_document(searcher: &Searcher, id: u64) -> BichonResult<Option<TantivyDocument>> {
+    let query = TermQuery::new(
+        Term::from_field_u64(SchemaTools::envelope_fields().f_id, id),
+        IndexRecordOption::Basic,
+    );
+    let docs = searcher
+        .search(&query, &TopDocs::with_limit(1))
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    match docs.first() {
+        Some((_, address)) => {
+            Ok(Some(searcher.doc_async(*address).await.map_err(|e| {
+                raise_error!(format!("{:#?}", e), ErrorCode::InternalError)
+            })?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Builds the envelope document of `record` from its EML, keeping what only
+/// the live index knows.
+async fn rebuild_document(
+    record: &EmlRecord,
+    live_doc: Option<&TantivyDocument>,
+) -> BichonResult<TantivyDocument> {
+    let fields = SchemaTools::envelope_fields();
+    let eml = EML_BLOB_MANAGER.load(record).await?;
+    let mut envelope = extract_envelope_from_eml(&eml, record.account_id, record.mailbox_id)?;
+    envelope.id = record.id;
+
+    if let Some(live_doc) = live_doc {
+        let u64_value = |field| live_doc.get_first(field).and_then(|v| v.as_u64());
+        let i64_value = |field| live_doc.get_first(field).and_then(|v| v.as_i64());
+        // A generated Message-ID is random, keep the one the id was derived from.
+        if let Some(message_id) = live_doc
+            .get_first(fields.f_message_id)
+            .and_then(|v| v.as_str())
+        {
+            envelope.message_id = message_id.to_string();
+        }
+        if let Some(mailbox_id) = u64_value(fields.f_mailbox_id) {
+            envelope.mailbox_id = mailbox_id;
+        }
+        if let Some(uid) = u64_value(fields.f_uid) {
+            envelope.uid = uid as u32;
+        }
+        if let Some(internal_date) = i64_value(fields.f_internal_date) {
+            envelope.internal_date = internal_date;
+        }
+        if let Some(size) = u64_value(fields.f_size) {
+            envelope.size = size as u32;
+        }
+        // Messages from Gmail are in the conversation Gmail reported.
+        if let Some(gmail_message_id) = u64_value(fields.f_gmail_message_id) {
+            envelope.gmail_message_id = Some(gmail_message_id);
+            envelope.thread_id = u64_value(fields.f_thread_id).unwrap_or(envelope.thread_id);
+        }
+    }
+
+    match envelope.gmail_message_id {
+        Some(_) => link_thread(&envelope).await?,
+        None => assign_thread(&mut envelope).await?,
+    }
+    let locations = MessageLocation::list(envelope.id).await?;
+    envelope.flags = flags_in(&locations, envelope.mailbox_id);
+    envelope.deleted_on_server_at = deleted_on_server_at(&locations);
+    envelope.locations = locations.iter().map(MailboxLocation::from).collect();
+
+    let mut doc = envelope.to_document(envelope.mailbox_id)?;
+    if let Some(live_doc) = live_doc {
+        for tag in live_doc.get_all(fields.f_tags) {
+            doc.add_field_value(fields.f_tags, tag);
+        }
+    }
+    Ok(doc)
+}
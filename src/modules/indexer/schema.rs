@@ -19,8 +19,14 @@
 
 use std::sync::{Arc, LazyLock};
 
+use crate::modules::indexer::address::ADDRESS_TOKENIZER;
+use crate::modules::indexer::analyzer::{StemLanguage, KEYWORD_TOKENIZER, TEXT_TOKENIZER};
 use crate::modules::indexer::fields::{EnvelopeFields, *};
-use tantivy::schema::{FacetOptions, Field, INDEXED};
+use clap::ValueEnum;
+use tantivy::schema::{
+    FacetOptions, Field, IndexRecordOption, JsonObjectOptions, TextFieldIndexing, TextOptions,
+    INDEXED,
+};
 use tantivy::schema::{Schema, FAST, STORED, STRING, TEXT};
 
 static ENVELOPE_FIELDS: LazyLock<Arc<EnvelopeFields>> = LazyLock::new(|| {
@@ -28,11 +34,6 @@ static ENVELOPE_FIELDS: LazyLock<Arc<EnvelopeFields>> = LazyLock::new(|| {
     Arc::new(fields)
 });
 
-static EML_FIELDS: LazyLock<Arc<EmlFields>> = LazyLock::new(|| {
-    let (_, fields) = SchemaTools::create_eml_schema();
-    Arc::new(fields)
-});
-
 pub struct SchemaTools;
 
 impl SchemaTools {
@@ -41,22 +42,24 @@ impl SchemaTools {
         schema
     }
 
-    pub fn eml_schema() -> Schema {
-        let (schema, _) = Self::create_eml_schema();
-        schema
-    }
-
     pub fn envelope_fields() -> &'static EnvelopeFields {
         &ENVELOPE_FIELDS
     }
 
-    pub fn eml_fields() -> &'static EmlFields {
-        &EML_FIELDS
-    }
-
     pub fn envelope_default_fields() -> Vec<Field> {
         let fields = Self::envelope_fields();
-        vec![fields.f_subject, fields.f_text, fields.f_attachments]
+        let mut default_fields = vec![
+            fields.f_subject,
+            fields.f_text,
+            fields.f_attachments,
+            fields.f_attachment_text,
+        ];
+        default_fields.extend(
+            StemLanguage::enabled()
+                .iter()
+                .map(|language| fields.stemmed_field(*language)),
+        );
+        default_fields
     }
 
     pub fn create_envelope_schema() -> (Schema, EnvelopeFields) {
@@ -67,14 +70,19 @@ impl SchemaTools {
         let f_mailbox_id = builder.add_u64_field(F_MAILBOX_ID, INDEXED | STORED | FAST);
         // UID: numeric, locate message
         let f_uid = builder.add_u64_field(F_UID, INDEXED | STORED | FAST);
-        // Subject/body: tokenized for full-text search
-        let f_subject = builder.add_text_field(F_SUBJECT, TEXT | STORED);
-        let f_text = builder.add_text_field(F_TEXT, TEXT | STORED);
-        // Email addresses: exact match search
+        // Subject/body: tokenized for full-text search, CJK text is split into bigrams
+        let f_subject = builder.add_text_field(F_SUBJECT, full_text_options().set_stored());
+        let f_text = builder.add_text_field(F_TEXT, full_text_options().set_stored());
+        // Email addresses: exact match search, fast for the facet counts
         let f_from = builder.add_text_field(F_FROM, STRING | STORED | FAST);
-        let f_to = builder.add_text_field(F_TO, STRING | STORED);
-        let f_cc = builder.add_text_field(F_CC, STRING | STORED);
-        let f_bcc = builder.add_text_field(F_BCC, STRING | STORED);
+        let f_to = builder.add_text_field(F_TO, STRING | STORED | FAST);
+        let f_cc = builder.add_text_field(F_CC, STRING | STORED | FAST);
+        let f_bcc = builder.add_text_field(F_BCC, STRING | STORED | FAST);
+        // Addresses with display names: lowercased address, local part, domain and name words
+        let f_from_name_addr = builder.add_text_field(F_FROM_NAME_ADDR, address_options());
+        let f_to_name_addr = builder.add_text_field(F_TO_NAME_ADDR, address_options());
+        let f_cc_name_addr = builder.add_text_field(F_CC_NAME_ADDR, address_options());
+        let f_bcc_name_addr = builder.add_text_field(F_BCC_NAME_ADDR, address_options());
         // Date fields: numeric, range filtering
         let f_date = builder.add_i64_field(F_DATE, STORED | FAST);
         let f_internal_date = builder.add_i64_field(F_INTERNAL_DATE, STORED | FAST);
@@ -82,12 +90,66 @@ impl SchemaTools {
         let f_size = builder.add_u64_field(F_SIZE, STORED | FAST);
         // Thread ID: numeric, filter by thread
         let f_thread_id = builder.add_u64_field(F_THREAD_ID, INDEXED | STORED | FAST);
+        // Gmail message ID: numeric, locate the message in Gmail
+        let f_gmail_message_id = builder.add_u64_field(F_GMAIL_MESSAGE_ID, INDEXED | STORED);
+        // Deletion on the server: numeric, range filtering and existence checks
+        let f_deleted_on_server_at = builder.add_i64_field(F_DELETED_ON_SERVER_AT, STORED | FAST);
         // Message-ID: unique identifier, no tokenization
         let f_message_id = builder.add_text_field(F_MESSAGE_ID, STRING | STORED);
         // Attachments: exact match search
         let f_attachments = builder.add_text_field(F_ATTACHMENTS, TEXT | STORED);
         let f_has_attachment = builder.add_bool_field(F_HAS_ATTACHMENT, INDEXED | STORED | FAST);
         let f_tags = builder.add_facet_field(F_TAGS, FacetOptions::default().set_stored());
+        // IMAP flags and keywords: stored and matched as a whole ignoring case
+        let f_flags = builder.add_text_field(
+            F_FLAGS,
+            TextOptions::default()
+                .set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(KEYWORD_TOKENIZER)
+                        .set_index_option(IndexRecordOption::Basic),
+                )
+                .set_stored(),
+        );
+        // Attachment contents: tokenized for full-text search, stored so that documents
+        // rewritten from the doc store (e.g. on tag updates) keep it
+        let f_attachment_text =
+            builder.add_text_field(F_ATTACHMENT_TEXT, full_text_options().set_stored());
+        // Selected headers: stored and matched as a whole ignoring case, the words of
+        // the headers enabled for text search are indexed separately
+        let f_headers = builder.add_json_field(
+            F_HEADERS,
+            JsonObjectOptions::default()
+                .set_stored()
+                .set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(KEYWORD_TOKENIZER)
+                        .set_index_option(IndexRecordOption::Basic),
+                ),
+        );
+        let f_headers_text = builder.add_json_field(
+            F_HEADERS_TEXT,
+            JsonObjectOptions::default().set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(TEXT_TOKENIZER)
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            ),
+        );
+        // Stemmed subject/body, filled for the detected language of each message
+        let f_stemmed = StemLanguage::value_variants()
+            .iter()
+            .map(|language| {
+                let options = TextOptions::default().set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(&language.tokenizer_name())
+                        .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+                );
+                (
+                    *language,
+                    builder.add_text_field(&language.field_name(), options),
+                )
+            })
+            .collect();
         let fields = EnvelopeFields {
             f_id,
             f_account_id,
@@ -99,30 +161,44 @@ impl SchemaTools {
             f_to,
             f_cc,
             f_bcc,
+            f_from_name_addr,
+            f_to_name_addr,
+            f_cc_name_addr,
+            f_bcc_name_addr,
             f_date,
             f_internal_date,
             f_size,
             f_thread_id,
+            f_gmail_message_id,
+            f_deleted_on_server_at,
             f_message_id,
             f_attachments,
             f_has_attachment,
             f_tags,
+            f_flags,
+            f_attachment_text,
+            f_headers,
+            f_headers_text,
+            f_stemmed,
         };
         (builder.build(), fields)
     }
+}
 
-    pub fn create_eml_schema() -> (Schema, EmlFields) {
-        let mut builder = Schema::builder();
-        let f_id = builder.add_u64_field(F_ID, INDEXED | FAST);
-        let f_account_id = builder.add_u64_field(F_ACCOUNT_ID, INDEXED | STORED | FAST);
-        let f_mailbox_id = builder.add_u64_field(F_MAILBOX_ID, INDEXED | STORED | FAST);
-        let f_eml = builder.add_bytes_field(F_EML, STORED);
-        let fields = EmlFields {
-            f_id,
-            f_account_id,
-            f_mailbox_id,
-            f_eml,
-        };
-        (builder.build(), fields)
-    }
+fn full_text_options() -> TextOptions {
+    TextOptions::default().set_indexing_options(
+        TextFieldIndexing::default()
+            .set_tokenizer(TEXT_TOKENIZER)
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    )
+}
+
+fn address_options() -> TextOptions {
+    TextOptions::default()
+        .set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(ADDRESS_TOKENIZER)
+                .set_index_option(IndexRecordOption::Basic),
+        )
+        .set_stored()
 }
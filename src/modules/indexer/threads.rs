@@ -0,0 +1,109 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use tantivy::{
+    collector::{Collector, SegmentCollector},
+    columnar::Column,
+    DocAddress, DocId, Score, Searcher, SegmentOrdinal, SegmentReader,
+};
+
+use crate::{
+    modules::{
+        error::{code::ErrorCode, BichonResult},
+        indexer::fields::{F_ACCOUNT_ID, F_THREAD_ID},
+    },
+    raise_error,
+};
+
+/// A thread is only unique within its account.
+pub type ThreadKey = (u64, u64);
+
+/// Counts the matching envelopes per `(account_id, thread_id)`.
+pub struct ThreadCountCollector;
+
+impl Collector for ThreadCountCollector {
+    type Fruit = HashMap<ThreadKey, u64>;
+    type Child = ThreadCountSegmentCollector;
+
+    fn for_segment(
+        &self,
+        _segment_local_id: SegmentOrdinal,
+        segment: &SegmentReader,
+    ) -> tantivy::Result<Self::Child> {
+        let fast_fields = segment.fast_fields();
+        Ok(ThreadCountSegmentCollector {
+            accounts: fast_fields.u64(F_ACCOUNT_ID)?,
+            threads: fast_fields.u64(F_THREAD_ID)?,
+            counts: HashMap::new(),
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        false
+    }
+
+    fn merge_fruits(
+        &self,
+        segment_fruits: Vec<HashMap<ThreadKey, u64>>,
+    ) -> tantivy::Result<HashMap<ThreadKey, u64>> {
+        let mut counts = HashMap::new();
+        for fruit in segment_fruits {
+            for (key, count) in fruit {
+                *counts.entry(key).or_default() += count;
+            }
+        }
+        Ok(counts)
+    }
+}
+
+pub struct ThreadCountSegmentCollector {
+    accounts: Column<u64>,
+    threads: Column<u64>,
+    counts: HashMap<ThreadKey, u64>,
+}
+
+impl SegmentCollector for ThreadCountSegmentCollector {
+    type Fruit = HashMap<ThreadKey, u64>;
+
+    fn collect(&mut self, doc: DocId, _score: Score) {
+        if let (Some(account_id), Some(thread_id)) =
+            (self.accounts.first(doc), self.threads.first(doc))
+        {
+            *self.counts.entry((account_id, thread_id)).or_default() += 1;
+        }
+    }
+
+    fn harvest(self) -> HashMap<ThreadKey, u64> {
+        self.counts
+    }
+}
+
+pub fn thread_key(searcher: &Searcher, doc_address: DocAddress) -> BichonResult<ThreadKey> {
+    let fast_fields = searcher
+        .segment_reader(doc_address.segment_ord)
+        .fast_fields();
+    let value = |field| {
+        fast_fields
+            .u64(field)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+            .map(|column| column.first(doc_address.doc_id).unwrap_or_default())
+    };
+    Ok((value(F_ACCOUNT_ID)?, value(F_THREAD_ID)?))
+}
@@ -0,0 +1,156 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::path::{Path, PathBuf};
+
+use crate::modules::error::{code::ErrorCode, BichonResult};
+use crate::modules::indexer::address::add_missing_name_addr_fields;
+use crate::modules::indexer::analyzer::{add_stemmed_fields, register_analyzers};
+use crate::modules::indexer::headers::add_header_text_fields;
+use crate::modules::indexer::reindex::REINDEX_DIR;
+use crate::modules::indexer::schema::SchemaTools;
+use crate::modules::settings::dir::DATA_DIR_MANAGER;
+use crate::raise_error;
+use tantivy::{Index, TantivyDocument};
+use tracing::{info, warn};
+
+const UPGRADE_DIR: &str = "envelope.upgrade";
+/// Where the live index is moved while a new one is swapped in.
+pub(crate) const PREVIOUS_DIR: &str = "envelope.previous";
+const PROGRESS_LOG_INTERVAL: u64 = 10_000;
+
+/// Rewrites an envelope index created by an older version with the current
+/// schema.
+///
+/// Tantivy cannot add fields to an existing index, so the stored fields of
+/// every live document are copied by name into a new index that is then swapped
+/// into place. Fields that did not exist before stay empty for the copied
+/// documents.
+pub async fn upgrade_envelope_index() -> BichonResult<()> {
+    let envelope_dir = &DATA_DIR_MANAGER.envelope_dir;
+    let upgrade_dir = DATA_DIR_MANAGER.root_dir.join(UPGRADE_DIR);
+    let previous_dir = DATA_DIR_MANAGER.root_dir.join(PREVIOUS_DIR);
+
+    // Finish or roll back a swap that was interrupted.
+    if previous_dir.exists() {
+        if envelope_dir.join("meta.json").exists() {
+            remove_dir(&previous_dir);
+        } else {
+            warn!(
+                "Restoring envelope index from {:?} after an interrupted upgrade",
+                previous_dir
+            );
+            std::fs::rename(&previous_dir, envelope_dir)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+    }
+    for leftover in [upgrade_dir.clone(), DATA_DIR_MANAGER.root_dir.join(REINDEX_DIR)] {
+        if leftover.exists() {
+            remove_dir(&leftover);
+        }
+    }
+
+    if !envelope_dir.join("meta.json").exists() {
+        return Ok(());
+    }
+    let index = Index::open_in_dir(envelope_dir)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    if index.schema() == SchemaTools::envelope_schema() {
+        return Ok(());
+    }
+
+    info!(
+        "Envelope index in {:?} uses an older schema, upgrading it",
+        envelope_dir
+    );
+    let copied = copy_index(&index, &upgrade_dir)?;
+    drop(index);
+
+    std::fs::rename(envelope_dir, &previous_dir)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    std::fs::rename(&upgrade_dir, envelope_dir)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    remove_dir(&previous_dir);
+    info!("Upgraded envelope index, {} documents copied", copied);
+    Ok(())
+}
+
+fn copy_index(source: &Index, target_dir: &PathBuf) -> BichonResult<u64> {
+    std::fs::create_dir_all(target_dir)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let schema = SchemaTools::envelope_schema();
+    let target = Index::create_in_dir(target_dir, schema.clone())
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    register_analyzers(&target);
+    let mut writer = target
+        .writer::<TantivyDocument>(268_435_456)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+    let source_schema = source.schema();
+    let reader = source
+        .reader()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let searcher = reader.searcher();
+    let total = searcher.num_docs();
+
+    let mut copied = 0u64;
+    for segment_reader in searcher.segment_readers() {
+        let store_reader = segment_reader
+            .get_store_reader(1)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        for doc_id in segment_reader.doc_ids_alive() {
+            let doc: TantivyDocument = store_reader
+                .get(doc_id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mut upgraded = TantivyDocument::new();
+            for (field, value) in doc.field_values() {
+                let name = source_schema.get_field_name(field);
+                if let Ok(target_field) = schema.get_field(name) {
+                    upgraded.add_field_value(target_field, value);
+                }
+            }
+            add_stemmed_fields(&mut upgraded);
+            add_missing_name_addr_fields(&mut upgraded);
+            add_header_text_fields(&mut upgraded);
+            writer
+                .add_document(upgraded)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            copied += 1;
+            if copied.is_multiple_of(PROGRESS_LOG_INTERVAL) {
+                info!("Envelope index upgrade progress: {}/{}", copied, total);
+            }
+        }
+    }
+
+    writer
+        .commit()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    writer
+        .wait_merging_threads()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(copied)
+}
+
+fn remove_dir(dir: &Path) {
+    if let Err(e) = std::fs::remove_dir_all(dir) {
+        warn!(
+            "Failed to remove {:?}, please remove it manually: {:#?}",
+            dir, e
+        );
+    }
+}
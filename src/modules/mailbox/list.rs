@@ -86,6 +86,7 @@ pub async fn convert_names_to_mailboxes(
                 mailbox.unseen = mx.unseen; // Number of unseen messages
                 mailbox.uid_next = mx.uid_next; // Next unique identifier to be assigned
                 mailbox.uid_validity = mx.uid_validity; // Validity of the UIDs
+                mailbox.highest_modseq = mx.highest_modseq; // Only sent by servers with CONDSTORE
                 Ok(mailbox)
             });
         tasks.push(task);
@@ -2,9 +2,10 @@ use crate::{
     encode_mailbox_name,
     modules::{
         account::migration::{AccountModel, AccountType},
+        blob::manager::EML_BLOB_MANAGER,
         context::executors::MAIL_CONTEXT,
         error::{code::ErrorCode, BichonResult},
-        indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
     },
     raise_error,
 };
@@ -57,7 +58,7 @@ pub async fn restore_emails(account_id: u64, message_ids: Vec<u64>) -> BichonRes
                     )
                 })?;
 
-            let eml = EML_INDEX_MANAGER
+            let eml = EML_BLOB_MANAGER
                 .get(account_id, message_id)
                 .await?
                 .ok_or_else(|| {
@@ -70,7 +71,16 @@ pub async fn restore_emails(account_id: u64, message_ids: Vec<u64>) -> BichonRes
                     )
                 })?;
 
-            if let Some(mailbox_name) = envelope.mailbox_name {
+            // Put the message back into every mailbox it was archived from.
+            let mut mailbox_names: Vec<String> = envelope
+                .locations
+                .iter()
+                .filter_map(|location| location.mailbox_name.clone())
+                .collect();
+            if mailbox_names.is_empty() {
+                mailbox_names.extend(envelope.mailbox_name);
+            }
+            for mailbox_name in mailbox_names {
                 executor
                     .append(encode_mailbox_name!(&mailbox_name), None, None, &eml)
                     .await?;
@@ -19,8 +19,8 @@
 
 use crate::base64_encode;
 use crate::modules::account::migration::AccountModel;
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
 use crate::modules::error::code::ErrorCode;
-use crate::modules::indexer::manager::EML_INDEX_MANAGER;
 use crate::{modules::error::BichonResult, raise_error};
 use mail_parser::{MessageParser, MimeHeaders};
 
@@ -69,7 +69,7 @@ pub async fn retrieve_email_content(
     id: u64,
 ) -> BichonResult<FullMessageContent> {
     AccountModel::check_account_exists(account_id).await?;
-    let eml = EML_INDEX_MANAGER
+    let eml = EML_BLOB_MANAGER
         .get(account_id, id)
         .await?
         .ok_or_else(|| {
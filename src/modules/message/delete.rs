@@ -18,13 +18,18 @@
 
 
 use crate::modules::error::BichonResult;
-use crate::modules::indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER};
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
+use crate::modules::envelope::fingerprint::MessageFingerprint;
+use crate::modules::envelope::location::MessageLocation;
+use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
 use std::collections::HashMap;
 
 pub async fn delete_messages_impl(request: HashMap<u64, Vec<u64>>) -> BichonResult<()> {
-    EML_INDEX_MANAGER
+    EML_BLOB_MANAGER
         .delete_email_multi_account(&request)
         .await?;
+    MessageLocation::remove_envelopes(&request).await?;
+    MessageFingerprint::remove_envelopes(&request).await?;
     ENVELOPE_INDEX_MANAGER
         .delete_envelopes_multi_account(&request)
         .await
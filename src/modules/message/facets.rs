@@ -0,0 +1,298 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modules::{
+        account::migration::AccountModel,
+        cache::imap::mailbox::MailBox,
+        error::{code::ErrorCode, BichonResult},
+        indexer::{
+            facets::{FacetCounts, SIZE_RANGES},
+            manager::ENVELOPE_INDEX_MANAGER,
+        },
+        message::search::SearchFilter,
+    },
+    raise_error,
+};
+
+const DEFAULT_FACET_LIMIT: u32 = 20;
+const MAX_FACET_LIMIT: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Enum)]
+pub enum FacetKind {
+    /// The `From` address.
+    Sender,
+    /// The domain of the `From` address.
+    SenderDomain,
+    /// The addresses in `To`, `Cc` and `Bcc`.
+    Recipient,
+    Mailbox,
+    Account,
+    Tag,
+    /// The month of the `Date` header, in UTC.
+    Month,
+    HasAttachment,
+    /// Fixed size ranges, from under 10 KB to over 10 MB.
+    Size,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct FacetRequest {
+    /// Selects the messages to count, as in `search-messages`.
+    filter: SearchFilter,
+    facets: Vec<FacetKind>,
+    /// Maximum number of buckets returned for the sender, sender domain, recipient,
+    /// mailbox, account and tag facets, the most frequent first. Defaults to 20, at most 1000.
+    /// The month, has-attachment and size facets always return every bucket.
+    limit: Option<u32>,
+}
+
+impl FacetRequest {
+    pub fn validate(&self) -> BichonResult<()> {
+        if self.facets.is_empty() {
+            return Err(raise_error!(
+                "At least one facet must be requested.".into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        if let Some(limit) = self.limit {
+            if limit == 0 || limit > MAX_FACET_LIMIT {
+                return Err(raise_error!(
+                    format!("The limit must be between 1 and {}.", MAX_FACET_LIMIT),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct FacetBucket {
+    /// The value counted: an address, domain, mailbox or account id, tag, `YYYY-MM` month,
+    /// `true`/`false`, or an inclusive `min-max` byte range (`min-` for the open-ended one).
+    pub key: String,
+    /// Mailbox name, account email or readable size range, where there is one.
+    pub label: Option<String>,
+    /// Number of matching messages with this value.
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct FacetResult {
+    pub facet: FacetKind,
+    pub buckets: Vec<FacetBucket>,
+    /// Number of distinct values, which can be more than the buckets returned.
+    pub distinct: u64,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct FacetsResponse {
+    /// Number of messages matching the filter.
+    pub total: u64,
+    pub facets: Vec<FacetResult>,
+}
+
+pub async fn search_facets_impl(
+    accounts: Option<HashSet<u64>>,
+    request: FacetRequest,
+) -> BichonResult<FacetsResponse> {
+    request.validate()?;
+    let limit = request.limit.unwrap_or(DEFAULT_FACET_LIMIT) as usize;
+    let mut kinds = Vec::new();
+    for kind in request.facets {
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    let (total, mut counts) = ENVELOPE_INDEX_MANAGER
+        .facets(accounts, request.filter, &kinds)
+        .await?;
+
+    let mut facets = Vec::with_capacity(kinds.len());
+    for kind in kinds {
+        let (buckets, distinct) = match kind {
+            FacetKind::Sender => top_buckets(counts.senders.clone(), limit),
+            FacetKind::SenderDomain => top_buckets(sender_domains(&counts), limit),
+            FacetKind::Recipient => top_buckets(std::mem::take(&mut counts.recipients), limit),
+            FacetKind::Mailbox => mailbox_buckets(&counts, limit).await?,
+            FacetKind::Account => account_buckets(&counts, limit).await?,
+            FacetKind::Tag => top_buckets(std::mem::take(&mut counts.tags), limit),
+            FacetKind::Month => month_buckets(&counts),
+            FacetKind::HasAttachment => {
+                let buckets: Vec<FacetBucket> = [true, false]
+                    .into_iter()
+                    .map(|has| FacetBucket {
+                        key: has.to_string(),
+                        label: None,
+                        count: counts.attachments[has as usize],
+                    })
+                    .filter(|bucket| bucket.count > 0)
+                    .collect();
+                let distinct = buckets.len() as u64;
+                (buckets, distinct)
+            }
+            FacetKind::Size => size_buckets(&counts),
+        };
+        facets.push(FacetResult {
+            facet: kind,
+            buckets,
+            distinct,
+        });
+    }
+    Ok(FacetsResponse { total, facets })
+}
+
+/// The `limit` most frequent values, ties in key order.
+fn top_buckets(counts: HashMap<String, u64>, limit: usize) -> (Vec<FacetBucket>, u64) {
+    let distinct = counts.len() as u64;
+    let mut counts: Vec<(String, u64)> = counts.into_iter().collect();
+    counts.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+    counts.truncate(limit);
+    let buckets = counts
+        .into_iter()
+        .map(|(key, count)| FacetBucket {
+            key,
+            label: None,
+            count,
+        })
+        .collect();
+    (buckets, distinct)
+}
+
+fn sender_domains(counts: &FacetCounts) -> HashMap<String, u64> {
+    let mut domains = HashMap::new();
+    for (sender, count) in &counts.senders {
+        let domain = sender
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_else(|| sender.clone());
+        *domains.entry(domain).or_default() += count;
+    }
+    domains
+}
+
+async fn mailbox_buckets(
+    counts: &FacetCounts,
+    limit: usize,
+) -> BichonResult<(Vec<FacetBucket>, u64)> {
+    let (mut buckets, distinct) = top_buckets(
+        counts
+            .mailboxes
+            .iter()
+            .map(|(id, (_, count))| (id.to_string(), *count))
+            .collect(),
+        limit,
+    );
+    let account_ids: HashSet<u64> = counts
+        .mailboxes
+        .values()
+        .map(|(account_id, _)| *account_id)
+        .collect();
+    let mut names = HashMap::new();
+    for account_id in account_ids {
+        for mailbox in MailBox::list_all(account_id).await? {
+            names.insert(mailbox.id.to_string(), mailbox.name);
+        }
+    }
+    for bucket in &mut buckets {
+        bucket.label = names.remove(&bucket.key);
+    }
+    Ok((buckets, distinct))
+}
+
+async fn account_buckets(
+    counts: &FacetCounts,
+    limit: usize,
+) -> BichonResult<(Vec<FacetBucket>, u64)> {
+    let (mut buckets, distinct) = top_buckets(
+        counts
+            .accounts
+            .iter()
+            .map(|(id, count)| (id.to_string(), *count))
+            .collect(),
+        limit,
+    );
+    let mut emails: HashMap<String, String> = AccountModel::list_all()
+        .await?
+        .into_iter()
+        .map(|account| (account.id.to_string(), account.email))
+        .collect();
+    for bucket in &mut buckets {
+        bucket.label = emails.remove(&bucket.key);
+    }
+    Ok((buckets, distinct))
+}
+
+fn month_buckets(counts: &FacetCounts) -> (Vec<FacetBucket>, u64) {
+    let mut months: Vec<(i32, u64)> = counts
+        .months
+        .iter()
+        .map(|(month, count)| (*month, *count))
+        .collect();
+    months.sort();
+    let buckets: Vec<FacetBucket> = months
+        .into_iter()
+        .map(|(month, count)| FacetBucket {
+            key: format!(
+                "{:04}-{:02}",
+                month.div_euclid(12),
+                month.rem_euclid(12) + 1
+            ),
+            label: None,
+            count,
+        })
+        .collect();
+    let distinct = buckets.len() as u64;
+    (buckets, distinct)
+}
+
+fn size_buckets(counts: &FacetCounts) -> (Vec<FacetBucket>, u64) {
+    let buckets: Vec<FacetBucket> = SIZE_RANGES
+        .iter()
+        .zip(counts.sizes)
+        .filter(|(_, count)| *count > 0)
+        .map(|((min, max), count)| match max {
+            Some(max) => FacetBucket {
+                key: format!("{}-{}", min, max),
+                label: Some(format!("{} - {}", format_size(*min), format_size(max + 1))),
+                count,
+            },
+            None => FacetBucket {
+                key: format!("{}-", min),
+                label: Some(format!("{}+", format_size(*min))),
+                count,
+            },
+        })
+        .collect();
+    let distinct = buckets.len() as u64;
+    (buckets, distinct)
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0 => "0 B".into(),
+        b if b >= 1024 * 1024 => format!("{} MB", b / (1024 * 1024)),
+        b => format!("{} KB", b / 1024),
+    }
+}
@@ -22,6 +22,7 @@ use crate::{
         account::migration::AccountModel,
         error::{code::ErrorCode, BichonResult},
         indexer::{envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
+        message::search::{PageCursor, SortOptions},
         rest::response::DataPage,
     },
     raise_error,
@@ -32,11 +33,18 @@ pub async fn list_messages_impl(
     mailbox_id: u64,
     page: u64,
     page_size: u64,
+    sort: SortOptions,
+    cursor: Option<String>,
 ) -> BichonResult<DataPage<Envelope>> {
     AccountModel::check_account_exists(account_id).await?;
     validate_pagination_params(page, page_size)?;
+    sort.validate()?;
+    let after = cursor
+        .as_deref()
+        .map(|cursor| PageCursor::decode(cursor, &sort))
+        .transpose()?;
     ENVELOPE_INDEX_MANAGER
-        .list_mailbox_envelopes(account_id, mailbox_id, page, page_size, true)
+        .list_mailbox_envelopes(account_id, mailbox_id, page, page_size, sort, after)
         .await
 }
 
@@ -61,9 +69,11 @@ pub async fn get_thread_messages(
     thread_id: u64,
     page: u64,
     page_size: u64,
+    sort: SortOptions,
 ) -> BichonResult<DataPage<Envelope>> {
     AccountModel::check_account_exists(account_id).await?;
+    sort.validate()?;
     ENVELOPE_INDEX_MANAGER
-        .list_thread_envelopes(account_id, thread_id, page, page_size, true)
+        .list_thread_envelopes(account_id, thread_id, page, page_size, sort)
         .await
 }
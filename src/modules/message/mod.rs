@@ -19,6 +19,8 @@
 pub mod append;
 pub mod content;
 pub mod delete;
+pub mod facets;
 pub mod list;
 pub mod search;
 pub mod tags;
+pub mod thread;
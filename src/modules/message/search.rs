@@ -18,7 +18,8 @@
 
 use std::collections::HashSet;
 
-use poem_openapi::Object;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -46,30 +47,208 @@ pub struct SearchFilter {
     pub message_id: Option<String>,
     pub has_attachment: Option<bool>,
     pub attachment_name: Option<String>,
+    /// Full-text query matched against attachment contents only.
+    pub attachment_text: Option<String>,
     pub tags: Option<Vec<String>>,
+    /// IMAP flags or keywords the messages must all have, e.g. `\Flagged` or
+    /// `$Label1`, ignoring case.
+    pub flags: Option<Vec<String>>,
+    /// `false` for unread messages, `true` for read ones.
+    pub seen: Option<bool>,
+    /// Whether the messages are flagged (starred).
+    pub flagged: Option<bool>,
+    /// Whether the messages were deleted on the server, see `deleted_on_server_at`
+    /// of the envelopes.
+    pub deleted_on_server: Option<bool>,
+    /// Only messages deleted on the server at or after this time (ms).
+    pub deleted_on_server_since: Option<i64>,
+    /// Only messages deleted on the server at or before this time (ms).
+    pub deleted_on_server_before: Option<i64>,
+    /// How `from`, `to`, `cc` and `bcc` are matched, `Exact` when omitted.
+    pub address_match: Option<AddressMatch>,
+    /// Headers enabled with `--bichon-index-headers` that must have the given values.
+    pub headers: Option<Vec<HeaderFilter>>,
+    /// Nested filters that must all match.
+    pub all_of: Option<Vec<SearchFilter>>,
+    /// Nested filters of which at least one must match.
+    pub any_of: Option<Vec<SearchFilter>>,
+    /// Nested filters that must not match.
+    pub none_of: Option<Vec<SearchFilter>>,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct HeaderFilter {
+    /// Header name, e.g. `List-Id`, in any case.
+    pub name: String,
+    /// Matches the whole value ignoring case, or words of the value for headers
+    /// indexed for text search.
+    pub value: String,
+}
+
+/// How the address filters match senders and recipients. All modes ignore case.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum AddressMatch {
+    /// The whole address, e.g. `alice@vendor.com`, or all mail of a domain and its
+    /// subdomains for a value starting with `@`, e.g. `@vendor.com`. A value without
+    /// `@` matches whole words of the display name or of the local part, e.g. `alice`
+    /// or `Alice Smith`.
+    #[default]
+    Exact,
+    /// Like `Exact`, but every word or address only has to start with the value,
+    /// e.g. `ali` or `alice@ven`.
+    Prefix,
+    /// Anyone at the domain of the value or one of its subdomains, e.g. `vendor.com`,
+    /// `@vendor.com` or `alice@vendor.com`.
+    Domain,
+    /// Like `Exact`, but tolerates typos: one in words of three to five characters,
+    /// two in longer ones.
+    Fuzzy,
 }
 
 #[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
 pub struct SearchRequest {
     filter: SearchFilter,
+    /// Ignored when `cursor` is set.
     page: u64,
     page_size: u64,
+    /// The `next_cursor` of the previous page. Continues right after the last message
+    /// of that page, unaffected by mail indexed in the meantime. Only supported when
+    /// sorting by `Date`.
+    cursor: Option<String>,
+    /// Result order, newest first by date when omitted.
+    sort: Option<SortOptions>,
+    /// Return snippets of the subject and body with the matched terms marked.
+    highlight: Option<bool>,
+    /// Leave the `text` preview out of the results to shrink the response.
+    exclude_text: Option<bool>,
+    /// Return only the best match of each thread, with the number of matches in
+    /// the thread in `thread_matches`. Paging counts threads, and `cursor` is not
+    /// supported.
+    group_by_thread: Option<bool>,
+}
+
+/// Controls what a search returns for each matching envelope.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ResultOptions {
+    pub highlight: bool,
+    pub exclude_text: bool,
+    pub group_by_thread: bool,
 }
 impl SearchRequest {
     pub fn validate(&self) -> BichonResult<()> {
-        if self.page == 0 || self.page_size == 0 {
+        validate_paging(self.page, self.page_size)?;
+        if self.cursor.is_some() && self.group_by_thread.unwrap_or(false) {
             return Err(raise_error!(
-                "Both page and page_size must be greater than 0.".into(),
+                "cursor cannot be combined with group_by_thread.".into(),
                 ErrorCode::InvalidParameter
             ));
         }
-        if self.page_size > 500 {
+        if let Some(sort) = &self.sort {
+            sort.validate()?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_paging(page: u64, page_size: u64) -> BichonResult<()> {
+    if page == 0 || page_size == 0 {
+        return Err(raise_error!(
+            "Both page and page_size must be greater than 0.".into(),
+            ErrorCode::InvalidParameter
+        ));
+    }
+    if page_size > 500 {
+        return Err(raise_error!(
+            "The page_size exceeds the maximum allowed limit of 500.".into(),
+            ErrorCode::InvalidParameter
+        ));
+    }
+    Ok(())
+}
+
+/// The order of search results and message lists.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum SortField {
+    /// Best match first, by BM25 score.
+    Relevance,
+    /// The `Date` header of the message.
+    #[default]
+    Date,
+    /// The time the message arrived in the mailbox.
+    InternalDate,
+    Size,
+    /// The `From` address.
+    Sender,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SortOptions {
+    /// Defaults to `Date`.
+    pub field: Option<SortField>,
+    /// Defaults to descending. Ignored for `Relevance`, which always puts the best match first.
+    pub desc: Option<bool>,
+    /// Boosts recent messages when sorting by `Relevance`. A message received now scores
+    /// twice as high as its text match alone, one this many days old 1.5 times as high,
+    /// and the boost keeps halving with every further period of this length.
+    pub date_decay_days: Option<u32>,
+}
+
+impl SortOptions {
+    pub fn validate(&self) -> BichonResult<()> {
+        if let Some(days) = self.date_decay_days {
+            if days == 0 {
+                return Err(raise_error!(
+                    "date_decay_days must be greater than 0.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            if self.field() != SortField::Relevance {
+                return Err(raise_error!(
+                    "date_decay_days can only be used when sorting by Relevance.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn field(&self) -> SortField {
+        self.field.unwrap_or_default()
+    }
+
+    pub fn desc(&self) -> bool {
+        self.desc.unwrap_or(true)
+    }
+}
+
+/// Position of the last message of a page in `Date` order, the `date` fast field
+/// with the envelope id as tie-breaker.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PageCursor {
+    pub date: i64,
+    pub id: u64,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.date, self.id))
+    }
+
+    pub fn decode(cursor: &str, sort: &SortOptions) -> BichonResult<Self> {
+        if sort.field() != SortField::Date {
             return Err(raise_error!(
-                "The page_size exceeds the maximum allowed limit of 500.".into(),
+                "A cursor can only be used when sorting by Date.".into(),
                 ErrorCode::InvalidParameter
             ));
         }
-        Ok(())
+        let invalid = || raise_error!("Invalid cursor.".into(), ErrorCode::InvalidParameter);
+        let decoded = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (date, id) = decoded.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            date: date.parse().map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
     }
 }
 
@@ -78,13 +257,66 @@ pub async fn search_messages_impl(
     request: SearchRequest,
 ) -> BichonResult<DataPage<Envelope>> {
     request.validate()?;
+    let sort = request.sort.unwrap_or_default();
+    let after = request
+        .cursor
+        .as_deref()
+        .map(|cursor| PageCursor::decode(cursor, &sort))
+        .transpose()?;
+    let options = ResultOptions {
+        highlight: request.highlight.unwrap_or(false),
+        exclude_text: request.exclude_text.unwrap_or(false),
+        group_by_thread: request.group_by_thread.unwrap_or(false),
+    };
     ENVELOPE_INDEX_MANAGER
         .search(
             accounts,
             request.filter,
             request.page,
             request.page_size,
-            true,
+            sort,
+            after,
+            options,
         )
         .await
 }
+
+pub async fn similar_messages_impl(
+    accounts: Option<HashSet<u64>>,
+    account_id: u64,
+    message_id: u64,
+    page: u64,
+    page_size: u64,
+) -> BichonResult<DataPage<Envelope>> {
+    validate_paging(page, page_size)?;
+    ENVELOPE_INDEX_MANAGER
+        .similar(accounts, account_id, message_id, page, page_size)
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    use super::{PageCursor, SortField, SortOptions};
+
+    #[test]
+    fn test_decode_cursor() {
+        let date = SortOptions::default();
+        let cursor = PageCursor {
+            date: -1_000,
+            id: 42,
+        };
+        assert_eq!(PageCursor::decode(&cursor.encode(), &date).unwrap(), cursor);
+
+        let size = SortOptions {
+            field: Some(SortField::Size),
+            ..Default::default()
+        };
+        assert!(PageCursor::decode(&cursor.encode(), &size).is_err());
+        assert!(PageCursor::decode("not base64!", &date).is_err());
+        for invalid in ["42", "a:42", "1000:-1", "1000:42:7"] {
+            assert!(PageCursor::decode(&URL_SAFE_NO_PAD.encode(invalid), &date).is_err());
+        }
+    }
+}
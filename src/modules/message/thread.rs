@@ -0,0 +1,381 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    modules::{
+        account::migration::AccountModel,
+        envelope::thread::ThreadNode,
+        error::{code::ErrorCode, BichonResult},
+        indexer::{envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
+    },
+    raise_error,
+};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+/// Threads with more messages only return the oldest ones.
+pub const MAX_THREAD_MESSAGES: usize = 1000;
+const SNIPPET_LENGTH: usize = 200;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ThreadTree {
+    pub thread_id: u64,
+    pub summary: ThreadSummary,
+    /// The messages in depth-first order: each message comes right before its
+    /// replies, which are sorted by date.
+    pub nodes: Vec<ThreadTreeNode>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ThreadTreeNode {
+    pub envelope: Envelope,
+    /// Envelope id of the message this one replies to, `None` at the top of the
+    /// thread. A reply to a message that is not archived is put under the
+    /// closest archived message above it.
+    pub parent_id: Option<u64>,
+    /// Envelope ids of the direct replies.
+    pub children: Vec<u64>,
+    /// 0 for the messages at the top of the thread.
+    pub depth: u32,
+    /// The start of the body, without quoted text and signature.
+    pub snippet: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ThreadSummary {
+    /// Subject of the first message.
+    pub subject: String,
+    /// Senders and recipients in order of appearance.
+    pub participants: Vec<String>,
+    /// Display names of the participants by address.
+    pub display_names: HashMap<String, String>,
+    pub first_date: i64,
+    pub last_date: i64,
+    pub message_count: u64,
+    pub attachment_count: u64,
+    /// Whether only the first `MAX_THREAD_MESSAGES` messages are in the tree.
+    pub truncated: bool,
+}
+
+pub async fn get_thread_tree(account_id: u64, thread_id: u64) -> BichonResult<ThreadTree> {
+    AccountModel::check_account_exists(account_id).await?;
+    let (total, messages) = ENVELOPE_INDEX_MANAGER
+        .thread_messages(account_id, thread_id, MAX_THREAD_MESSAGES)
+        .await?;
+    if messages.is_empty() {
+        return Err(raise_error!(
+            format!(
+                "Thread not found: account_id={} thread_id={}",
+                account_id, thread_id
+            ),
+            ErrorCode::ResourceNotFound
+        ));
+    }
+    // Looked up by Message-ID, Gmail threads have ids of their own.
+    let message_ids = messages
+        .iter()
+        .map(|(envelope, _)| envelope.message_id.clone())
+        .collect();
+    let parents = parent_links(ThreadNode::list_threads_of(account_id, message_ids).await?);
+
+    let summary = summarize(&messages, total);
+    Ok(ThreadTree {
+        thread_id,
+        summary,
+        nodes: build_tree(messages, &parents),
+    })
+}
+
+/// The parent of every node, keyed by node id, which is the id of the first
+/// envelope of the Message-ID; envelopes sharing it with another message get
+/// the same parent.
+fn parent_links(nodes: Vec<ThreadNode>) -> HashMap<u64, Option<u64>> {
+    nodes
+        .into_iter()
+        .flat_map(|node| {
+            let parent = node.parent;
+            std::iter::once(node.id)
+                .chain(node.envelope_ids)
+                .map(move |id| (id, parent))
+        })
+        .collect()
+}
+
+fn summarize(messages: &[(Envelope, String)], total: u64) -> ThreadSummary {
+    let mut participants = Vec::new();
+    let mut seen = HashSet::new();
+    let mut display_names = HashMap::new();
+    for (envelope, _) in messages {
+        let addresses = std::iter::once(&envelope.from)
+            .chain(&envelope.to)
+            .chain(&envelope.cc)
+            .chain(&envelope.bcc);
+        for address in addresses {
+            if seen.insert(address.to_lowercase()) {
+                participants.push(address.clone());
+            }
+        }
+        for (address, name) in &envelope.display_names {
+            display_names
+                .entry(address.clone())
+                .or_insert_with(|| name.clone());
+        }
+    }
+    let dates = messages.iter().map(|(envelope, _)| envelope.date);
+    ThreadSummary {
+        subject: messages
+            .first()
+            .map(|(envelope, _)| envelope.subject.clone())
+            .unwrap_or_default(),
+        participants,
+        display_names,
+        first_date: dates.clone().min().unwrap_or_default(),
+        last_date: dates.max().unwrap_or_default(),
+        message_count: total,
+        attachment_count: messages
+            .iter()
+            .map(|(envelope, _)| envelope.attachments.len() as u64)
+            .sum(),
+        truncated: total > messages.len() as u64,
+    }
+}
+
+/// Arranges the messages, oldest first, by the parent links of the thread.
+fn build_tree(
+    messages: Vec<(Envelope, String)>,
+    parents: &HashMap<u64, Option<u64>>,
+) -> Vec<ThreadTreeNode> {
+    let present: HashSet<u64> = messages.iter().map(|(envelope, _)| envelope.id).collect();
+    let mut nodes: HashMap<u64, ThreadTreeNode> = HashMap::with_capacity(messages.len());
+    let mut order = Vec::with_capacity(messages.len());
+    for (envelope, text) in messages {
+        // Skip the placeholders of messages that are not archived; the limit
+        // guards against loops.
+        let mut parent = parents.get(&envelope.id).copied().flatten();
+        for _ in 0..parents.len() {
+            match parent {
+                Some(id) if !present.contains(&id) => {
+                    parent = parents.get(&id).copied().flatten();
+                }
+                _ => break,
+            }
+        }
+        let parent_id = parent.filter(|id| present.contains(id) && *id != envelope.id);
+        order.push(envelope.id);
+        nodes.insert(
+            envelope.id,
+            ThreadTreeNode {
+                snippet: snippet(&text),
+                envelope,
+                parent_id,
+                children: Vec::new(),
+                depth: 0,
+            },
+        );
+    }
+    for id in &order {
+        if let Some(parent_id) = nodes[id].parent_id {
+            if let Some(parent) = nodes.get_mut(&parent_id) {
+                parent.children.push(*id);
+            }
+        }
+    }
+
+    let mut tree = Vec::with_capacity(order.len());
+    let mut stack: Vec<(u64, u32)> = order
+        .iter()
+        .rev()
+        .filter(|id| nodes[*id].parent_id.is_none())
+        .map(|id| (*id, 0))
+        .collect();
+    while let Some((id, depth)) = stack.pop() {
+        let Some(mut node) = nodes.remove(&id) else {
+            continue;
+        };
+        node.depth = depth;
+        stack.extend(node.children.iter().rev().map(|child| (*child, depth + 1)));
+        tree.push(node);
+    }
+    // Messages caught in a loop of parent links are appended at the top level.
+    for id in order {
+        if let Some(mut node) = nodes.remove(&id) {
+            node.parent_id = None;
+            tree.push(node);
+        }
+    }
+    tree
+}
+
+fn snippet(text: &str) -> String {
+    let text = strip_quotes(text);
+    if text.chars().count() > SNIPPET_LENGTH {
+        text.chars().take(SNIPPET_LENGTH).collect::<String>() + "..."
+    } else {
+        text
+    }
+}
+
+/// What a message adds to the conversation, on one line: quoted lines are left
+/// out, and everything from a reply or forward header or a signature on is
+/// cut off.
+pub fn strip_quotes(text: &str) -> String {
+    let mut kept: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        let lower = line.to_lowercase();
+        if line == "--" || is_quote_header(&lower) {
+            break;
+        }
+        if lower.ends_with("wrote:") || lower.ends_with("schrieb:") {
+            // "On <date>, <name>" may have been wrapped before "wrote:".
+            if kept
+                .last()
+                .is_some_and(|last| last.to_lowercase().starts_with("on "))
+            {
+                kept.pop();
+            }
+            break;
+        }
+        if line.starts_with('>') || line.is_empty() {
+            continue;
+        }
+        kept.push(line);
+    }
+    kept.join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_quote_header(lower: &str) -> bool {
+    lower.starts_with("-----original message")
+        || lower.starts_with("-----ursprüngliche nachricht")
+        || lower.starts_with("---------- forwarded message")
+        || lower.starts_with("begin forwarded message")
+        || (lower.len() >= 20 && lower.chars().all(|c| c == '_'))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{build_tree, parent_links, strip_quotes};
+    use crate::modules::envelope::thread::{link_thread, ThreadNode};
+    use crate::modules::indexer::envelope::Envelope;
+    use crate::modules::settings::cli::use_test_root_dir;
+    use crate::modules::utils::create_hash;
+
+    #[test]
+    fn test_strip_quotes() {
+        let text = "Sounds good.\n\nSee you then\n-- \nJane\n";
+        assert_eq!(strip_quotes(text), "Sounds good. See you then");
+        let text = "Yes.\n\nOn Mon, 1 Jan 2024 at 10:00, John <j@x.com>\nwrote:\n> Lunch?\n";
+        assert_eq!(strip_quotes(text), "Yes.");
+        let text = "Inline\n> quoted\nanswer\n";
+        assert_eq!(strip_quotes(text), "Inline answer");
+        let text = "FYI\n\n-----Original Message-----\nFrom: a@x.com\n";
+        assert_eq!(strip_quotes(text), "FYI");
+    }
+
+    #[test]
+    fn test_build_tree() {
+        let message = |id: u64, date: i64| {
+            let envelope = Envelope {
+                id,
+                date,
+                ..Default::default()
+            };
+            (envelope, String::new())
+        };
+        // 2 replies to 1, 4 replies to the missing 3, which replies to 1.
+        let parents = HashMap::from([
+            (1, None),
+            (2, Some(1)),
+            (3, Some(1)),
+            (4, Some(3)),
+            (5, Some(2)),
+            (6, None),
+        ]);
+        let messages = vec![
+            message(1, 10),
+            message(2, 20),
+            message(6, 25),
+            message(4, 30),
+            message(5, 40),
+        ];
+        let tree: Vec<_> = build_tree(messages, &parents)
+            .into_iter()
+            .map(|node| (node.envelope.id, node.parent_id, node.depth))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                (1, None, 0),
+                (2, Some(1), 1),
+                (5, Some(2), 2),
+                (4, Some(1), 1),
+                (6, None, 0)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gmail_thread_tree() {
+        use_test_root_dir();
+        let account_id = 24_001;
+        let message = |message_id: &str, date: i64, references: &[&str]| Envelope {
+            id: create_hash(account_id, message_id),
+            account_id,
+            message_id: message_id.into(),
+            date,
+            references: references.iter().map(|r| r.to_string()).collect(),
+            // The conversation Gmail reported, not a thread of the tree.
+            thread_id: 1_234_567,
+            ..Default::default()
+        };
+        let first = message("a@x", 10, &[]);
+        let reply = message("b@x", 20, &["a@x"]);
+        let answer = message("c@x", 30, &["a@x", "b@x"]);
+        // The newest message arrives first.
+        for envelope in [&answer, &first, &reply] {
+            link_thread(envelope).await.unwrap();
+        }
+
+        let messages: Vec<(Envelope, String)> = [first, reply, answer]
+            .into_iter()
+            .map(|envelope| (envelope, String::new()))
+            .collect();
+        let message_ids = messages
+            .iter()
+            .map(|(envelope, _)| envelope.message_id.clone())
+            .collect();
+        let nodes = ThreadNode::list_threads_of(account_id, message_ids)
+            .await
+            .unwrap();
+        let tree: Vec<_> = build_tree(messages, &parent_links(nodes))
+            .into_iter()
+            .map(|node| (node.envelope.message_id, node.depth))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![("a@x".into(), 0), ("b@x".into(), 1), ("c@x".into(), 2)]
+        );
+    }
+}
@@ -18,6 +18,7 @@
 
 pub mod account;
 pub mod autoconfig;
+pub mod blob;
 pub mod cache;
 pub mod cli;
 pub mod common;
@@ -149,6 +149,7 @@ impl AccountApi {
                     total_items: 0,
                     items: vec![],
                     total_pages: Some(0),
+                    next_cursor: None,
                 }));
             }
 
@@ -181,6 +182,7 @@ impl AccountApi {
             total_items: page_data.total_items,
             total_pages: page_data.total_pages,
             items,
+            next_cursor: None,
         }))
     }
 
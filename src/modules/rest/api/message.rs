@@ -17,18 +17,22 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::modules::account::migration::AccountModel;
+use crate::modules::blob::manager::EML_BLOB_MANAGER;
 use crate::modules::common::auth::ClientContext;
 use crate::modules::indexer::envelope::Envelope;
-use crate::modules::indexer::manager::EML_INDEX_MANAGER;
 use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
 use crate::modules::message::append::restore_emails;
 use crate::modules::message::append::RestoreMessagesRequest;
 use crate::modules::message::content::{retrieve_email_content, FullMessageContent};
 use crate::modules::message::delete::delete_messages_impl;
+use crate::modules::message::facets::{search_facets_impl, FacetRequest, FacetsResponse};
 use crate::modules::message::list::{get_thread_messages, list_messages_impl};
-use crate::modules::message::search::{search_messages_impl, SearchRequest};
+use crate::modules::message::search::{
+    search_messages_impl, similar_messages_impl, SearchRequest, SortField, SortOptions,
+};
 use crate::modules::message::tags::TagCount;
 use crate::modules::message::tags::UpdateTagsRequest;
+use crate::modules::message::thread::{get_thread_tree, ThreadTree};
 use crate::modules::rest::api::ApiTags;
 use crate::modules::rest::response::DataPage;
 use crate::modules::rest::ApiResult;
@@ -74,6 +78,7 @@ impl MessageApi {
         method = "get",
         operation_id = "list_messages"
     )]
+    #[allow(clippy::too_many_arguments)]
     async fn list_messages(
         &self,
         /// The ID of the account.
@@ -82,6 +87,15 @@ impl MessageApi {
         mailbox_id: Query<u64>,
         page: Query<u64>,
         page_size: Query<u64>,
+        /// The order of the messages, by date when omitted.
+        sort: Query<Option<SortField>>,
+        /// Sort descending (the default) or ascending.
+        desc: Query<Option<bool>>,
+        /// Half-life in days of the recency boost when sorting by relevance.
+        date_decay_days: Query<Option<u32>>,
+        /// The `next_cursor` of the previous page, `page` is ignored when set.
+        /// Only supported when sorting by date.
+        cursor: Query<Option<String>>,
         context: ClientContext,
     ) -> ApiResult<Json<DataPage<Envelope>>> {
         let account_id = account_id.0;
@@ -89,8 +103,13 @@ impl MessageApi {
         context
             .require_permission(Some(account_id), Permission::DATA_READ)
             .await?;
+        let sort = SortOptions {
+            field: sort.0,
+            desc: desc.0,
+            date_decay_days: date_decay_days.0,
+        };
         Ok(Json(
-            list_messages_impl(account_id, mailbox_id, page.0, page_size.0).await?,
+            list_messages_impl(account_id, mailbox_id, page.0, page_size.0, sort, cursor.0).await?,
         ))
     }
 
@@ -117,12 +136,80 @@ impl MessageApi {
         Ok(Json(search_messages_impl(authorized_ids, payload.0).await?))
     }
 
+    /// Finds the archived messages most similar to a message by the words of their
+    /// subject, body and attachment names, across all mailboxes and accounts the
+    /// caller can read. Best match first.
+    #[oai(
+        path = "/similar-messages/:account_id/:message_id",
+        method = "get",
+        operation_id = "similar_messages"
+    )]
+    async fn similar_messages(
+        &self,
+        /// The ID of the account.
+        account_id: Path<u64>,
+        /// The ID of the message to find similar messages for.
+        message_id: Path<u64>,
+        /// The page number for pagination (1-based).
+        page: Query<u64>,
+        /// The number of messages per page.
+        page_size: Query<u64>,
+        context: ClientContext,
+    ) -> ApiResult<Json<DataPage<Envelope>>> {
+        let account_id = account_id.0;
+        context
+            .require_permission(Some(account_id), Permission::DATA_READ)
+            .await?;
+        let authorized_ids: Option<HashSet<u64>> = if context
+            .has_permission(None, Permission::DATA_READ_ALL)
+            .await
+        {
+            None
+        } else {
+            Some(context.user.account_access_map.keys().cloned().collect())
+        };
+        Ok(Json(
+            similar_messages_impl(
+                authorized_ids,
+                account_id,
+                message_id.0,
+                page.0,
+                page_size.0,
+            )
+            .await?,
+        ))
+    }
+
+    /// Counts the messages matching a search filter per sender, recipient, mailbox,
+    /// month and other facets, to narrow a search down step by step.
+    #[oai(
+        path = "/search-facets",
+        method = "post",
+        operation_id = "search_facets"
+    )]
+    async fn search_facets(
+        &self,
+        payload: Json<FacetRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<FacetsResponse>> {
+        let authorized_ids: Option<HashSet<u64>> = if context
+            .has_permission(None, Permission::DATA_READ_ALL)
+            .await
+        {
+            None
+        } else {
+            Some(context.user.account_access_map.keys().cloned().collect())
+        };
+        Ok(Json(search_facets_impl(authorized_ids, payload.0).await?))
+    }
+
     /// Retrieves all messages belonging to a specific thread. Requires `thread_id`, `page`, and `page_size` query parameters.
     #[oai(
         path = "/get-thread-messages/:account_id",
         method = "get",
         operation_id = "get_thread_messages"
     )]
+    #[allow(clippy::too_many_arguments)]
     async fn get_thread_messages(
         &self,
         /// The ID of the account owning the mailbox.
@@ -133,6 +220,12 @@ impl MessageApi {
         page: Query<u64>,
         /// The number of messages per page.
         page_size: Query<u64>,
+        /// The order of the messages, by date when omitted.
+        sort: Query<Option<SortField>>,
+        /// Sort descending (the default) or ascending.
+        desc: Query<Option<bool>>,
+        /// Half-life in days of the recency boost when sorting by relevance.
+        date_decay_days: Query<Option<u32>>,
         context: ClientContext,
     ) -> ApiResult<Json<DataPage<Envelope>>> {
         let account_id = account_id.0;
@@ -140,11 +233,38 @@ impl MessageApi {
         context
             .require_permission(Some(account_id), Permission::DATA_READ)
             .await?;
+        let sort = SortOptions {
+            field: sort.0,
+            desc: desc.0,
+            date_decay_days: date_decay_days.0,
+        };
         Ok(Json(
-            get_thread_messages(account_id, thread_id, page.0, page_size.0).await?,
+            get_thread_messages(account_id, thread_id, page.0, page_size.0, sort).await?,
         ))
     }
 
+    /// Retrieves the reply tree of a thread, with a snippet of every message and a
+    /// summary of the thread. Requires the `thread_id` query parameter.
+    #[oai(
+        path = "/get-thread-tree/:account_id",
+        method = "get",
+        operation_id = "get_thread_tree"
+    )]
+    async fn get_thread_tree(
+        &self,
+        /// The ID of the account owning the thread.
+        account_id: Path<u64>,
+        /// The ID of the thread.
+        thread_id: Query<u64>,
+        context: ClientContext,
+    ) -> ApiResult<Json<ThreadTree>> {
+        let account_id = account_id.0;
+        context
+            .require_permission(Some(account_id), Permission::DATA_READ)
+            .await?;
+        Ok(Json(get_thread_tree(account_id, thread_id.0).await?))
+    }
+
     /// Fetches the content of a specific email.
     #[oai(
         path = "/message-content/:account_id/:message_id",
@@ -221,7 +341,7 @@ impl MessageApi {
             .require_permission(Some(account_id), Permission::DATA_RAW_DOWNLOAD)
             .await?;
         let message_id = message_id.0;
-        let reader = EML_INDEX_MANAGER.get_reader(account_id, message_id).await?;
+        let reader = EML_BLOB_MANAGER.get_reader(account_id, message_id).await?;
         let body = Body::from_async_read(reader);
         let attachment = Attachment::new(body)
             .attachment_type(AttachmentType::Attachment)
@@ -270,7 +390,7 @@ impl MessageApi {
             .require_permission(Some(account_id), Permission::DATA_READ)
             .await?;
         let name = name.0.trim();
-        let reader = EML_INDEX_MANAGER
+        let reader = EML_BLOB_MANAGER
             .get_attachment(account_id, message_id.0, name)
             .await?;
         let body = Body::from_async_read(reader);
@@ -19,6 +19,8 @@
 use crate::modules::common::auth::ClientContext;
 use crate::modules::dashboard::DashboardStats;
 use crate::modules::error::code::ErrorCode;
+use crate::modules::indexer::dedup::{DedupStatus, DEDUP_JOB};
+use crate::modules::indexer::reindex::{ReindexStatus, REINDEX_JOB};
 use crate::modules::rest::api::ApiTags;
 use crate::modules::rest::ApiResult;
 use crate::modules::settings::cli::SETTINGS;
@@ -152,4 +154,71 @@ impl SystemApi {
         let config: SystemConfigurations = SystemConfigurations::from(&*SETTINGS);
         Ok(Json(config))
     }
+
+    /// Start rebuilding the envelope index from the stored EML files. Requires root permission.
+    ///
+    /// The rebuild runs in the background while search keeps using the current
+    /// index, which is replaced once the rebuild completes. Tags are preserved.
+    /// Fails if a rebuild is already running.
+    #[oai(method = "post", path = "/reindex", operation_id = "start_reindex")]
+    async fn start_reindex(&self, context: ClientContext) -> ApiResult<Json<ReindexStatus>> {
+        context
+            .require_permission(None, Permission::ROOT)
+            .await?;
+        Ok(Json(REINDEX_JOB.start()?))
+    }
+
+    /// Get the progress of the current or last envelope index rebuild. Requires root permission.
+    #[oai(method = "get", path = "/reindex", operation_id = "get_reindex_status")]
+    async fn get_reindex_status(&self, context: ClientContext) -> ApiResult<Json<ReindexStatus>> {
+        context
+            .require_permission(None, Permission::ROOT)
+            .await?;
+        Ok(Json(REINDEX_JOB.status()))
+    }
+
+    /// Cancel the running envelope index rebuild. Requires root permission.
+    ///
+    /// The current index is left untouched.
+    #[oai(method = "delete", path = "/reindex", operation_id = "cancel_reindex")]
+    async fn cancel_reindex(&self, context: ClientContext) -> ApiResult<Json<ReindexStatus>> {
+        context
+            .require_permission(None, Permission::ROOT)
+            .await?;
+        Ok(Json(REINDEX_JOB.cancel()?))
+    }
+
+    /// Start merging the copies of emails archived without a Message-ID. Requires root permission.
+    ///
+    /// Such emails used to get a random Message-ID, so every copy of one was
+    /// archived separately. The job gives them a Message-ID derived from their
+    /// content and merges the copies, keeping the mailboxes and tags of all of
+    /// them. It runs in the background and cannot run alongside a reindex.
+    #[oai(method = "post", path = "/dedup", operation_id = "start_dedup")]
+    async fn start_dedup(&self, context: ClientContext) -> ApiResult<Json<DedupStatus>> {
+        context
+            .require_permission(None, Permission::ROOT)
+            .await?;
+        Ok(Json(DEDUP_JOB.start()?))
+    }
+
+    /// Get the progress of the current or last deduplication. Requires root permission.
+    #[oai(method = "get", path = "/dedup", operation_id = "get_dedup_status")]
+    async fn get_dedup_status(&self, context: ClientContext) -> ApiResult<Json<DedupStatus>> {
+        context
+            .require_permission(None, Permission::ROOT)
+            .await?;
+        Ok(Json(DEDUP_JOB.status()))
+    }
+
+    /// Cancel the running deduplication. Requires root permission.
+    ///
+    /// Emails processed so far stay merged.
+    #[oai(method = "delete", path = "/dedup", operation_id = "cancel_dedup")]
+    async fn cancel_dedup(&self, context: ClientContext) -> ApiResult<Json<DedupStatus>> {
+        context
+            .require_permission(None, Permission::ROOT)
+            .await?;
+        Ok(Json(DEDUP_JOB.cancel()?))
+    }
 }
@@ -37,6 +37,7 @@ use crate::modules::database::Paginated;
 /// - `total_items`: The total number of items matching the query.
 /// - `items`: The list of items returned for the current page.
 /// - `total_pages`: The total number of pages available. `None` if not calculated.
+/// - `next_cursor`: Where the next page starts, for endpoints that support cursor paging.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Object)]
 pub struct DataPage<S>
 where
@@ -59,6 +60,9 @@ where
     pub items: Vec<S>,
     /// The total number of pages. This is optional and may not be set if not calculated.
     pub total_pages: Option<u64>,
+    /// Opaque cursor to pass back to fetch the page after this one. Only set by
+    /// endpoints that support cursor paging, and only if more items may follow.
+    pub next_cursor: Option<String>,
 }
 
 impl<
@@ -79,6 +83,7 @@ impl<
             total_items: paginated.total_items,
             total_pages: paginated.total_pages,
             items: paginated.items,
+            next_cursor: None,
         }
     }
 }
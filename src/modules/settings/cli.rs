@@ -16,11 +16,24 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::modules::indexer::analyzer::StemLanguage;
+use crate::modules::indexer::headers::IndexedHeader;
 use clap::{builder::ValueParser, Parser, ValueEnum};
 use std::{collections::HashSet, env, fmt, path::PathBuf, sync::LazyLock};
 
 pub static SETTINGS: LazyLock<Settings> = LazyLock::new(Settings::init);
 
+/// Points bichon at an empty data directory, for tests that use the databases
+/// and stores. Must be called before anything reads `SETTINGS`.
+#[cfg(test)]
+pub fn use_test_root_dir() {
+    static ROOT_DIR: std::sync::Once = std::sync::Once::new();
+    ROOT_DIR.call_once(|| {
+        let dir = tempfile::tempdir().unwrap().keep();
+        env::set_var("BICHON_ROOT_DIR", dir);
+    });
+}
+
 #[derive(Debug, Parser)]
 #[clap(
     name = "bichon",
@@ -218,11 +231,43 @@ pub struct Settings {
         value_parser = clap::value_parser!(u16).range(1..)
     )]
     pub bichon_sync_concurrency: Option<u16>,
+
+    /// Languages whose stemmer is applied to subject and body.
+    ///
+    /// The language of each message is detected when it is indexed. Messages in
+    /// other languages are still searchable by their exact words.
+    #[clap(
+        long,
+        env,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "en,de,fr,es,it,pt,nl,sv,da,no,fi,ru",
+        help = "Comma-separated languages to apply stemming to (da, de, el, en, es, fi, fr, hu, it, nl, no, pt, ro, ru, sv, tr)"
+    )]
+    pub bichon_stem_languages: Vec<StemLanguage>,
+
+    /// Headers extracted into the envelope index, in addition to the fixed ones.
+    ///
+    /// Only mail indexed after a header was added has its values, existing mail
+    /// gets them with a reindex from the stored EML (`POST /api/v1/reindex`).
+    #[clap(
+        long,
+        env,
+        value_delimiter = ',',
+        default_value = "List-Id,Reply-To,Auto-Submitted,X-Mailer:text",
+        help = "Comma-separated headers to index. Values match exactly, ignoring case, or by their words with a ':text' suffix, e.g. 'List-Id,X-Mailer:text'"
+    )]
+    pub bichon_index_headers: Vec<IndexedHeader>,
 }
 
 impl Settings {
     pub fn init() -> Self {
+        #[cfg(not(test))]
         let s = Self::parse();
+        // The arguments of a test binary are for the test harness, tests set
+        // the settings through the environment, see `use_test_root_dir`.
+        #[cfg(test)]
+        let s = Self::parse_from(["bichon"]);
         if s.bichon_encrypt_password.is_none() && s.bichon_encrypt_password_file.is_none() {
             panic!(
                 "One of --bichon_encrypt_password or --bichon_encrypt_password_file has to be set"
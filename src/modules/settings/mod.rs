@@ -51,6 +51,8 @@ pub struct SystemConfigurations {
     pub bichon_enable_rest_https: bool,
     pub bichon_http_compression_enabled: bool,
     pub bichon_sync_concurrency: Option<u16>,
+    pub bichon_stem_languages: Vec<String>,
+    pub bichon_index_headers: Vec<String>,
 }
 
 impl From<&Settings> for SystemConfigurations {
@@ -78,6 +80,16 @@ impl From<&Settings> for SystemConfigurations {
             bichon_enable_rest_https: s.bichon_enable_rest_https,
             bichon_http_compression_enabled: s.bichon_http_compression_enabled,
             bichon_sync_concurrency: s.bichon_sync_concurrency,
+            bichon_stem_languages: s
+                .bichon_stem_languages
+                .iter()
+                .map(|language| language.code().to_string())
+                .collect(),
+            bichon_index_headers: s
+                .bichon_index_headers
+                .iter()
+                .map(|header| header.to_string())
+                .collect(),
         }
     }
 }
@@ -47,6 +47,19 @@ export interface AccountRunningState {
     progress?: ProgressMap;
     initial_sync_start_time?: number;
     initial_sync_end_time?: number;
+    message_id_collisions: MessageIdCollision[];
+    message_id_collision_count: number;
+    vanished_counts: Record<string, number>; // deleted on the server, by mailbox name
+    last_reconciled_at?: number;
+}
+
+export interface MessageIdCollision {
+    message_id: string;
+    envelope_id: number;
+    colliding_envelope_id: number;
+    mailbox_id: number;
+    uid: number;
+    at: number; // milliseconds timestamp
 }
 
 
@@ -99,6 +112,7 @@ export interface AccountModel {
     sync_folders: string[];
     sync_interval_min?: number;
     sync_batch_size?: number;
+    idle_folders?: string[];
     created_by: number;
     created_user_name: string;
     created_user_email: string;
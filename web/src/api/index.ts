@@ -23,6 +23,7 @@ export interface PaginatedResponse<S> {
   total_items: number;
   items: S[];
   total_pages: number | null;
+  next_cursor?: string | null;
 }
 
 
@@ -43,6 +44,26 @@ export interface EmailEnvelope {
   internal_date: number;
   size: number;
   thread_id: number,
+  gmail_message_id?: number | null;
+  deleted_on_server_at?: number | null;
   attachments: string[];
   tags: string[];
+  flags: string[];
+  display_names: Record<string, string>;
+  headers: Record<string, string[]>;
+  highlights?: Highlights | null;
+  thread_matches?: number | null;
+  locations: MailboxLocation[];
+}
+
+export interface MailboxLocation {
+  mailbox_id: number;
+  mailbox_name?: string | null;
+  uid: number;
+  vanished_at?: number | null;
+}
+
+export interface Highlights {
+  subject?: string | null;
+  text?: string | null;
 }
\ No newline at end of file
@@ -28,6 +28,7 @@ export interface MailboxData {
     name: string;
     uid_next: number | null;
     uid_validity: number | null;
+    highest_modseq: number | null;
     unseen: number | null;
 }
 
@@ -47,6 +47,54 @@ export const get_thread_messages = async (accountId: number, thread_id: number,
     return response.data;
 }
 
+export interface ThreadTreeNode {
+    envelope: EmailEnvelope;
+    parent_id?: number | null;
+    children: number[];
+    depth: number;
+    snippet: string;
+}
+
+export interface ThreadSummary {
+    subject: string;
+    participants: string[];
+    display_names: Record<string, string>;
+    first_date: number;
+    last_date: number;
+    message_count: number;
+    attachment_count: number;
+    truncated: boolean;
+}
+
+export interface ThreadTree {
+    thread_id: number;
+    summary: ThreadSummary;
+    nodes: ThreadTreeNode[];
+}
+
+export const get_thread_tree = async (accountId: number, thread_id: number) => {
+    const params = new URLSearchParams({
+        thread_id: String(thread_id),
+    });
+
+    const response = await axiosInstance.get<ThreadTree>(
+        `/api/v1/get-thread-tree/${accountId}?${params.toString()}`
+    );
+    return response.data;
+}
+
+export const similar_messages = async (accountId: number, id: number, page: number, page_size: number) => {
+    const params = new URLSearchParams({
+        page: String(page),
+        page_size: String(page_size),
+    });
+
+    const response = await axiosInstance.get<PaginatedResponse<EmailEnvelope>>(
+        `/api/v1/similar-messages/${accountId}/${id}?${params.toString()}`
+    );
+    return response.data;
+}
+
 export const download_attachment = async (accountId: number, id: number, attachmentFileName: string) => {
     const response = await axiosInstance.get(`/api/v1/download-attachment/${accountId}/${id}?name=${attachmentFileName}`, { responseType: 'blob' });
     const blob = new Blob([response.data]);
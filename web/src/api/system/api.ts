@@ -46,6 +46,7 @@ export interface DashboardStats {
     email_count: number;                   // Total number of emails
     total_size_bytes: number;              // Total size of all emails (in bytes)
     storage_usage_bytes: number;           // Actual storage used (in bytes)
+    dedup_saved_bytes: number;             // Bytes saved by attachment deduplication
     index_usage_bytes: number;             // Index storage size (in bytes)
     recent_activity: TimeBucket[];        // Email activity over recent days
     top_senders: Group[];            // Top 10 senders
@@ -98,6 +99,8 @@ export type ServerConfigurations = {
     bichon_enable_rest_https: boolean
     bichon_http_compression_enabled: boolean
     bichon_sync_concurrency?: number | null
+    bichon_stem_languages: string[]
+    bichon_index_headers: string[]
 }
 
 export const get_dashboard_stats = async () => {
@@ -184,7 +184,7 @@ export function MailList({
 
                             <div className="col-span-1 sm:col-span-8 flex flex-col min-w-0 gap-0.5">
                                 <div className="flex items-center gap-1 min-w-0">
-                                    <p className="text-sm font-medium truncate">{item.from}</p>
+                                    <p className="text-sm font-medium truncate" title={item.from}>{item.display_names?.[item.from] ?? item.from}</p>
                                     <h3 className="text-sm text-muted-foreground truncate hidden sm:block">
                                         {item.subject}
                                     </h3>
@@ -25,7 +25,7 @@ import { Button } from '@/components/ui/button';
 import { Separator } from '@/components/ui/separator';
 import { Tooltip, TooltipContent, TooltipTrigger } from '@/components/ui/tooltip';
 import { toast } from '@/hooks/use-toast';
-import { formatBytes } from '@/lib/utils';
+import { formatAddress, formatBytes } from '@/lib/utils';
 import EmailIframe from '@/components/mail-iframe';
 import {
   AttachmentInfo,
@@ -197,12 +197,12 @@ export function MailMessageView({
         {envelope.from && (
           <div className="flex space-x-2">
             <span className="font-medium text-gray-400">{t('mail.from')}:</span>
-            <span>{envelope.from}</span>
+            <span>{formatAddress(envelope.from, envelope.display_names)}</span>
           </div>
         )}
-        {envelope.to && envelope.to.length > 0 && <Multilines title={t('mail.to')} lines={envelope.to} />}
-        {envelope.cc && envelope.cc.length > 0 && <Multilines title={t('mail.cc')} lines={envelope.cc} />}
-        {envelope.bcc && envelope.bcc.length > 0 && <Multilines title={t('mail.bcc')} lines={envelope.bcc} />}
+        {envelope.to && envelope.to.length > 0 && <Multilines title={t('mail.to')} lines={envelope.to.map((a) => formatAddress(a, envelope.display_names))} />}
+        {envelope.cc && envelope.cc.length > 0 && <Multilines title={t('mail.cc')} lines={envelope.cc.map((a) => formatAddress(a, envelope.display_names))} />}
+        {envelope.bcc && envelope.bcc.length > 0 && <Multilines title={t('mail.bcc')} lines={envelope.bcc.map((a) => formatAddress(a, envelope.display_names))} />}
         {envelope.subject && (
           <div className="flex space-x-2">
             <span className="font-medium text-gray-400">{t('mail.subject')}:</span>
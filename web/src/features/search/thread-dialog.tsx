@@ -34,6 +34,7 @@ import { MailMessageView } from './mail-message-view';
 import { useSearchContext } from './context';
 import { useTranslation } from 'react-i18next';
 import { format } from 'date-fns';
+import { formatAddress } from '@/lib/utils';
 
 interface MailThreadDialogProps {
   open: boolean;
@@ -136,7 +137,7 @@ export function MailThreadDialog({ open, onOpenChange }: MailThreadDialogProps)
                     <div className="flex items-start justify-between gap-4">
                       <div className="flex-1 min-w-0">
                         <div className="flex items-center gap-2 text-sm">
-                          <span className="font-medium truncate">{msg.from}</span>
+                          <span className="font-medium truncate">{formatAddress(msg.from, msg.display_names)}</span>
                           <span className="text-muted-foreground">→</span>
                           <span className="text-muted-foreground truncate">
                             {msg.to.join(', ')}
@@ -224,6 +224,14 @@ export default function ServerConfigurationsPage() {
               label="bichon_sync_concurrency"
               value={data.bichon_sync_concurrency ?? t("systemConfig.status.auto")}
             />
+            <SettingRow
+              label="bichon_stem_languages"
+              value={data.bichon_stem_languages.join(", ")}
+            />
+            <SettingRow
+              label="bichon_index_headers"
+              value={data.bichon_index_headers.join(", ")}
+            />
           </SettingsCard>
         </div>
       </ScrollArea>
@@ -25,6 +25,11 @@ export function cn(...inputs: ClassValue[]) {
 }
 
 
+export const formatAddress = (address: string, displayNames?: Record<string, string>): string => {
+  const name = displayNames?.[address];
+  return name ? `${name} <${address}>` : address;
+};
+
 export const formatBytes = (sizeInBytes: number): string => {
   if (sizeInBytes < 1024) {
     return `${sizeInBytes} B`;